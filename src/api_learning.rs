@@ -0,0 +1,875 @@
+use crate::scanner::{EndpointInfo, EndpointType, Evidence, EvidenceKind, Protocol, ScanResults};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// API surface inferred from observed traffic for one endpoint.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ApiModel {
+    pub operations: Vec<ApiOperation>,
+}
+
+impl ApiModel {
+    /// Stable fingerprint of the routes and their parameters, so scans can
+    /// tell when an API's surface changed. Sample bodies and counts are left out.
+    pub fn spec_hash(&self) -> String {
+        let mut routes: Vec<String> = self
+            .operations
+            .iter()
+            .map(|operation| {
+                let mut query = operation.query_params.clone();
+                query.sort();
+                format!(
+                    "{} {}?{}",
+                    operation.method,
+                    operation.path_template,
+                    query.join(",")
+                )
+            })
+            .collect();
+        routes.sort();
+
+        // FNV-1a: deterministic across runs and platforms, unlike std's hasher
+        let hash = routes
+            .join("\n")
+            .bytes()
+            .fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+                (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+            });
+        format!("{:016x}", hash)
+    }
+}
+
+/// One route, e.g. `GET /users/{id}`, with everything seen on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiOperation {
+    pub method: String,
+    pub path_template: String,
+    pub path_params: Vec<String>,
+    pub query_params: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_body: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_body: Option<Value>,
+    pub status_codes: Vec<u16>,
+    pub sample_count: usize,
+}
+
+/// A single request seen in a HAR file or access log.
+#[derive(Debug, Clone)]
+pub struct TrafficRecord {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    // Resolved server address when the source records it (HAR serverIPAddress, Envoy upstream)
+    pub server_ip: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub query_params: Vec<String>,
+    pub request_body: Option<Value>,
+    pub response_body: Option<Value>,
+    pub status: Option<u16>,
+    pub timestamp: u64,
+    /// Credential scheme the request carried (`none` when it had none);
+    /// unknown for sources without headers
+    pub auth: Option<String>,
+}
+
+/// Read requests from a HAR (HTTP Archive) file exported from browser developer tools.
+pub fn read_har<P: AsRef<Path>>(path: P) -> Result<Vec<TrafficRecord>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path.as_ref())?;
+    let har: Value = serde_json::from_str(&contents)?;
+    let entries = har
+        .pointer("/log/entries")
+        .and_then(Value::as_array)
+        .ok_or("Not a HAR file: missing log.entries")?;
+
+    let mut records = Vec::new();
+    for entry in entries {
+        let request = match entry.get("request") {
+            Some(request) => request,
+            None => continue,
+        };
+        let url = request.get("url").and_then(Value::as_str).unwrap_or("");
+        let (tls, host, port, path, query) = match split_url(url) {
+            Some(parts) => parts,
+            None => continue,
+        };
+
+        let request_body = request
+            .pointer("/postData/text")
+            .and_then(Value::as_str)
+            .and_then(|text| serde_json::from_str::<Value>(text).ok());
+        let response_body = entry
+            .pointer("/response/content/text")
+            .and_then(Value::as_str)
+            .and_then(|text| serde_json::from_str::<Value>(text).ok());
+        let status = entry
+            .pointer("/response/status")
+            .and_then(Value::as_u64)
+            .filter(|status| *status > 0)
+            .map(|status| status as u16);
+        let timestamp = entry
+            .get("startedDateTime")
+            .and_then(Value::as_str)
+            .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+            .map_or_else(
+                || chrono::Utc::now().timestamp() as u64,
+                |date| date.timestamp() as u64,
+            );
+        let headers = request
+            .get("headers")
+            .and_then(Value::as_array)
+            .map(|headers| {
+                headers
+                    .iter()
+                    .filter_map(|header| {
+                        Some((
+                            header.get("name")?.as_str()?,
+                            header.get("value")?.as_str()?,
+                        ))
+                    })
+                    .collect::<Vec<_>>()
+            });
+        let server_ip = entry
+            .get("serverIPAddress")
+            .and_then(Value::as_str)
+            .map(|ip| ip.trim_matches(|c| c == '[' || c == ']').to_string())
+            .filter(|ip| !ip.is_empty());
+
+        records.push(TrafficRecord {
+            host,
+            port,
+            tls,
+            server_ip,
+            method: request
+                .get("method")
+                .and_then(Value::as_str)
+                .unwrap_or("GET")
+                .to_uppercase(),
+            path,
+            query_params: query_param_names(&query),
+            request_body,
+            response_body,
+            status,
+            timestamp,
+            auth: headers.map(|headers| {
+                auth_scheme(headers.into_iter()).unwrap_or_else(|| "none".to_string())
+            }),
+        });
+    }
+
+    Ok(records)
+}
+
+/// Read requests from an nginx "combined" or Envoy default-format access log.
+///
+/// nginx logs do not record which server handled the request, so `default_target`
+/// (`host:port`) is used for lines that carry no authority or upstream address.
+pub fn read_access_log<P: AsRef<Path>>(
+    path: P,
+    default_target: Option<&str>,
+) -> Result<Vec<TrafficRecord>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path.as_ref())?;
+    let default_target = default_target.and_then(split_host_port);
+
+    let mut records = Vec::new();
+    let mut skipped = 0;
+
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        match parse_access_log_line(line, default_target.as_ref()) {
+            Some(record) => records.push(record),
+            None => skipped += 1,
+        }
+    }
+
+    if skipped > 0 {
+        log::warn!(
+            "Skipped {} unrecognised or untargeted lines in {}",
+            skipped,
+            path.as_ref().display()
+        );
+    }
+
+    Ok(records)
+}
+
+fn parse_access_log_line(
+    line: &str,
+    default_target: Option<&(String, u16)>,
+) -> Option<TrafficRecord> {
+    let (quoted, ends): (Vec<String>, Vec<usize>) = quoted_fields(line).into_iter().unzip();
+    let request_line = quoted.first()?;
+    let mut request_parts = request_line.split_whitespace();
+    let method = request_parts.next()?.to_uppercase();
+    let target = request_parts.next()?;
+    if !method.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+
+    // Status code is the first token after the request line
+    let after_request = &line[ends[0]..];
+    let status = after_request
+        .split_whitespace()
+        .next()
+        .and_then(|token| token.parse::<u16>().ok());
+
+    let timestamp = line
+        .find('[')
+        .and_then(|start| Some((start, line[start..].find(']')? + start)))
+        .and_then(|(start, end)| parse_log_time(&line[start + 1..end]))
+        .unwrap_or_else(|| chrono::Utc::now().timestamp() as u64);
+
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (target.to_string(), String::new()),
+    };
+
+    // Envoy default format ends with "authority" "upstream_host"
+    let envoy_upstream = if quoted.len() >= 6 {
+        quoted.last().and_then(|upstream| split_host_port(upstream))
+    } else {
+        None
+    };
+    let envoy_authority = if quoted.len() >= 6 {
+        quoted.get(quoted.len() - 2).map(String::as_str)
+    } else {
+        None
+    };
+
+    let (host, port, server_ip) = match (envoy_authority, envoy_upstream) {
+        (Some(authority), Some((ip, port))) if authority != "-" => {
+            let host = split_host_port(authority).map_or(authority.to_string(), |(h, _)| h);
+            (host, port, Some(ip))
+        }
+        (_, Some((ip, port))) => (ip.clone(), port, Some(ip)),
+        _ => {
+            let (host, port) = default_target?.clone();
+            (host, port, None)
+        }
+    };
+
+    Some(TrafficRecord {
+        host,
+        tls: port == 443 || port == 8443,
+        port,
+        server_ip,
+        method,
+        path,
+        query_params: query_param_names(&query),
+        request_body: None,
+        response_body: None,
+        status,
+        timestamp,
+        auth: None,
+    })
+}
+
+/// Build an API model per endpoint and attach it to matching scan results.
+///
+/// Traffic to targets that were not discovered by other means is added as new API endpoints.
+pub fn attach_models(results: &mut ScanResults, records: &[TrafficRecord]) {
+    let mut by_target: BTreeMap<(String, u16), Vec<&TrafficRecord>> = BTreeMap::new();
+    for record in records {
+        by_target
+            .entry((record.host.to_lowercase(), record.port))
+            .or_default()
+            .push(record);
+    }
+
+    for ((host, port), target_records) in by_target {
+        let model = infer_model(&target_records);
+        let server_ips: BTreeSet<&str> = target_records
+            .iter()
+            .filter_map(|record| record.server_ip.as_deref())
+            .collect();
+
+        let matching: Vec<usize> = results
+            .iter()
+            .enumerate()
+            .filter(|(_, endpoint)| {
+                endpoint.port == port
+                    && (endpoint.address.eq_ignore_ascii_case(&host)
+                        || server_ips.contains(endpoint.address.as_str())
+                        || endpoint_names(endpoint).contains(&host))
+            })
+            .map(|(index, _)| index)
+            .collect();
+
+        if matching.is_empty() {
+            log::info!(
+                "Adding endpoint {}:{} learned from traffic ({} operations)",
+                host,
+                port,
+                model.operations.len()
+            );
+            results.push(learned_endpoint(
+                &host,
+                port,
+                &server_ips,
+                &target_records,
+                model,
+            ));
+            continue;
+        }
+
+        for index in matching {
+            let endpoint = &mut results[index];
+            log::info!(
+                "Attaching {} learned operations to {}:{}",
+                model.operations.len(),
+                endpoint.address,
+                endpoint.port
+            );
+            // Evidence for an earlier, different classification no longer applies
+            if !matches!(endpoint.endpoint_type, EndpointType::API) {
+                endpoint.evidence.clear();
+            }
+            endpoint.endpoint_type = EndpointType::API;
+            endpoint.add_evidence(traffic_evidence(&target_records, &model));
+            if !matches!(endpoint.protocol, Protocol::HTTP | Protocol::HTTPS) {
+                endpoint.protocol = if target_records.iter().any(|r| r.tls) {
+                    Protocol::HTTPS
+                } else {
+                    Protocol::HTTP
+                };
+            }
+            endpoint
+                .metadata
+                .entry("http_hosts".to_string())
+                .or_insert_with(|| host.clone());
+            insert_model_metadata(&mut endpoint.metadata, &target_records, &model);
+            endpoint.api_model = Some(model.clone());
+        }
+    }
+}
+
+/// Hostnames other discovery methods have recorded for an endpoint.
+fn endpoint_names(endpoint: &EndpointInfo) -> BTreeSet<String> {
+    [
+        "hostname",
+        "http_hosts",
+        "dns_names",
+        "tls_sni",
+        "service_name",
+    ]
+    .iter()
+    .filter_map(|key| endpoint.metadata.get(*key))
+    .flat_map(|value| value.split(','))
+    .map(|name| {
+        split_host_port(name)
+            .map_or(name.to_string(), |(host, _)| host)
+            .to_lowercase()
+    })
+    .collect()
+}
+
+fn learned_endpoint(
+    host: &str,
+    port: u16,
+    server_ips: &BTreeSet<&str>,
+    records: &[&TrafficRecord],
+    model: ApiModel,
+) -> EndpointInfo {
+    let address = server_ips
+        .iter()
+        .next()
+        .map_or(host.to_string(), |ip| ip.to_string());
+
+    let mut metadata = HashMap::new();
+    metadata.insert("detected_by".to_string(), "traffic_log".to_string());
+    metadata.insert("http_hosts".to_string(), host.to_string());
+    metadata.insert("request_count".to_string(), records.len().to_string());
+    insert_model_metadata(&mut metadata, records, &model);
+
+    let evidence = traffic_evidence(records, &model);
+    let mut endpoint = EndpointInfo {
+        endpoint_type: EndpointType::API,
+        address,
+        port,
+        protocol: if records.iter().any(|r| r.tls) {
+            Protocol::HTTPS
+        } else {
+            Protocol::HTTP
+        },
+        metadata,
+        discovered_timestamp: records.iter().map(|r| r.timestamp).min().unwrap_or(0),
+        api_model: Some(model),
+        confidence: 0.0,
+        evidence: Vec::new(),
+    };
+    endpoint.add_evidence(evidence);
+    endpoint
+}
+
+/// Record the spec hash and the credential schemes seen in the traffic.
+fn insert_model_metadata(
+    metadata: &mut HashMap<String, String>,
+    records: &[&TrafficRecord],
+    model: &ApiModel,
+) {
+    metadata.insert("spec_hash".to_string(), model.spec_hash());
+    let schemes: BTreeSet<String> = records
+        .iter()
+        .filter_map(|record| record.auth.clone())
+        .collect();
+    if let Some(auth_type) = summarize_auth(&schemes) {
+        metadata.insert("auth_type".to_string(), auth_type);
+    }
+}
+
+/// The credential scheme of a request's headers, e.g. `bearer`, `basic` or
+/// `api_key`.
+pub fn auth_scheme<'a>(headers: impl Iterator<Item = (&'a str, &'a str)>) -> Option<String> {
+    let mut scheme = None;
+    for (name, value) in headers {
+        match name.trim().to_lowercase().as_str() {
+            "authorization" => {
+                return value
+                    .split_whitespace()
+                    .next()
+                    .map(|scheme| scheme.to_lowercase())
+            }
+            "x-api-key" | "api-key" | "apikey" | "x-auth-token" => {
+                scheme = Some("api_key".to_string())
+            }
+            _ => {}
+        }
+    }
+    scheme
+}
+
+/// Combine the schemes seen on an endpoint. Routes without credentials only
+/// count when no request carried any.
+pub fn summarize_auth(schemes: &BTreeSet<String>) -> Option<String> {
+    let used: Vec<&str> = schemes
+        .iter()
+        .map(String::as_str)
+        .filter(|scheme| *scheme != "none")
+        .collect();
+    if !used.is_empty() {
+        Some(used.join(","))
+    } else if schemes.is_empty() {
+        None
+    } else {
+        Some("none".to_string())
+    }
+}
+
+fn traffic_evidence(records: &[&TrafficRecord], model: &ApiModel) -> Evidence {
+    Evidence::new(
+        EvidenceKind::TrafficObserved,
+        "traffic_log",
+        format!(
+            "{} recorded HTTP requests to {} routes",
+            records.len(),
+            model.operations.len()
+        ),
+        0.95,
+    )
+}
+
+/// Group requests by method and path template and merge what was seen on each route.
+pub fn infer_model(records: &[&TrafficRecord]) -> ApiModel {
+    let mut operations: BTreeMap<(String, String), ApiOperation> = BTreeMap::new();
+
+    for record in records {
+        let (path_template, path_params) = template_path(&record.path);
+        let operation = operations
+            .entry((path_template.clone(), record.method.clone()))
+            .or_insert_with(|| ApiOperation {
+                method: record.method.clone(),
+                path_template,
+                path_params,
+                query_params: Vec::new(),
+                request_body: None,
+                response_body: None,
+                status_codes: Vec::new(),
+                sample_count: 0,
+            });
+
+        operation.sample_count += 1;
+        for name in &record.query_params {
+            if !operation.query_params.contains(name) {
+                operation.query_params.push(name.clone());
+            }
+        }
+        if let Some(status) = record.status {
+            if !operation.status_codes.contains(&status) {
+                operation.status_codes.push(status);
+            }
+        }
+        if let Some(ref body) = record.request_body {
+            operation.request_body = Some(merge_shapes(
+                operation.request_body.take(),
+                json_shape(body),
+            ));
+        }
+        if let Some(ref body) = record.response_body {
+            operation.response_body = Some(merge_shapes(
+                operation.response_body.take(),
+                json_shape(body),
+            ));
+        }
+    }
+
+    let operations = operations
+        .into_values()
+        .map(|mut operation| {
+            operation.query_params.sort();
+            operation.status_codes.sort_unstable();
+            operation
+        })
+        .collect();
+
+    ApiModel { operations }
+}
+
+/// Replace identifier-like path segments with parameters,
+/// e.g. `/users/123/orders/9f1c...` becomes `/users/{id}/orders/{id2}`.
+pub fn template_path(path: &str) -> (String, Vec<String>) {
+    let mut params = Vec::new();
+    let segments: Vec<String> = path
+        .split('/')
+        .map(|segment| {
+            if is_identifier(segment) {
+                let name = if params.is_empty() {
+                    "id".to_string()
+                } else {
+                    format!("id{}", params.len() + 1)
+                };
+                params.push(name.clone());
+                format!("{{{}}}", name)
+            } else {
+                segment.to_string()
+            }
+        })
+        .collect();
+
+    let template = segments.join("/");
+    if template.is_empty() {
+        ("/".to_string(), params)
+    } else {
+        (template, params)
+    }
+}
+
+fn is_identifier(segment: &str) -> bool {
+    if segment.is_empty() {
+        return false;
+    }
+    let digits = segment.chars().filter(|c| c.is_ascii_digit()).count();
+    let hex = segment.chars().all(|c| c.is_ascii_hexdigit());
+    let uuid_like = segment.len() == 36
+        && segment.chars().filter(|c| *c == '-').count() == 4
+        && segment.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+
+    // Numeric ids, UUIDs, hashes/object ids and long opaque tokens
+    digits == segment.len()
+        || uuid_like
+        || (hex && segment.len() >= 16 && digits > 0)
+        || (segment.len() >= 20 && digits >= 4 && !segment.contains('.'))
+        || segment.contains('@')
+}
+
+/// Describe the structure of a JSON value without its data.
+pub fn json_shape(value: &Value) -> Value {
+    match value {
+        Value::Null => json!("null"),
+        Value::Bool(_) => json!("boolean"),
+        Value::Number(n) if n.is_i64() || n.is_u64() => json!("integer"),
+        Value::Number(_) => json!("number"),
+        Value::String(_) => json!("string"),
+        Value::Array(items) => {
+            let element = items
+                .iter()
+                .map(json_shape)
+                .fold(None, |acc, shape| Some(merge_shapes(acc, shape)));
+            json!([element.unwrap_or_else(|| json!("unknown"))])
+        }
+        Value::Object(fields) => {
+            let shape: Map<String, Value> = fields
+                .iter()
+                .map(|(key, value)| (key.clone(), json_shape(value)))
+                .collect();
+            Value::Object(shape)
+        }
+    }
+}
+
+/// Combine two shapes, taking the union of object fields and joining conflicting types.
+fn merge_shapes(existing: Option<Value>, new: Value) -> Value {
+    let existing = match existing {
+        Some(existing) => existing,
+        None => return new,
+    };
+
+    match (existing, new) {
+        (Value::Object(mut a), Value::Object(b)) => {
+            for (key, shape) in b {
+                let merged = merge_shapes(a.remove(&key), shape);
+                a.insert(key, merged);
+            }
+            Value::Object(a)
+        }
+        (Value::Array(mut a), Value::Array(mut b)) => {
+            let merged = merge_shapes(a.pop(), b.pop().unwrap_or_else(|| json!("unknown")));
+            json!([merged])
+        }
+        (a, b) if a == b => a,
+        (Value::String(a), Value::String(b)) => {
+            let mut types: BTreeSet<&str> = a.split('|').collect();
+            types.extend(b.split('|'));
+            json!(types.into_iter().collect::<Vec<_>>().join("|"))
+        }
+        // Objects mixed with scalars: keep the richer structure
+        (a @ Value::Object(_), _) | (_, a @ Value::Object(_)) => a,
+        (a, _) => a,
+    }
+}
+
+/// Split `scheme://host[:port]/path?query` into its parts.
+fn split_url(url: &str) -> Option<(bool, String, u16, String, String)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let tls = match scheme.to_lowercase().as_str() {
+        "https" | "wss" => true,
+        "http" | "ws" => false,
+        _ => return None,
+    };
+
+    let authority_end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let authority = &rest[..authority_end];
+    let authority = authority.rsplit('@').next().unwrap_or(authority);
+    let (host, port) = split_host_port(authority)
+        .unwrap_or_else(|| (authority.to_string(), if tls { 443 } else { 80 }));
+
+    let target = &rest[authority_end..];
+    let target = target.split('#').next().unwrap_or(target);
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (target, ""),
+    };
+    let path = if path.is_empty() { "/" } else { path };
+
+    Some((
+        tls,
+        host.trim_matches(|c| c == '[' || c == ']').to_lowercase(),
+        port,
+        path.to_string(),
+        query.to_string(),
+    ))
+}
+
+fn split_host_port(value: &str) -> Option<(String, u16)> {
+    let (host, port) = value.rsplit_once(':')?;
+    let port = port.parse::<u16>().ok()?;
+    // Bare IPv6 addresses contain colons but no brackets
+    if host.contains(':') && !host.starts_with('[') {
+        return None;
+    }
+    Some((
+        host.trim_matches(|c| c == '[' || c == ']').to_string(),
+        port,
+    ))
+}
+
+fn query_param_names(query: &str) -> Vec<String> {
+    let mut names: Vec<String> = query
+        .split('&')
+        .filter_map(|pair| pair.split('=').next())
+        .filter(|name| !name.is_empty())
+        .map(str::to_string)
+        .collect();
+    names.sort();
+    names.dedup();
+    names
+}
+
+/// Unescaped `"..."` fields with the byte offset just past each closing quote.
+fn quoted_fields(line: &str) -> Vec<(String, usize)> {
+    let mut fields = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut escaped = false;
+
+    for (offset, c) in line.char_indices() {
+        match (in_quotes, c) {
+            (true, '\\') if !escaped => escaped = true,
+            (true, '"') if !escaped => {
+                fields.push((std::mem::take(&mut current), offset + 1));
+                in_quotes = false;
+            }
+            (true, c) => {
+                current.push(c);
+                escaped = false;
+            }
+            (false, '"') => in_quotes = true,
+            _ => {}
+        }
+    }
+
+    fields
+}
+
+/// Parse nginx `10/Oct/2023:13:55:36 +0000` or Envoy RFC 3339 timestamps.
+fn parse_log_time(value: &str) -> Option<u64> {
+    chrono::DateTime::parse_from_str(value, "%d/%b/%Y:%H:%M:%S %z")
+        .or_else(|_| chrono::DateTime::parse_from_rfc3339(value))
+        .ok()
+        .map(|date| date.timestamp() as u64)
+}
+
+/// Describe learned operations as MCP tool definitions for the API connector template.
+pub fn operations_as_tools(model: &ApiModel) -> Value {
+    let tools: Vec<Value> = model
+        .operations
+        .iter()
+        .map(|operation| {
+            json!({
+                "name": tool_name(operation),
+                "description": format!(
+                    "{} {} (observed {} times)",
+                    operation.method, operation.path_template, operation.sample_count
+                ),
+                "method": operation.method,
+                "path": operation.path_template,
+                "path_params": operation.path_params,
+                "query_params": operation.query_params,
+                "request_body": operation.request_body,
+                "response_body": operation.response_body,
+            })
+        })
+        .collect();
+    Value::Array(tools)
+}
+
+/// `GET /users/{id}/orders` becomes `get_users_by_id_orders`.
+fn tool_name(operation: &ApiOperation) -> String {
+    let mut parts = vec![operation.method.to_lowercase()];
+    for segment in operation.path_template.split('/').filter(|s| !s.is_empty()) {
+        if segment.starts_with('{') {
+            parts.push(format!(
+                "by_{}",
+                segment.trim_matches(|c| c == '{' || c == '}')
+            ));
+        } else {
+            let cleaned: String = segment
+                .chars()
+                .map(|c| {
+                    if c.is_ascii_alphanumeric() {
+                        c.to_ascii_lowercase()
+                    } else {
+                        '_'
+                    }
+                })
+                .collect();
+            parts.push(cleaned);
+        }
+    }
+    parts.join("_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target() -> (String, u16) {
+        ("api.example.com".to_string(), 443)
+    }
+
+    #[test]
+    fn parse_access_log_line_reads_nginx_combined() {
+        let line = r#"10.0.0.5 - - [10/Oct/2023:13:55:36 +0000] "GET /users/42?expand=orders&page=2 HTTP/1.1" 200 512 "-" "curl/8.0""#;
+        let record = parse_access_log_line(line, Some(&target())).unwrap();
+
+        assert_eq!(record.method, "GET");
+        assert_eq!(record.path, "/users/42");
+        assert_eq!(record.query_params, vec!["expand", "page"]);
+        assert_eq!(record.status, Some(200));
+        assert_eq!(record.timestamp, 1_696_946_136);
+        assert_eq!(
+            (record.host.as_str(), record.port),
+            ("api.example.com", 443)
+        );
+        assert!(record.tls);
+        assert_eq!(record.server_ip, None);
+    }
+
+    #[test]
+    fn parse_access_log_line_handles_escaped_quotes() {
+        let line = r#"10.0.0.5 - - [10/Oct/2023:13:55:36 +0000] "GET /search?q=\"x\" HTTP/1.1" 404 0 "-" "curl/8.0""#;
+        let record = parse_access_log_line(line, Some(&target())).unwrap();
+
+        assert_eq!(record.path, "/search");
+        assert_eq!(record.query_params, vec!["q"]);
+        assert_eq!(record.status, Some(404));
+    }
+
+    #[test]
+    fn parse_access_log_line_reads_status_after_the_request_field() {
+        // The request text also appears in an earlier field
+        let line = r#"GET / HTTP/1.1 999 "GET / HTTP/1.1" 500 0"#;
+        let record = parse_access_log_line(line, Some(&target())).unwrap();
+
+        assert_eq!(record.status, Some(500));
+    }
+
+    #[test]
+    fn parse_access_log_line_uses_envoy_upstream() {
+        let line = r#"[2023-10-10T13:55:36.000Z] "POST /v1/orders HTTP/1.1" 201 - 15 32 4 3 "10.0.0.9" "curl/8.0" "b1c2" "shop.internal:8080" "10.1.2.3:8080""#;
+        let record = parse_access_log_line(line, None).unwrap();
+
+        assert_eq!(record.method, "POST");
+        assert_eq!(record.path, "/v1/orders");
+        assert_eq!(record.status, Some(201));
+        assert_eq!(record.host, "shop.internal");
+        assert_eq!(record.port, 8080);
+        assert_eq!(record.server_ip.as_deref(), Some("10.1.2.3"));
+        assert!(!record.tls);
+    }
+
+    #[test]
+    fn parse_access_log_line_rejects_unusable_lines() {
+        assert!(parse_access_log_line("no quotes here", Some(&target())).is_none());
+        assert!(parse_access_log_line(r#""-" 400 0"#, Some(&target())).is_none());
+        // Plain nginx lines need a target to attribute the request to
+        let line = r#"10.0.0.5 - - [10/Oct/2023:13:55:36 +0000] "GET / HTTP/1.1" 200 1"#;
+        assert!(parse_access_log_line(line, None).is_none());
+    }
+
+    #[test]
+    fn template_path_replaces_identifiers() {
+        assert_eq!(
+            template_path("/users/123/orders/550e8400-e29b-41d4-a716-446655440000"),
+            (
+                "/users/{id}/orders/{id2}".to_string(),
+                vec!["id".to_string(), "id2".to_string()]
+            )
+        );
+        assert_eq!(
+            template_path("/objects/5f8d0d55b54764421b7156c9"),
+            ("/objects/{id}".to_string(), vec!["id".to_string()])
+        );
+        assert_eq!(
+            template_path("/people/jane@example.com/profile"),
+            ("/people/{id}/profile".to_string(), vec!["id".to_string()])
+        );
+    }
+
+    #[test]
+    fn template_path_keeps_static_segments() {
+        assert_eq!(
+            template_path("/api/v2/health"),
+            ("/api/v2/health".to_string(), vec![])
+        );
+        assert_eq!(
+            template_path("/static/app.min.js"),
+            ("/static/app.min.js".to_string(), vec![])
+        );
+        assert_eq!(template_path(""), ("/".to_string(), vec![]));
+        assert_eq!(template_path("/"), ("/".to_string(), vec![]));
+    }
+}
@@ -0,0 +1,395 @@
+use crate::merge;
+use crate::scanner::{EndpointInfo, NetworkPlan, ScanResults};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::Path;
+
+/// Progress of a port scan: hosts whose ports have all been probed and the
+/// endpoints found so far.
+pub struct ScanState {
+    pub started_at: String,
+    pub completed: HostSet,
+    pub discovery_done: bool,
+    pub results: ScanResults,
+}
+
+impl ScanState {
+    pub fn new() -> Self {
+        ScanState {
+            started_at: chrono::Utc::now().to_rfc3339(),
+            completed: HostSet::default(),
+            discovery_done: false,
+            results: Vec::new(),
+        }
+    }
+}
+
+impl Default for ScanState {
+    fn default() -> Self {
+        ScanState::new()
+    }
+}
+
+/// Set of IPv4 addresses stored as merged inclusive ranges, so a finished /16
+/// takes a single entry in the checkpoint file.
+#[derive(Debug, Clone, Default)]
+pub struct HostSet {
+    ranges: BTreeMap<u32, u32>,
+}
+
+impl HostSet {
+    pub fn contains(&self, host: u32) -> bool {
+        self.ranges
+            .range(..=host)
+            .next_back()
+            .is_some_and(|(_, end)| host <= *end)
+    }
+
+    pub fn insert(&mut self, host: u32) {
+        if self.contains(host) {
+            return;
+        }
+
+        // Extend the range ending just before this host, or start a new one
+        let start = match self.ranges.range(..host).next_back() {
+            Some((start, end)) if end.checked_add(1) == Some(host) => *start,
+            _ => host,
+        };
+        let mut end = host;
+
+        // Absorb a range starting just after this host
+        if let Some(next) = host.checked_add(1) {
+            if let Some(next_end) = self.ranges.remove(&next) {
+                end = next_end;
+            }
+        }
+
+        self.ranges.insert(start, end);
+    }
+
+    /// Number of hosts in the set that fall within `first..=last`.
+    pub fn count_in(&self, first: u32, last: u32) -> u64 {
+        self.ranges
+            .range(..=last)
+            .filter(|(_, end)| **end >= first)
+            .map(|(start, end)| u64::from((*end).min(last) - (*start).max(first)) + 1)
+            .sum()
+    }
+
+    /// The set's ranges clipped to `first..=last`.
+    pub fn ranges_within(&self, first: u32, last: u32) -> Vec<(u32, u32)> {
+        self.ranges
+            .range(..=last)
+            .filter(|(_, end)| **end >= first)
+            .map(|(start, end)| ((*start).max(first), (*end).min(last)))
+            .collect()
+    }
+
+    pub fn count(&self) -> u64 {
+        self.ranges
+            .iter()
+            .map(|(start, end)| u64::from(end - start) + 1)
+            .sum()
+    }
+
+    fn to_strings(&self) -> Vec<String> {
+        self.ranges
+            .iter()
+            .map(|(start, end)| {
+                if start == end {
+                    Ipv4Addr::from(*start).to_string()
+                } else {
+                    format!("{}-{}", Ipv4Addr::from(*start), Ipv4Addr::from(*end))
+                }
+            })
+            .collect()
+    }
+
+    fn from_strings(ranges: &[String]) -> Result<Self, Box<dyn Error>> {
+        let mut set = HostSet::default();
+        for range in ranges {
+            let (start, end) = match range.split_once('-') {
+                Some((start, end)) => (start, end),
+                None => (range.as_str(), range.as_str()),
+            };
+            let start = u32::from(start.trim().parse::<Ipv4Addr>()?);
+            let end = u32::from(end.trim().parse::<Ipv4Addr>()?);
+            if start > end {
+                return Err(format!("Invalid host range in checkpoint: {}", range).into());
+            }
+            // Ranges are written merged and in order, so they can be stored directly
+            set.ranges.insert(start, end);
+        }
+        Ok(set)
+    }
+}
+
+/// On-disk form of a `ScanState`, tied to the resolved network plans it was
+/// created for: address ranges, exclusions, ports and per-network settings.
+#[derive(Debug, Serialize, Deserialize)]
+struct Checkpoint {
+    #[serde(default)]
+    plans: serde_json::Value,
+    started_at: String,
+    updated_at: String,
+    completed_hosts: Vec<String>,
+    discovery_done: bool,
+    results: ScanResults,
+}
+
+/// Write the current progress, replacing the previous checkpoint atomically so
+/// a crash while writing never leaves a truncated file behind.
+pub fn save<P: AsRef<Path>>(
+    path: P,
+    plans: &[NetworkPlan],
+    state: &ScanState,
+) -> Result<(), Box<dyn Error>> {
+    let checkpoint = Checkpoint {
+        plans: serde_json::to_value(plans)?,
+        started_at: state.started_at.clone(),
+        updated_at: chrono::Utc::now().to_rfc3339(),
+        completed_hosts: state.completed.to_strings(),
+        discovery_done: state.discovery_done,
+        // Hosts that were only partly probed are scanned again on resume, so
+        // their findings are left out to avoid duplicates
+        results: state
+            .results
+            .iter()
+            .filter(|endpoint| is_checkpointed(endpoint, &state.completed))
+            .cloned()
+            .collect(),
+    };
+
+    let path = path.as_ref();
+    let temp_path = path.with_extension("tmp");
+    fs::write(&temp_path, serde_json::to_string(&checkpoint)?)?;
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+fn is_checkpointed(endpoint: &EndpointInfo, completed: &HostSet) -> bool {
+    if !merge::detected_by(endpoint).contains(&"port_scan") {
+        return true;
+    }
+    endpoint
+        .address
+        .parse::<Ipv4Addr>()
+        .is_ok_and(|address| completed.contains(u32::from(address)))
+}
+
+/// Load a checkpoint to resume from. Returns `None` when there is none, and
+/// fails when it was written for networks that resolve differently, e.g. after
+/// a change to the ports, profiles, exclusions or host discovery settings.
+pub fn load<P: AsRef<Path>>(
+    path: P,
+    plans: &[NetworkPlan],
+) -> Result<Option<ScanState>, Box<dyn Error>> {
+    let path = path.as_ref();
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let contents = fs::read_to_string(path)?;
+    let checkpoint: Checkpoint = serde_json::from_str(&contents)
+        .map_err(|err| format!("Invalid checkpoint {}: {}", path.display(), err))?;
+
+    if checkpoint.plans != serde_json::to_value(plans)? {
+        return Err(format!(
+            "Checkpoint {} was created for different networks or scan settings; remove it or restore the original configuration",
+            path.display()
+        )
+        .into());
+    }
+
+    Ok(Some(ScanState {
+        started_at: checkpoint.started_at,
+        completed: HostSet::from_strings(&checkpoint.completed_hosts)?,
+        discovery_done: checkpoint.discovery_done,
+        results: checkpoint.results,
+    }))
+}
+
+/// Delete the checkpoint once the scan it tracks has finished.
+pub fn remove<P: AsRef<Path>>(path: P) {
+    let path = path.as_ref();
+    if path.exists() {
+        if let Err(err) = fs::remove_file(path) {
+            log::warn!("Failed to remove checkpoint {}: {}", path.display(), err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiles::ScanSettings;
+    use crate::scanner::{EndpointType, Protocol};
+    use std::collections::HashMap;
+
+    fn ip(address: &str) -> u32 {
+        u32::from(address.parse::<Ipv4Addr>().unwrap())
+    }
+
+    fn plan(cidr: &str, ports: Vec<u16>) -> NetworkPlan {
+        let (first, last) = match cidr {
+            "10.0.0.0/24" => (ip("10.0.0.0"), ip("10.0.0.255")),
+            _ => (ip("10.0.1.0"), ip("10.0.1.255")),
+        };
+        NetworkPlan {
+            cidr: cidr.to_string(),
+            first,
+            last,
+            ports,
+            settings: ScanSettings {
+                profile: None,
+                port_scan: true,
+                ports: Vec::new(),
+                concurrency: 100,
+                timeout_ms: 1000,
+                use_mdns: false,
+                use_upnp: false,
+                host_discovery: Default::default(),
+                rate_limit: Default::default(),
+            },
+            excluded: Vec::new(),
+        }
+    }
+
+    fn endpoint(address: &str, detected_by: &str) -> EndpointInfo {
+        let mut metadata = HashMap::new();
+        metadata.insert("detected_by".to_string(), detected_by.to_string());
+        EndpointInfo {
+            endpoint_type: EndpointType::API,
+            address: address.to_string(),
+            port: 80,
+            protocol: Protocol::HTTP,
+            metadata,
+            discovered_timestamp: 0,
+            api_model: None,
+            confidence: 0.5,
+            evidence: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn host_set_merges_adjacent_inserts() {
+        let mut set = HostSet::default();
+        set.insert(ip("10.0.0.1"));
+        set.insert(ip("10.0.0.3"));
+        assert_eq!(set.to_strings(), vec!["10.0.0.1", "10.0.0.3"]);
+
+        // Fills the gap and joins both neighbours into one range
+        set.insert(ip("10.0.0.2"));
+        assert_eq!(set.to_strings(), vec!["10.0.0.1-10.0.0.3"]);
+
+        set.insert(ip("10.0.0.0"));
+        set.insert(ip("10.0.0.4"));
+        assert_eq!(set.to_strings(), vec!["10.0.0.0-10.0.0.4"]);
+        assert_eq!(set.count(), 5);
+    }
+
+    #[test]
+    fn host_set_ignores_overlapping_inserts() {
+        let mut set = HostSet::default();
+        (ip("10.0.0.10")..=ip("10.0.0.20")).for_each(|host| set.insert(host));
+        set.insert(ip("10.0.0.10"));
+        set.insert(ip("10.0.0.15"));
+        set.insert(ip("10.0.0.20"));
+
+        assert_eq!(set.to_strings(), vec!["10.0.0.10-10.0.0.20"]);
+        assert_eq!(set.count(), 11);
+        assert!(set.contains(ip("10.0.0.15")));
+        assert!(!set.contains(ip("10.0.0.9")));
+        assert!(!set.contains(ip("10.0.0.21")));
+    }
+
+    #[test]
+    fn host_set_handles_address_space_edges() {
+        let mut set = HostSet::default();
+        set.insert(u32::MAX);
+        set.insert(u32::MAX - 1);
+        set.insert(0);
+        set.insert(1);
+
+        assert_eq!(
+            set.to_strings(),
+            vec!["0.0.0.0-0.0.0.1", "255.255.255.254-255.255.255.255"]
+        );
+        assert_eq!(set.count(), 4);
+    }
+
+    #[test]
+    fn host_set_counts_and_clips_ranges() {
+        let mut set = HostSet::default();
+        (ip("10.0.0.250")..=ip("10.0.1.5")).for_each(|host| set.insert(host));
+
+        assert_eq!(set.count_in(ip("10.0.0.0"), ip("10.0.0.255")), 6);
+        assert_eq!(set.count_in(ip("10.0.1.0"), ip("10.0.1.255")), 6);
+        assert_eq!(set.count_in(ip("10.0.2.0"), ip("10.0.2.255")), 0);
+        assert_eq!(
+            set.ranges_within(ip("10.0.1.0"), ip("10.0.1.255")),
+            vec![(ip("10.0.1.0"), ip("10.0.1.5"))]
+        );
+    }
+
+    #[test]
+    fn host_set_rejects_reversed_ranges() {
+        assert!(HostSet::from_strings(&["10.0.0.9-10.0.0.1".to_string()]).is_err());
+        assert!(HostSet::from_strings(&["10.0.0".to_string()]).is_err());
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scan.checkpoint");
+        let plans = vec![plan("10.0.0.0/24", vec![80, 443])];
+
+        let mut state = ScanState::new();
+        (ip("10.0.0.1")..=ip("10.0.0.9")).for_each(|host| state.completed.insert(host));
+        state.completed.insert(ip("10.0.0.42"));
+        state.discovery_done = true;
+        state.results = vec![
+            endpoint("10.0.0.5", "port_scan"),
+            // Host not finished yet, so it is scanned again on resume
+            endpoint("10.0.0.77", "port_scan"),
+            endpoint("10.0.0.77", "mdns"),
+        ];
+        save(&path, &plans, &state).unwrap();
+        assert!(!path.with_extension("tmp").exists());
+
+        let loaded = load(&path, &plans).unwrap().unwrap();
+        assert_eq!(loaded.started_at, state.started_at);
+        assert_eq!(
+            loaded.completed.to_strings(),
+            vec!["10.0.0.1-10.0.0.9", "10.0.0.42"]
+        );
+        assert!(loaded.discovery_done);
+        let kept: Vec<(&str, Vec<&str>)> = loaded
+            .results
+            .iter()
+            .map(|endpoint| (endpoint.address.as_str(), merge::detected_by(endpoint)))
+            .collect();
+        assert_eq!(
+            kept,
+            vec![("10.0.0.5", vec!["port_scan"]), ("10.0.0.77", vec!["mdns"])]
+        );
+
+        remove(&path);
+        assert!(!path.exists());
+        assert!(load(&path, &plans).unwrap().is_none());
+    }
+
+    #[test]
+    fn load_rejects_checkpoint_for_other_plans() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("scan.checkpoint");
+        save(&path, &[plan("10.0.0.0/24", vec![80])], &ScanState::new()).unwrap();
+
+        assert!(load(&path, &[plan("10.0.0.0/24", vec![80, 443])]).is_err());
+        assert!(load(&path, &[plan("10.0.1.0/24", vec![80])]).is_err());
+        assert!(load(&path, &[plan("10.0.0.0/24", vec![80])]).is_ok());
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
 use std::path::Path;
@@ -7,18 +8,175 @@ use std::path::Path;
 pub struct Config {
     pub scan: ScanConfig,
     pub connector: ConnectorConfig,
+    #[serde(default)]
+    pub server: ServerConfig,
     pub logging: LoggingConfig,
+    #[serde(default)]
+    pub inventory: InventoryConfig,
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    #[serde(default)]
+    pub checkpoint: CheckpointConfig,
+    #[serde(default)]
+    pub classification: ClassificationConfig,
+    #[serde(default)]
+    pub mcp_clients: McpClientsConfig,
+    #[serde(default)]
+    pub scope: ScopeConfig,
+    #[serde(default)]
+    pub profiles: HashMap<String, ScanProfile>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct ScanConfig {
-    pub networks: Vec<String>,
-    pub ports: Vec<u16>,
+    pub networks: Vec<NetworkEntry>,
+    pub ports: Vec<PortSpec>,
     pub concurrency: usize,
     pub timeout_ms: u64,
     pub use_mdns: bool,
     pub use_upnp: bool,
     pub exclude_ips: Vec<String>,
+    /// Profile applied to networks that do not name their own
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Set by `--skip-host-discovery`; overrides profiles as well
+    #[serde(skip)]
+    pub skip_host_discovery: bool,
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    #[serde(default)]
+    pub host_discovery: HostDiscoveryConfig,
+}
+
+/// Liveness checks run before port probing so dead addresses are skipped.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct HostDiscoveryConfig {
+    pub enabled: bool,
+    /// Treat hosts in the kernel's ARP table as alive
+    pub use_arp: bool,
+    /// Send ICMP echo requests (needs unprivileged ping sockets or raw socket access)
+    pub use_icmp: bool,
+    /// Ports used for TCP pings; any answer, even a reset, marks the host alive
+    pub tcp_ping_ports: Vec<u16>,
+    pub timeout_ms: u64,
+    /// Hosts or CIDR blocks that drop pings and are always port scanned
+    pub assume_alive: Vec<String>,
+}
+
+impl Default for HostDiscoveryConfig {
+    fn default() -> Self {
+        HostDiscoveryConfig {
+            enabled: true,
+            use_arp: true,
+            use_icmp: true,
+            tcp_ping_ports: vec![80, 443, 22, 445],
+            timeout_ms: 1000,
+            assume_alive: Vec::new(),
+        }
+    }
+}
+
+/// A network to scan: either a plain CIDR string, or a table that overrides
+/// scan settings for that network, e.g.
+/// `{ cidr = "10.1.0.0/24", profile = "quick", ports = ["web"] }`.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum NetworkEntry {
+    Cidr(String),
+    Detailed {
+        cidr: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        profile: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ports: Option<Vec<PortSpec>>,
+    },
+}
+
+impl NetworkEntry {
+    pub fn cidr(&self) -> &str {
+        match self {
+            NetworkEntry::Cidr(cidr) | NetworkEntry::Detailed { cidr, .. } => cidr,
+        }
+    }
+
+    pub fn profile(&self) -> Option<&str> {
+        match self {
+            NetworkEntry::Detailed {
+                profile: Some(profile),
+                ..
+            } => Some(profile),
+            _ => None,
+        }
+    }
+
+    pub fn port_override(&self) -> Option<&[PortSpec]> {
+        match self {
+            NetworkEntry::Detailed {
+                ports: Some(ports), ..
+            } => Some(ports),
+            _ => None,
+        }
+    }
+}
+
+/// Named bundle of scan settings in `[profiles.<name>]`. Unset fields keep
+/// the values from `[scan]`; see `profiles` for the built-in profiles.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ScanProfile {
+    /// Probe ports at all; `false` leaves only mDNS/UPnP discovery
+    pub port_scan: Option<bool>,
+    pub ports: Option<Vec<PortSpec>>,
+    pub concurrency: Option<usize>,
+    pub timeout_ms: Option<u64>,
+    pub use_mdns: Option<bool>,
+    pub use_upnp: Option<bool>,
+    pub host_discovery: Option<HostDiscoveryConfig>,
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+/// A port number, or an expression with ranges, named sets and exclusions
+/// such as `"8000-8100"`, `"web"` or `"top-1000,!databases"` (see `ports::resolve`).
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum PortSpec {
+    Port(u16),
+    Expression(String),
+}
+
+/// Politeness controls applied on top of `concurrency`, so fragile devices and
+/// IDS sensors are not flooded with connection attempts.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Connection attempts per second across the whole scan; 0 disables the limit
+    pub packets_per_second: u32,
+    /// Concurrent connection attempts to a single host
+    pub max_connections_per_host: usize,
+    /// Probe hosts and ports in random order instead of sequentially
+    pub randomize_order: bool,
+    /// Random delay of up to this many milliseconds before each probe
+    pub jitter_ms: u64,
+    /// Slow down on hosts that start timing out or resetting connections
+    pub adaptive_backoff: bool,
+    /// Consecutive failed probes before a host is slowed down
+    pub backoff_threshold: u32,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            packets_per_second: 1000,
+            max_connections_per_host: 4,
+            randomize_order: true,
+            jitter_ms: 10,
+            adaptive_backoff: true,
+            backoff_threshold: 5,
+            max_backoff_ms: 5000,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -26,7 +184,147 @@ pub struct ConnectorConfig {
     pub default_polling_interval: u64,
     pub output_format: String,
     pub include_metadata: bool,
+    /// Directory of `*.hbs` templates that override or add to the built-in ones
     pub templates_dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub template_rules: Vec<TemplateRule>,
+}
+
+/// Use `template` for endpoints matching every condition given. Rules are
+/// checked in order before the built-in choice by endpoint type.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct TemplateRule {
+    pub template: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint_type: Option<String>,
+    /// Metadata values to match, case-insensitively; `*` only requires the key
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
+}
+
+/// MCP server runtime started by `serve`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ServerConfig {
+    pub transport: ServerTransport,
+    /// Address and port the Streamable HTTP transport listens on
+    pub bind_address: String,
+    pub port: u16,
+    /// The single MCP endpoint path
+    pub path: String,
+    /// Answer POSTed requests with plain JSON instead of an SSE stream
+    pub json_response: bool,
+    /// Sessions idle for longer than this are forgotten
+    pub session_timeout_secs: u64,
+    /// Sessions kept at once; `initialize` is refused beyond this
+    pub max_sessions: usize,
+    /// Browser origins allowed besides localhost
+    pub allowed_origins: Vec<String>,
+    /// Bearer token every HTTP request must carry, or `${NAME}` to read it
+    /// from the environment. Required to listen on a non-loopback address
+    pub auth_token: Option<String>,
+    /// Limits on requests made by API connector tools
+    pub request_timeout_ms: u64,
+    pub max_response_bytes: usize,
+    /// Transformations still running after this long fail the tool call
+    pub transform_timeout_ms: u64,
+    /// Limits on statements run by database connector tools
+    pub query_timeout_ms: u64,
+    pub max_rows: usize,
+    /// First keywords of the statements the query tools accept
+    pub allowed_statements: Vec<String>,
+    /// Database name and credentials for database connectors that leave
+    /// them out, keyed by `host:port`
+    pub databases: HashMap<String, DatabaseDefaults>,
+}
+
+/// Connection settings a database connector falls back to. Any value may be
+/// written as `${NAME}` to read it from the environment.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct DatabaseDefaults {
+    pub database: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ServerTransport {
+    Stdio,
+    /// Streamable HTTP
+    Http,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            transport: ServerTransport::Stdio,
+            bind_address: "127.0.0.1".to_string(),
+            port: 8765,
+            path: "/mcp".to_string(),
+            json_response: false,
+            session_timeout_secs: 3600,
+            max_sessions: 100,
+            allowed_origins: Vec::new(),
+            auth_token: None,
+            request_timeout_ms: 30000,
+            max_response_bytes: 1024 * 1024,
+            transform_timeout_ms: 5000,
+            query_timeout_ms: 10000,
+            max_rows: 500,
+            allowed_statements: ["select", "with", "values", "explain", "show", "describe"]
+                .iter()
+                .map(|keyword| keyword.to_string())
+                .collect(),
+            databases: HashMap::new(),
+        }
+    }
+}
+
+/// MCP client configuration written alongside the connectors.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct McpClientsConfig {
+    pub enabled: bool,
+    /// Name the server is listed under in client configuration
+    pub server_name: String,
+}
+
+impl Default for McpClientsConfig {
+    fn default() -> Self {
+        McpClientsConfig {
+            enabled: true,
+            server_name: "network-connectors".to_string(),
+        }
+    }
+}
+
+/// What to do with endpoints whose classification is less certain than
+/// `min_confidence`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ClassificationConfig {
+    pub min_confidence: f64,
+    pub below_threshold: LowConfidenceAction,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LowConfidenceAction {
+    /// Generate the connector but flag it with `needs_review`
+    Review,
+    /// Do not generate a connector
+    Skip,
+}
+
+impl Default for ClassificationConfig {
+    fn default() -> Self {
+        ClassificationConfig {
+            min_confidence: 0.5,
+            below_threshold: LowConfidenceAction::Review,
+        }
+    }
 }
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -36,6 +334,81 @@ pub struct LoggingConfig {
     pub console: bool,
 }
 
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InventoryConfig {
+    pub enabled: bool,
+    pub path: String,
+}
+
+impl Default for InventoryConfig {
+    fn default() -> Self {
+        InventoryConfig {
+            enabled: true,
+            path: "mcp_inventory.db".to_string(),
+        }
+    }
+}
+
+/// Schedules used by `daemon` mode. Schedules are cron expressions with an
+/// optional leading seconds field, e.g. `0 */30 * * * *` or `@hourly`.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DaemonConfig {
+    pub default_schedule: String,
+    #[serde(default)]
+    pub schedules: HashMap<String, String>,
+    pub discovery_schedule: Option<String>,
+    pub status_file: String,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        DaemonConfig {
+            default_schedule: "@hourly".to_string(),
+            schedules: HashMap::new(),
+            discovery_schedule: None,
+            status_file: "mcp_daemon_status.json".to_string(),
+        }
+    }
+}
+
+/// Guardrails checked before any active scan. The scope file is mandatory.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct ScopeConfig {
+    /// File listing the CIDRs, addresses and domains that may be scanned
+    pub file: String,
+    /// Allow networks outside private, loopback and link-local ranges
+    pub allow_public: bool,
+    /// Largest number of hosts a single scan may target
+    pub max_hosts: u64,
+}
+
+impl Default for ScopeConfig {
+    fn default() -> Self {
+        ScopeConfig {
+            file: "scope.txt".to_string(),
+            allow_public: false,
+            max_hosts: 65536,
+        }
+    }
+}
+
+/// Where scan progress is saved so an interrupted scan can be resumed.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct CheckpointConfig {
+    pub file: String,
+    pub interval_secs: u64,
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        CheckpointConfig {
+            file: "mcp_scan_checkpoint.json".to_string(),
+            interval_secs: 30,
+        }
+    }
+}
+
 pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Config, Box<dyn Error>> {
     let config_path = path.as_ref();
 
@@ -57,33 +430,50 @@ pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Config, Box<dyn Error>> {
 fn create_default_config() -> Config {
     Config {
         scan: ScanConfig {
-            networks: vec!["192.168.1.0/24".to_string()],
-            ports: vec![
+            networks: vec![NetworkEntry::Cidr("192.168.1.0/24".to_string())],
+            ports: [
                 22, 80, 443, 3306, 5432, 1521, 27017, // Common service ports
                 8080, 8443, 9000, 9200, // Common web services
                 514, 1468, 10514, // Syslog
                 1883, 8883, 5672, // MQTT and AMQP
-            ],
+            ]
+            .iter()
+            .map(|port| PortSpec::Port(*port))
+            .collect(),
             concurrency: 100,
             timeout_ms: 1000,
             use_mdns: true,
             use_upnp: true,
             exclude_ips: Vec::new(),
+            profile: None,
+            skip_host_discovery: false,
+            rate_limit: RateLimitConfig::default(),
+            host_discovery: HostDiscoveryConfig::default(),
         },
         connector: ConnectorConfig {
             default_polling_interval: 60,
             output_format: "json".to_string(),
             include_metadata: true,
             templates_dir: None,
+            template_rules: Vec::new(),
         },
+        server: ServerConfig::default(),
         logging: LoggingConfig {
             level: "info".to_string(),
             file: Some("mcp_scanner.log".to_string()),
             console: true,
         },
+        inventory: InventoryConfig::default(),
+        daemon: DaemonConfig::default(),
+        checkpoint: CheckpointConfig::default(),
+        classification: ClassificationConfig::default(),
+        mcp_clients: McpClientsConfig::default(),
+        scope: ScopeConfig::default(),
+        profiles: HashMap::new(),
     }
 }
 
+#[allow(dead_code)]
 pub fn save_config<P: AsRef<Path>>(config: &Config, path: P) -> Result<(), Box<dyn Error>> {
     let toml_str = toml::to_string(config)?;
     fs::write(path, toml_str)?;
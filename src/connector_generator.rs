@@ -1,12 +1,121 @@
-use crate::config::Config;
+use crate::api_learning;
+use crate::config::{Config, LowConfidenceAction, TemplateRule};
+use crate::inventory;
+use crate::merge;
 use crate::scanner::{EndpointInfo, EndpointType, Protocol, ScanResults};
-use handlebars::Handlebars;
+use handlebars::{handlebars_helper, Handlebars};
 use serde_json::{json, Value};
 use std::error::Error;
+use std::collections::HashSet;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::net::IpAddr;
+use std::path::Path;
 use uuid::Uuid;
 
+// Namespace for connector IDs, so the same endpoint always gets the same ID
+const CONNECTOR_NAMESPACE: Uuid = Uuid::from_u128(0x5a1588c6_577c_4d64_ac6b_1704f1f38b96);
+
+/// Stable ID for an endpoint's connector, derived from its address, port and
+/// transport. Reclassifying the endpoint keeps the ID.
+pub fn connector_id(endpoint: &EndpointInfo) -> Uuid {
+    let identity = format!(
+        "{}:{}/{}",
+        endpoint.address.to_lowercase(),
+        endpoint.port,
+        merge::transport(endpoint)
+    );
+    Uuid::new_v5(&CONNECTOR_NAMESPACE, identity.as_bytes())
+}
+
+/// File name of an endpoint's connector, which carries the connector ID.
+pub fn connector_file_name(endpoint: &EndpointInfo, format: OutputFormat) -> String {
+    format!(
+        "mcp_connector_{}.{}",
+        connector_id(endpoint),
+        format.extension()
+    )
+}
+
+/// File format of connectors and the manifest, set by `connector.output_format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Json,
+    Yaml,
+    Toml,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name.to_lowercase().as_str() {
+            "json" => Some(OutputFormat::Json),
+            "yaml" | "yml" => Some(OutputFormat::Yaml),
+            "toml" => Some(OutputFormat::Toml),
+            _ => None,
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Result<OutputFormat, Box<dyn Error>> {
+        let name = &config.connector.output_format;
+        OutputFormat::from_name(name).ok_or_else(|| {
+            format!(
+                "Unsupported connector output_format '{}' (use json, yaml or toml)",
+                name
+            )
+            .into()
+        })
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Json => "json",
+            OutputFormat::Yaml => "yaml",
+            OutputFormat::Toml => "toml",
+        }
+    }
+
+    /// Write a document in this format.
+    pub fn serialize(self, document: &Value) -> Result<String, Box<dyn Error>> {
+        Ok(match self {
+            OutputFormat::Json => serde_json::to_string_pretty(document)?,
+            OutputFormat::Yaml => serde_yaml::to_string(document)?,
+            // TOML has no null, so keys without a value are left out
+            OutputFormat::Toml => toml::to_string_pretty(&without_nulls(document))?,
+        })
+    }
+}
+
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key.clone(), without_nulls(value)))
+                .collect(),
+        ),
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .filter(|item| !item.is_null())
+                .map(without_nulls)
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Order endpoints by address (numerically for IP addresses) and port, so
+/// generated output does not depend on discovery order.
+fn sort_endpoints(endpoints: &mut [&EndpointInfo]) {
+    endpoints.sort_by_key(|endpoint| {
+        (
+            endpoint.address.parse::<IpAddr>().ok(),
+            endpoint.address.to_lowercase(),
+            endpoint.port,
+            merge::transport(endpoint),
+        )
+    });
+}
+
 pub fn generate_connectors(
     scan_results: &ScanResults,
     output_dir: &str,
@@ -19,24 +128,33 @@ pub fn generate_connectors(
     }
 
     // Initialize template engine
-    let mut handlebars = Handlebars::new();
+    let handlebars = create_template_engine(config)?;
+    let format = OutputFormat::from_config(config)?;
+
+    let (mut accepted, skipped): (Vec<&EndpointInfo>, Vec<&EndpointInfo>) = scan_results
+        .iter()
+        .partition(|endpoint| should_generate(endpoint, config));
+    if !skipped.is_empty() {
+        log::info!(
+            "Skipping {} endpoints classified with confidence below {}",
+            skipped.len(),
+            config.classification.min_confidence
+        );
+    }
 
-    // Register templates
-    register_templates(&mut handlebars)?;
+    sort_endpoints(&mut accepted);
 
     // Generate a connector for each endpoint
     let mut generated_count = 0;
-    for endpoint in scan_results {
-        let template_name = select_template_for_endpoint(endpoint);
-        let connector_data = prepare_connector_data(endpoint, config);
-
-        // Generate connector filename
-        let connector_id = Uuid::new_v4().to_string();
-        let filename = format!("mcp_connector_{}.json", connector_id);
-        let file_path = output_path.join(filename);
+    let mut written = HashSet::new();
+    for endpoint in accepted.iter().copied() {
+        let connector_id = connector_id(endpoint);
+        let filename = connector_file_name(endpoint, format);
+        let file_path = output_path.join(&filename);
+        written.insert(filename);
 
         // Render template
-        let rendered = handlebars.render(template_name, &connector_data)?;
+        let rendered = render_connector(&handlebars, endpoint, config)?;
 
         // Write connector to file
         fs::write(file_path, rendered)?;
@@ -51,11 +169,168 @@ pub fn generate_connectors(
     }
 
     // Generate manifest file
-    generate_manifest(scan_results, output_path)?;
+    let accepted: ScanResults = accepted.into_iter().cloned().collect();
+    written.insert(generate_manifest(&accepted, output_path, config)?);
+
+    remove_stale_connectors(output_path, &written)?;
 
     Ok(generated_count)
 }
 
+/// Remove connectors left by an earlier run for endpoints that were not
+/// generated this time, and manifests in another format, so the directory
+/// matches the latest results.
+fn remove_stale_connectors(
+    output_path: &Path,
+    written: &HashSet<String>,
+) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(output_path)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let generated = name.starts_with("mcp_connector_") || name.starts_with("mcp_manifest.");
+        if generated && !written.contains(&name) {
+            log::info!("Removing stale output file {}", name);
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether a connector is written for the endpoint, given the configured
+/// confidence threshold.
+pub fn should_generate(endpoint: &EndpointInfo, config: &Config) -> bool {
+    !needs_review(endpoint, config)
+        || config.classification.below_threshold == LowConfidenceAction::Review
+}
+
+fn needs_review(endpoint: &EndpointInfo, config: &Config) -> bool {
+    endpoint.confidence < config.classification.min_confidence
+}
+
+/// Create a template engine with the built-in connector templates, overridden
+/// or extended by the templates in `connector.templates_dir`.
+pub fn create_template_engine(config: &Config) -> Result<Handlebars<'static>, Box<dyn Error>> {
+    let mut handlebars = Handlebars::new();
+    // Templates produce JSON, so values are escaped for JSON strings, not HTML
+    handlebars.register_escape_fn(escape_json_string);
+    register_helpers(&mut handlebars);
+    register_templates(&mut handlebars)?;
+
+    if let Some(templates_dir) = &config.connector.templates_dir {
+        let dir = Path::new(templates_dir);
+        let loaded = load_template_dir(&mut handlebars, dir, dir)?;
+        log::info!("Loaded {} templates from {}", loaded, templates_dir);
+    }
+
+    for rule in &config.connector.template_rules {
+        if !handlebars.has_template(&rule.template) {
+            return Err(format!(
+                "Template rule refers to unknown template '{}'",
+                rule.template
+            )
+            .into());
+        }
+    }
+    Ok(handlebars)
+}
+
+handlebars_helper!(json_helper: |value: Json| serde_json::to_string(value).unwrap_or_default());
+handlebars_helper!(uppercase_helper: |value: str| value.to_uppercase());
+handlebars_helper!(slugify_helper: |value: str| slugify(value));
+
+/// Escape a value for use inside a JSON string literal.
+fn escape_json_string(value: &str) -> String {
+    let quoted = Value::String(value.to_string()).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+fn register_helpers(handlebars: &mut Handlebars) {
+    handlebars.register_helper("json", Box::new(json_helper));
+    handlebars.register_helper("uppercase", Box::new(uppercase_helper));
+    handlebars.register_helper("slugify", Box::new(slugify_helper));
+}
+
+/// Lowercase `value` and join its words with dashes, e.g. `My API v2` becomes `my-api-v2`.
+fn slugify(value: &str) -> String {
+    value
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Register every `*.hbs` file under `dir`, named by its path relative to
+/// `root` without the extension (`api_connector`, `partials/auth`). A file
+/// named like a built-in template replaces it, and any template can be
+/// included in another as a partial with `{{> name}}`.
+fn load_template_dir(
+    handlebars: &mut Handlebars,
+    root: &Path,
+    dir: &Path,
+) -> Result<usize, Box<dyn Error>> {
+    let mut entries: Vec<_> = fs::read_dir(dir)
+        .map_err(|err| format!("Cannot read templates directory {}: {}", dir.display(), err))?
+        .collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.path());
+
+    let mut loaded = 0;
+    for entry in entries {
+        let path = entry.path();
+        if path.is_dir() {
+            loaded += load_template_dir(handlebars, root, &path)?;
+            continue;
+        }
+        if path.extension().and_then(|ext| ext.to_str()) != Some("hbs") {
+            continue;
+        }
+
+        let relative = path.strip_prefix(root)?.with_extension("");
+        let name = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if handlebars.has_template(&name) {
+            log::debug!("Template {} overrides the built-in template", name);
+        }
+        handlebars
+            .register_template_file(&name, &path)
+            .map_err(|err| format!("Invalid template {}: {}", path.display(), err))?;
+        loaded += 1;
+    }
+    Ok(loaded)
+}
+
+/// Render the connector document for a single endpoint.
+pub fn render_connector(
+    handlebars: &Handlebars,
+    endpoint: &EndpointInfo,
+    config: &Config,
+) -> Result<String, Box<dyn Error>> {
+    let template_name = select_template_for_endpoint(endpoint, config);
+    let connector_data = prepare_connector_data(endpoint, config);
+    let rendered = handlebars.render(template_name, &connector_data)?;
+
+    // A template that produces broken JSON must not leave a broken connector behind
+    let document = match serde_json::from_str::<Value>(&rendered) {
+        Ok(document) => document,
+        Err(err) => {
+            return Err(format!(
+                "Template {} rendered invalid JSON for {}:{}: {}",
+                template_name, endpoint.address, endpoint.port, err
+            )
+            .into())
+        }
+    };
+
+    // JSON output keeps the template's own layout
+    match OutputFormat::from_config(config)? {
+        OutputFormat::Json => Ok(rendered),
+        format => format.serialize(&document),
+    }
+}
+
 fn register_templates(handlebars: &mut Handlebars) -> Result<(), Box<dyn Error>> {
     // Embed templates in the binary for easier distribution
     // In a real-world scenario, you might want to load these from files
@@ -79,13 +354,14 @@ fn register_templates(handlebars: &mut Handlebars) -> Result<(), Box<dyn Error>>
     }
   },
   "polling_interval": {{polling_interval}},
+  "tools": {{{json tools}}},
   "transformations": [
     {
       "type": "jq",
       "expression": "{{default_transformation}}"
     }
   ],
-  "metadata": {{metadata}}
+  "metadata": {{{json metadata}}}
 }"#,
     )?;
 
@@ -114,7 +390,7 @@ fn register_templates(handlebars: &mut Handlebars) -> Result<(), Box<dyn Error>>
     "pattern": "{{log_pattern}}",
     "time_format": "{{time_format}}"
   },
-  "metadata": {{metadata}}
+  "metadata": {{{json metadata}}}
 }"#,
     )?;
 
@@ -142,7 +418,7 @@ fn register_templates(handlebars: &mut Handlebars) -> Result<(), Box<dyn Error>>
       "interval": {{polling_interval}}
     }
   ],
-  "metadata": {{metadata}}
+  "metadata": {{{json metadata}}}
 }"#,
     )?;
 
@@ -170,7 +446,7 @@ fn register_templates(handlebars: &mut Handlebars) -> Result<(), Box<dyn Error>>
     "type": "{{processing_type}}",
     "config": {}
   },
-  "metadata": {{metadata}}
+  "metadata": {{{json metadata}}}
 }"#,
     )?;
 
@@ -191,14 +467,23 @@ fn register_templates(handlebars: &mut Handlebars) -> Result<(), Box<dyn Error>>
     "type": "tcp_connection",
     "interval": {{polling_interval}}
   },
-  "metadata": {{metadata}}
+  "metadata": {{{json metadata}}}
 }"#,
     )?;
 
     Ok(())
 }
 
-fn select_template_for_endpoint(endpoint: &EndpointInfo) -> &'static str {
+fn select_template_for_endpoint<'a>(endpoint: &EndpointInfo, config: &'a Config) -> &'a str {
+    let rule = config
+        .connector
+        .template_rules
+        .iter()
+        .find(|rule| rule_matches(rule, endpoint));
+    if let Some(rule) = rule {
+        return &rule.template;
+    }
+
     match endpoint.endpoint_type {
         EndpointType::API => "api_connector",
         EndpointType::Log => "log_connector",
@@ -208,13 +493,31 @@ fn select_template_for_endpoint(endpoint: &EndpointInfo) -> &'static str {
     }
 }
 
+fn rule_matches(rule: &TemplateRule, endpoint: &EndpointInfo) -> bool {
+    let type_matches = rule.endpoint_type.as_ref().is_none_or(|wanted| {
+        let wanted = inventory::normalize_type_name(wanted);
+        wanted == endpoint.endpoint_type.name()
+            || wanted == endpoint.endpoint_type.label().to_lowercase()
+    });
+    let metadata_matches = rule.metadata.iter().all(|(key, wanted)| {
+        endpoint
+            .metadata
+            .get(key)
+            .is_some_and(|value| wanted == "*" || value.eq_ignore_ascii_case(wanted))
+    });
+    type_matches && metadata_matches
+}
+
 fn prepare_connector_data(endpoint: &EndpointInfo, config: &Config) -> Value {
-    let connector_id = Uuid::new_v4().to_string();
+    let connector_id = connector_id(endpoint).to_string();
     let host = &endpoint.address;
     let port = endpoint.port;
 
-    // Convert metadata to JSON
-    let metadata_json = json!(endpoint.metadata);
+    // Convert metadata to JSON, with the evidence behind the classification
+    let mut metadata_json = json!(endpoint.metadata);
+    metadata_json["confidence"] = json!(endpoint.confidence);
+    metadata_json["evidence"] = json!(endpoint.evidence);
+    metadata_json["needs_review"] = json!(needs_review(endpoint, config));
 
     // Base connector data common to all types
     let mut data = json!({
@@ -236,9 +539,16 @@ fn prepare_connector_data(endpoint: &EndpointInfo, config: &Config) -> Value {
             };
 
             data["protocol"] = json!(protocol_str);
-            data["base_path"] = json!("/");
+            data["base_path"] = json!(observed_base_path(endpoint));
             data["auth_type"] = json!("none");
             data["default_transformation"] = json!("."); // Identity transformation
+
+            // Per-route tools learned from HAR files and access logs
+            let tools = endpoint
+                .api_model
+                .as_ref()
+                .map_or_else(|| json!([]), api_learning::operations_as_tools);
+            data["tools"] = tools;
         }
 
         EndpointType::Log => {
@@ -305,10 +615,61 @@ fn prepare_connector_data(endpoint: &EndpointInfo, config: &Config) -> Value {
     data
 }
 
-fn generate_manifest(scan_results: &ScanResults, output_path: &Path) -> Result<(), Box<dyn Error>> {
+/// Derive the API base path from paths observed in passive captures,
+/// e.g. `/api/v1/users` and `/api/v1/orders` yield `/api/v1`.
+fn observed_base_path(endpoint: &EndpointInfo) -> String {
+    let paths = match endpoint.metadata.get("http_paths") {
+        Some(paths) => paths,
+        None => return "/".to_string(),
+    };
+
+    let mut prefix: Option<Vec<&str>> = None;
+    for path in paths.split(',') {
+        // The last segment is the resource itself, not part of the base path
+        let mut segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        segments.pop();
+
+        prefix = Some(match prefix {
+            None => segments,
+            Some(current) => current
+                .iter()
+                .zip(segments.iter())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| *a)
+                .collect(),
+        });
+    }
+
+    match prefix {
+        Some(segments) if !segments.is_empty() => format!("/{}", segments.join("/")),
+        _ => "/".to_string(),
+    }
+}
+
+/// Write the manifest listing every connector and return its file name.
+pub fn generate_manifest(
+    scan_results: &ScanResults,
+    output_path: &Path,
+    config: &Config,
+) -> Result<String, Box<dyn Error>> {
+    let format = OutputFormat::from_config(config)?;
+    let mut endpoints: Vec<&EndpointInfo> = scan_results.iter().collect();
+    sort_endpoints(&mut endpoints);
+    let connectors: Vec<Value> = endpoints
+        .iter()
+        .map(|endpoint| {
+            json!({
+                "connector_id": connector_id(endpoint).to_string(),
+                "file": connector_file_name(endpoint, format),
+                "type": endpoint.endpoint_type.name(),
+                "host": endpoint.address,
+                "port": endpoint.port,
+            })
+        })
+        .collect();
+
     let manifest = json!({
         "manifest_version": "1.0",
-        "generated_timestamp": chrono::Utc::now().to_rfc3339(),
         "connector_count": scan_results.len(),
         "endpoint_summary": {
             "api_count": scan_results.iter().filter(|e| matches!(e.endpoint_type, EndpointType::API)).count(),
@@ -316,11 +677,13 @@ fn generate_manifest(scan_results: &ScanResults, output_path: &Path) -> Result<(
             "database_count": scan_results.iter().filter(|e| matches!(e.endpoint_type, EndpointType::Database)).count(),
             "message_queue_count": scan_results.iter().filter(|e| matches!(e.endpoint_type, EndpointType::MessageQueue)).count(),
             "other_count": scan_results.iter().filter(|e| matches!(e.endpoint_type, EndpointType::Other(_))).count(),
-        }
+        },
+        "needs_review_count": scan_results.iter().filter(|e| needs_review(e, config)).count(),
+        "connectors": connectors
     });
 
-    let manifest_path = output_path.join("mcp_manifest.json");
-    fs::write(manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    let manifest_name = format!("mcp_manifest.{}", format.extension());
+    fs::write(output_path.join(&manifest_name), format.serialize(&manifest)?)?;
 
-    Ok(())
+    Ok(manifest_name)
 }
@@ -0,0 +1,442 @@
+use crate::config::{Config, NetworkEntry};
+use crate::connector_generator::{self, OutputFormat};
+use crate::diff;
+use crate::inventory::Inventory;
+use crate::merge;
+use crate::scanner::{self, Discovery, ScanResults};
+use crate::scope;
+use crate::signals;
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+// Name of the job running mDNS/UPnP discovery, which is not tied to a network
+const DISCOVERY_JOB: &str = "discovery";
+
+/// A network (or the discovery pass) rescanned on its own schedule.
+struct Job {
+    name: String,
+    networks: Vec<NetworkEntry>,
+    discovery: Discovery,
+    schedule: Schedule,
+    next_run: DateTime<Utc>,
+    status: JobStatus,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct JobStatus {
+    name: String,
+    schedule: String,
+    last_started: Option<String>,
+    last_finished: Option<String>,
+    last_duration_ms: Option<u64>,
+    last_endpoint_count: Option<usize>,
+    last_error: Option<String>,
+    next_run: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+struct ChangeSummary {
+    added: usize,
+    removed: usize,
+    changed: usize,
+    rewritten: usize,
+}
+
+/// Written to `daemon.status_file` after every pass.
+#[derive(Debug, Serialize)]
+struct DaemonStatus {
+    state: &'static str,
+    pid: u32,
+    started_at: String,
+    updated_at: String,
+    output_dir: String,
+    connector_count: usize,
+    last_changes: ChangeSummary,
+    jobs: Vec<JobStatus>,
+}
+
+/// Connector output kept in sync with the latest results of every job.
+struct OutputState {
+    output_path: PathBuf,
+    results_by_job: BTreeMap<String, ScanResults>,
+    current: ScanResults,
+    /// Written connectors keyed by file name, which is derived from the connector ID
+    files: HashMap<String, PathBuf>,
+    format: OutputFormat,
+}
+
+/// Run until SIGINT/SIGTERM, rescanning each network on its schedule and
+/// updating the connector directory incrementally.
+pub fn run(config: &Config, output_dir: &str) -> Result<(), Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(run_async(config, output_dir))
+}
+
+async fn run_async(config: &Config, output_dir: &str) -> Result<(), Box<dyn Error>> {
+    // Check the whole scope up front rather than failing on the first scheduled run
+    scope::enforce(
+        config,
+        &scanner::plan_networks(config, &config.scan.networks),
+    )?;
+
+    let handlebars = connector_generator::create_template_engine(config)?;
+    let format = OutputFormat::from_config(config)?;
+    let mut jobs = build_jobs(config)?;
+    let started_at = Utc::now();
+
+    let output_path = Path::new(output_dir).to_path_buf();
+    fs::create_dir_all(&output_path)?;
+    remove_stale_connectors(&output_path)?;
+
+    let mut output = OutputState {
+        output_path,
+        results_by_job: BTreeMap::new(),
+        current: Vec::new(),
+        files: HashMap::new(),
+        format,
+    };
+    let mut last_changes = ChangeSummary::default();
+
+    log::info!(
+        "Daemon started with {} scheduled jobs, writing connectors to {}",
+        jobs.len(),
+        output_dir
+    );
+
+    let shutdown = signals::shutdown_requested();
+    tokio::pin!(shutdown);
+
+    let mut stopping = false;
+    while !stopping {
+        let now = Utc::now();
+
+        for job in jobs.iter_mut().filter(|job| job.next_run <= now) {
+            let results = tokio::select! {
+                results = run_job(config, job) => results,
+                _ = &mut shutdown => {
+                    log::info!("Shutdown requested, abandoning scheduled scan");
+                    stopping = true;
+                    break;
+                }
+            };
+            if let Some(results) = results {
+                output.results_by_job.insert(job.name.clone(), results);
+            }
+
+            match sync_output(&mut output, &handlebars, config) {
+                Ok(changes) => last_changes = changes,
+                Err(err) => log::error!("Failed to update connectors: {}", err),
+            }
+
+            job.next_run = job
+                .schedule
+                .after(&Utc::now())
+                .next()
+                .unwrap_or_else(|| Utc::now() + chrono::Duration::hours(1));
+            job.status.next_run = Some(job.next_run.to_rfc3339());
+        }
+
+        if stopping {
+            break;
+        }
+
+        write_status(
+            config,
+            "running",
+            started_at,
+            output_dir,
+            &output,
+            &last_changes,
+            &jobs,
+        );
+
+        let next_run = jobs
+            .iter()
+            .map(|job| job.next_run)
+            .min()
+            .unwrap_or_else(|| Utc::now() + chrono::Duration::hours(1));
+        let wait = (next_run - Utc::now()).to_std().unwrap_or_default();
+        log::info!("Next scheduled scan at {}", next_run.to_rfc3339());
+
+        tokio::select! {
+            _ = tokio::time::sleep(wait) => {}
+            _ = &mut shutdown => {
+                log::info!("Shutdown requested, stopping daemon");
+                stopping = true;
+            }
+        }
+    }
+
+    write_status(
+        config,
+        "stopped",
+        started_at,
+        output_dir,
+        &output,
+        &last_changes,
+        &jobs,
+    );
+    Ok(())
+}
+
+/// One job per configured network plus one for mDNS/UPnP discovery when enabled.
+fn build_jobs(config: &Config) -> Result<Vec<Job>, Box<dyn Error>> {
+    let mut jobs = Vec::new();
+    let now = Utc::now();
+
+    for network in &config.scan.networks {
+        let expression = config
+            .daemon
+            .schedules
+            .get(network.cidr())
+            .unwrap_or(&config.daemon.default_schedule);
+        jobs.push(new_job(
+            network.cidr(),
+            vec![network.clone()],
+            Discovery::default(),
+            expression,
+            now,
+        )?);
+    }
+
+    // Enabled when any network's profile or overrides turn on mDNS or UPnP
+    let discovery = Discovery::for_networks(config, &config.scan.networks)?;
+    if discovery.any() {
+        let expression = config
+            .daemon
+            .discovery_schedule
+            .as_ref()
+            .unwrap_or(&config.daemon.default_schedule);
+        jobs.push(new_job(
+            DISCOVERY_JOB,
+            Vec::new(),
+            discovery,
+            expression,
+            now,
+        )?);
+    }
+
+    Ok(jobs)
+}
+
+fn new_job(
+    name: &str,
+    networks: Vec<NetworkEntry>,
+    discovery: Discovery,
+    expression: &str,
+    now: DateTime<Utc>,
+) -> Result<Job, Box<dyn Error>> {
+    let schedule = parse_schedule(expression)
+        .map_err(|err| format!("Invalid schedule '{}' for {}: {}", expression, name, err))?;
+
+    Ok(Job {
+        name: name.to_string(),
+        networks,
+        discovery,
+        schedule,
+        // Every job runs once at startup
+        next_run: now,
+        status: JobStatus {
+            name: name.to_string(),
+            schedule: expression.to_string(),
+            ..JobStatus::default()
+        },
+    })
+}
+
+/// Accept standard five-field cron expressions as well as the six/seven-field
+/// form with seconds and the `@hourly`-style shortcuts.
+pub fn parse_schedule(expression: &str) -> Result<Schedule, cron::error::Error> {
+    let expression = expression.trim();
+    if !expression.starts_with('@') && expression.split_whitespace().count() == 5 {
+        return Schedule::from_str(&format!("0 {}", expression));
+    }
+    Schedule::from_str(expression)
+}
+
+async fn run_job(config: &Config, job: &mut Job) -> Option<ScanResults> {
+    let started = Utc::now();
+    log::info!("Running scheduled scan '{}'", job.name);
+    job.status.last_started = Some(started.to_rfc3339());
+
+    let result = scanner::scan_networks(config, &job.networks, job.discovery)
+        .await
+        .map(merge::merge_endpoints);
+    let finished = Utc::now();
+    job.status.last_finished = Some(finished.to_rfc3339());
+    job.status.last_duration_ms = Some((finished - started).num_milliseconds().max(0) as u64);
+
+    match result {
+        Ok(results) => {
+            log::info!(
+                "Scheduled scan '{}' found {} endpoints",
+                job.name,
+                results.len()
+            );
+            job.status.last_endpoint_count = Some(results.len());
+            job.status.last_error = None;
+
+            if config.inventory.enabled {
+                let source = format!("daemon:{}", job.name);
+                if let Err(err) = Inventory::open(&config.inventory.path).and_then(|mut inv| {
+                    inv.record_run(&results, &source, started.timestamp() as u64)
+                }) {
+                    log::warn!("Failed to update inventory: {}", err);
+                }
+            }
+
+            Some(results)
+        }
+        Err(err) => {
+            // Keep the previous results so a transient failure does not remove connectors
+            log::error!("Scheduled scan '{}' failed: {}", job.name, err);
+            job.status.last_error = Some(err.to_string());
+            None
+        }
+    }
+}
+
+/// Write connectors whose rendered content differs from the file on disk, and
+/// remove connectors for endpoints that disappeared.
+fn sync_output(
+    output: &mut OutputState,
+    handlebars: &handlebars::Handlebars,
+    config: &Config,
+) -> Result<ChangeSummary, Box<dyn Error>> {
+    // Discovery jobs and network jobs can report the same service
+    let combined: ScanResults =
+        merge::merge_endpoints(output.results_by_job.values().flatten().cloned().collect())
+            .into_iter()
+            .filter(|endpoint| connector_generator::should_generate(endpoint, config))
+            .collect();
+    let changes = diff::diff_scans(&output.current, &combined);
+
+    let format = output.format;
+    for endpoint in &changes.removed {
+        let key = connector_generator::connector_file_name(endpoint, format);
+        if let Some(path) = output.files.remove(&key) {
+            log::info!(
+                "Removing connector for {}:{}",
+                endpoint.address,
+                endpoint.port
+            );
+            if let Err(err) = fs::remove_file(&path) {
+                log::warn!("Failed to remove {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    // Metadata such as confidence or needs_review does not count as a change
+    // in the diff, so every connector is rendered and compared with the file
+    // on disk instead.
+    let output_path = output.output_path.clone();
+    let mut rewritten = 0;
+    for endpoint in &combined {
+        let key = connector_generator::connector_file_name(endpoint, format);
+        let path = output
+            .files
+            .entry(key)
+            .or_insert_with_key(|name| output_path.join(name));
+        let rendered = connector_generator::render_connector(handlebars, endpoint, config)?;
+        if fs::read_to_string(&path).ok().as_deref() == Some(rendered.as_str()) {
+            continue;
+        }
+        fs::write(&path, rendered)?;
+        rewritten += 1;
+        log::debug!(
+            "Wrote connector for {}:{} to {}",
+            endpoint.address,
+            endpoint.port,
+            path.display()
+        );
+    }
+
+    if !changes.is_empty() || rewritten > 0 || output.current.is_empty() {
+        connector_generator::generate_manifest(&combined, &output.output_path, config)?;
+    }
+
+    if !changes.is_empty() || rewritten > 0 {
+        log::info!(
+            "Connectors updated: {} added, {} removed, {} changed, {} rewritten",
+            changes.added.len(),
+            changes.removed.len(),
+            changes.changed.len(),
+            rewritten
+        );
+    }
+
+    let summary = ChangeSummary {
+        added: changes.added.len(),
+        removed: changes.removed.len(),
+        changed: changes.changed.len(),
+        rewritten,
+    };
+    output.current = combined;
+    Ok(summary)
+}
+
+/// Connectors left by a previous run may be for endpoints that are gone, so
+/// the daemon starts from an empty directory and regenerates them on the first pass.
+fn remove_stale_connectors(output_path: &Path) -> Result<(), Box<dyn Error>> {
+    for entry in fs::read_dir(output_path)? {
+        let path = entry?.path();
+        let is_connector = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| {
+                name.starts_with("mcp_connector_") || name.starts_with("mcp_manifest.")
+            });
+        if is_connector {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_status(
+    config: &Config,
+    state: &'static str,
+    started_at: DateTime<Utc>,
+    output_dir: &str,
+    output: &OutputState,
+    last_changes: &ChangeSummary,
+    jobs: &[Job],
+) {
+    let status = DaemonStatus {
+        state,
+        pid: std::process::id(),
+        started_at: started_at.to_rfc3339(),
+        updated_at: Utc::now().to_rfc3339(),
+        output_dir: output_dir.to_string(),
+        connector_count: output.files.len(),
+        last_changes: last_changes.clone(),
+        jobs: jobs.iter().map(|job| job.status.clone()).collect(),
+    };
+
+    let written = serde_json::to_string_pretty(&status)
+        .map_err(|err| err.to_string())
+        .and_then(|json| {
+            fs::write(&config.daemon.status_file, json).map_err(|err| err.to_string())
+        });
+    if let Err(err) = written {
+        log::warn!(
+            "Failed to write daemon status to {}: {}",
+            config.daemon.status_file,
+            err
+        );
+    }
+}
+
+/// Print the status written by a running (or stopped) daemon.
+pub fn print_status(config: &Config) -> Result<(), Box<dyn Error>> {
+    let status = fs::read_to_string(&config.daemon.status_file)
+        .map_err(|err| format!("No daemon status at {}: {}", config.daemon.status_file, err))?;
+    println!("{}", status);
+    Ok(())
+}
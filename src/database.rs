@@ -0,0 +1,1186 @@
+use crate::config::{Config, DatabaseDefaults};
+use crate::mcp_tools;
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use postgres::types::{FromSql, Kind, ToSql, Type};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// SQL engines the query tools can talk to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Engine {
+    Postgres,
+    MySql,
+    Sqlite,
+}
+
+impl Engine {
+    pub fn label(self) -> &'static str {
+        match self {
+            Engine::Postgres => "PostgreSQL",
+            Engine::MySql => "MySQL",
+            Engine::Sqlite => "SQLite",
+        }
+    }
+
+    /// How the query tool's parameters are written in SQL.
+    pub fn placeholders(self) -> &'static str {
+        match self {
+            Engine::Postgres => "$1, $2, ...",
+            Engine::MySql | Engine::Sqlite => "?",
+        }
+    }
+}
+
+/// Where and how to reach the database behind a database connector.
+#[derive(Debug, Clone)]
+pub struct Database {
+    pub engine: Engine,
+    host: Option<String>,
+    port: Option<u16>,
+    /// Database name, or the file of a SQLite database
+    name: Option<String>,
+    authentication: Value,
+}
+
+/// Limits applied to every statement, from `[server]`.
+#[derive(Debug, Clone)]
+pub struct QueryLimits {
+    connect_timeout: Duration,
+    timeout: Duration,
+    max_rows: usize,
+    allowed_statements: Vec<String>,
+}
+
+impl QueryLimits {
+    pub fn new(config: &Config) -> Self {
+        QueryLimits {
+            connect_timeout: Duration::from_millis(config.scan.timeout_ms.max(1)),
+            timeout: Duration::from_millis(config.server.query_timeout_ms.max(1)),
+            max_rows: config.server.max_rows,
+            allowed_statements: config
+                .server
+                .allowed_statements
+                .iter()
+                .map(|keyword| keyword.to_lowercase())
+                .collect(),
+        }
+    }
+}
+
+/// What a database tool was asked to do.
+#[derive(Debug, Clone)]
+pub enum Request {
+    ListTables {
+        schema: Option<String>,
+    },
+    DescribeTable {
+        schema: Option<String>,
+        table: String,
+    },
+    Query {
+        sql: String,
+        params: Vec<Value>,
+        max_rows: Option<usize>,
+    },
+}
+
+/// Rows read from a result set, at most the row limit.
+struct Rows {
+    columns: Vec<String>,
+    rows: Vec<Vec<Value>>,
+    truncated: bool,
+}
+
+impl Database {
+    /// Read the `connection` section of a database connector.
+    pub fn from_connection(connection: &Value) -> Result<Self, Box<dyn Error>> {
+        let text = |key: &str| {
+            connection
+                .get(key)
+                .and_then(Value::as_str)
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+        };
+        let engine = match text("type").as_deref() {
+            Some("postgresql") | Some("postgres") => Engine::Postgres,
+            Some("mysql") | Some("mariadb") => Engine::MySql,
+            Some("sqlite") => Engine::Sqlite,
+            Some(other) => return Err(format!("unsupported database type '{}'", other).into()),
+            None => return Err("connection has no database type".into()),
+        };
+        let database = Database {
+            engine,
+            host: text("host"),
+            port: connection
+                .get("port")
+                .and_then(Value::as_u64)
+                .and_then(|port| u16::try_from(port).ok()),
+            name: text("database"),
+            authentication: connection
+                .get("authentication")
+                .cloned()
+                .unwrap_or(Value::Null),
+        };
+
+        match engine {
+            Engine::Sqlite if database.name.is_none() => {
+                Err("SQLite connection needs the database file".into())
+            }
+            Engine::Postgres | Engine::MySql
+                if database.host.is_none() || database.port.is_none() =>
+            {
+                Err("connection needs a host and port".into())
+            }
+            _ => Ok(database),
+        }
+    }
+
+    /// Fill in the database name and credentials the connector leaves out
+    /// from the `server.databases` entry for its `host:port`.
+    pub fn with_defaults(
+        &self,
+        defaults: &HashMap<String, DatabaseDefaults>,
+    ) -> Result<Database, String> {
+        let mut database = self.clone();
+        let defaults = match self.address().and_then(|address| defaults.get(&address)) {
+            Some(defaults) => defaults,
+            None => return Ok(database),
+        };
+
+        if database.name.is_none() {
+            database.name = defaults
+                .database
+                .as_deref()
+                .map(mcp_tools::resolve_env)
+                .transpose()?;
+        }
+        if let Some(username) = &defaults.username {
+            let authentication = &mut database.authentication;
+            if !authentication.is_object() {
+                *authentication = json!({});
+            }
+            if matches!(
+                authentication.get("type").and_then(Value::as_str),
+                None | Some("none")
+            ) {
+                authentication["type"] = json!("basic");
+            }
+            if !authentication.get("params").is_some_and(Value::is_object) {
+                authentication["params"] = json!({});
+            }
+            // Values are resolved like connector params, so `${NAME}` is
+            // read from the environment when connecting
+            let params = &mut authentication["params"];
+            if params.get("username").is_none() {
+                params["username"] = json!(username);
+                if let Some(password) = &defaults.password {
+                    params["password"] = json!(password);
+                }
+            }
+        }
+        Ok(database)
+    }
+
+    /// `host:port` of a database server.
+    fn address(&self) -> Option<String> {
+        match (&self.host, self.port) {
+            (Some(host), Some(port)) => Some(format!("{}:{}", host, port)),
+            _ => None,
+        }
+    }
+
+    /// Human readable location, e.g. `shop at 10.0.0.5:5432`.
+    pub fn location(&self) -> String {
+        let name = self.name.as_deref().unwrap_or("default database");
+        match (&self.host, self.port) {
+            (Some(host), Some(port)) if self.engine != Engine::Sqlite => {
+                format!("{} at {}:{}", name, host, port)
+            }
+            _ => name.to_string(),
+        }
+    }
+
+    /// User name and password from `basic` authentication.
+    fn credentials(&self) -> Result<(Option<String>, Option<String>), String> {
+        let kind = self
+            .authentication
+            .get("type")
+            .and_then(Value::as_str)
+            .unwrap_or("none");
+        match kind {
+            "none" => Ok((None, None)),
+            "basic" => Ok((
+                Some(
+                    mcp_tools::auth_param(&self.authentication, "username").map_err(|err| {
+                        format!(
+                            "{}; set it in the connector or in server.databases.\"{}\"",
+                            err,
+                            self.address().unwrap_or_default()
+                        )
+                    })?,
+                ),
+                match self.authentication.pointer("/params/password") {
+                    Some(_) => Some(mcp_tools::auth_param(&self.authentication, "password")?),
+                    None => None,
+                },
+            )),
+            other => Err(format!(
+                "Unsupported authentication type '{}' for databases",
+                other
+            )),
+        }
+    }
+}
+
+/// Run a request in a read-only transaction and return the rows as objects.
+/// Blocking database clients run on the blocking thread pool.
+pub async fn execute(
+    database: &Database,
+    request: Request,
+    limits: &QueryLimits,
+) -> Result<Value, String> {
+    let (sql, params, max_rows) = match request {
+        Request::Query {
+            sql,
+            params,
+            max_rows,
+        } => {
+            let sql = single_statement(&sql, &limits.allowed_statements)?.to_string();
+            let max_rows = max_rows.map_or(limits.max_rows, |rows| rows.min(limits.max_rows));
+            (sql, params, max_rows)
+        }
+        Request::ListTables { schema } => {
+            let (sql, params) = list_tables(database.engine, schema);
+            (sql.to_string(), params, limits.max_rows)
+        }
+        Request::DescribeTable { schema, table } => {
+            let (sql, params) = describe_table(database.engine, schema, table);
+            (sql.to_string(), params, limits.max_rows)
+        }
+    };
+    let credentials = database.credentials()?;
+
+    let job = {
+        let database = database.clone();
+        let limits = limits.clone();
+        tokio::task::spawn_blocking(move || match database.engine {
+            Engine::Postgres => {
+                fetch_postgres(&database, credentials, &sql, &params, max_rows, &limits)
+            }
+            Engine::MySql => fetch_mysql(&database, credentials, &sql, &params, max_rows, &limits),
+            Engine::Sqlite => fetch_sqlite(&database, &sql, &params, max_rows, &limits),
+        })
+    };
+    // The engines stop slow statements themselves; this catches a connection
+    // that hangs
+    let deadline = limits.connect_timeout + limits.timeout + Duration::from_secs(1);
+    let Rows {
+        columns,
+        rows,
+        truncated,
+    } = match tokio::time::timeout(deadline, job).await {
+        Ok(Ok(rows)) => rows?,
+        Ok(Err(err)) => return Err(format!("Query failed: {}", err)),
+        Err(_) => {
+            return Err(format!(
+                "{} did not answer within {} ms",
+                database.location(),
+                deadline.as_millis()
+            ))
+        }
+    };
+
+    let objects: Vec<Value> = rows
+        .into_iter()
+        .map(|row| {
+            let fields: Map<String, Value> = columns.iter().cloned().zip(row).collect();
+            Value::Object(fields)
+        })
+        .collect();
+    Ok(json!({
+        "columns": columns,
+        "row_count": objects.len(),
+        "truncated": truncated,
+        "rows": objects,
+    }))
+}
+
+/// Check that `sql` is one statement starting with an allowed keyword, without
+/// `INTO` (which writes files with MySQL's `INTO OUTFILE`/`DUMPFILE`, or new
+/// tables with PostgreSQL's `SELECT INTO`), and return it without a trailing
+/// semicolon.
+fn single_statement<'a>(sql: &'a str, allowed: &[String]) -> Result<&'a str, String> {
+    let bytes = sql.as_bytes();
+    let mut first_word = None;
+    let mut has_into = false;
+    let mut end = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i = sql[i + 2..]
+                    .find("*/")
+                    .map_or(bytes.len(), |close| i + close + 4);
+                continue;
+            }
+            quote @ (b'\'' | b'"' | b'`') => {
+                if end.is_some() {
+                    return Err("Only one statement can be run at a time".to_string());
+                }
+                i = sql[i + 1..]
+                    .find(quote as char)
+                    .map_or(bytes.len(), |close| i + close + 2);
+                continue;
+            }
+            b';' if end.is_none() => end = Some(i),
+            byte if byte.is_ascii_whitespace() || byte == b';' => {}
+            byte => {
+                if end.is_some() {
+                    return Err("Only one statement can be run at a time".to_string());
+                }
+                if byte.is_ascii_alphabetic() || byte == b'_' {
+                    let word_end = sql[i..]
+                        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+                        .map_or(sql.len(), |len| i + len);
+                    let word = sql[i..word_end].to_lowercase();
+                    has_into |= word == "into";
+                    if first_word.is_none() {
+                        first_word = Some(word);
+                    }
+                    i = word_end;
+                    continue;
+                }
+            }
+        }
+        i += 1;
+    }
+
+    match first_word {
+        Some(word) if allowed.contains(&word) && has_into => Err(format!(
+            "{} ... INTO is not allowed; it writes files or tables",
+            word.to_uppercase()
+        )),
+        Some(word) if allowed.contains(&word) => Ok(sql[..end.unwrap_or(sql.len())].trim()),
+        Some(word) => Err(format!(
+            "{} statements are not allowed; server.allowed_statements permits {}",
+            word.to_uppercase(),
+            allowed.join(", ")
+        )),
+        None => Err("Empty query".to_string()),
+    }
+}
+
+fn list_tables(engine: Engine, schema: Option<String>) -> (&'static str, Vec<Value>) {
+    let schema = json!(schema);
+    match engine {
+        Engine::Postgres => (
+            "SELECT table_schema::text AS schema, table_name::text AS name, \
+             table_type::text AS type FROM information_schema.tables \
+             WHERE table_schema NOT IN ('pg_catalog', 'information_schema') \
+             AND table_schema::text = COALESCE($1::text, table_schema::text) \
+             ORDER BY 1, 2",
+            vec![schema],
+        ),
+        Engine::MySql => (
+            "SELECT table_schema AS `schema`, table_name AS name, table_type AS type \
+             FROM information_schema.tables \
+             WHERE table_schema NOT IN ('mysql', 'information_schema', 'performance_schema', 'sys') \
+             AND table_schema = COALESCE(?, table_schema) ORDER BY 1, 2",
+            vec![schema],
+        ),
+        Engine::Sqlite => (
+            "SELECT schema, name, type FROM pragma_table_list \
+             WHERE name NOT LIKE 'sqlite_%' AND schema = COALESCE(?1, schema) \
+             ORDER BY 1, 2",
+            vec![schema],
+        ),
+    }
+}
+
+fn describe_table(
+    engine: Engine,
+    schema: Option<String>,
+    table: String,
+) -> (&'static str, Vec<Value>) {
+    let params = vec![json!(table), json!(schema)];
+    let sql = match engine {
+        Engine::Postgres => {
+            "SELECT c.column_name::text AS name, c.data_type::text AS type, \
+             c.is_nullable = 'YES' AS nullable, c.column_default::text AS default, \
+             EXISTS (SELECT 1 FROM information_schema.table_constraints t \
+             JOIN information_schema.key_column_usage k \
+             ON k.constraint_schema = t.constraint_schema AND k.constraint_name = t.constraint_name \
+             WHERE t.constraint_type = 'PRIMARY KEY' AND k.table_schema = c.table_schema \
+             AND k.table_name = c.table_name AND k.column_name = c.column_name) AS primary_key \
+             FROM information_schema.columns c \
+             WHERE c.table_name::text = $1::text \
+             AND c.table_schema::text = COALESCE($2::text, current_schema()) \
+             ORDER BY c.ordinal_position"
+        }
+        Engine::MySql => {
+            "SELECT column_name AS name, column_type AS type, is_nullable = 'YES' AS nullable, \
+             column_default AS `default`, column_key = 'PRI' AS primary_key \
+             FROM information_schema.columns \
+             WHERE table_name = ? AND table_schema = COALESCE(?, DATABASE()) \
+             ORDER BY ordinal_position"
+        }
+        Engine::Sqlite => {
+            "SELECT name, type, \"notnull\" = 0 AS nullable, dflt_value AS \"default\", \
+             pk > 0 AS primary_key FROM pragma_table_info(?1, ?2) ORDER BY cid"
+        }
+    };
+    (sql, params)
+}
+
+fn timed_out(limits: &QueryLimits) -> String {
+    format!(
+        "Query exceeded server.query_timeout_ms ({} ms)",
+        limits.timeout.as_millis()
+    )
+}
+
+fn fetch_postgres(
+    database: &Database,
+    (user, password): (Option<String>, Option<String>),
+    sql: &str,
+    params: &[Value],
+    max_rows: usize,
+    limits: &QueryLimits,
+) -> Result<Rows, String> {
+    let mut config = postgres::Config::new();
+    config
+        .host(database.host.as_deref().unwrap_or("localhost"))
+        .port(database.port.unwrap_or(5432))
+        .connect_timeout(limits.connect_timeout)
+        .application_name(env!("CARGO_PKG_NAME"));
+    if let Some(user) = &user {
+        config.user(user);
+    }
+    if let Some(password) = &password {
+        config.password(password);
+    }
+    if let Some(name) = &database.name {
+        config.dbname(name);
+    }
+    let pg_error = |err: postgres::Error| match err.as_db_error() {
+        Some(db) if db.code() == &postgres::error::SqlState::QUERY_CANCELED => timed_out(limits),
+        Some(db) => db.message().to_string(),
+        None => err.to_string(),
+    };
+
+    let mut client = config.connect(postgres::NoTls).map_err(|err| {
+        // The error itself only says that connecting failed
+        let cause = err
+            .source()
+            .map_or(String::new(), |cause| format!(": {}", cause));
+        format!("Cannot connect to {}{}", database.location(), cause)
+    })?;
+    let mut transaction = client
+        .build_transaction()
+        .read_only(true)
+        .start()
+        .map_err(pg_error)?;
+    transaction
+        .batch_execute(&format!(
+            "SET LOCAL statement_timeout = {}",
+            limits.timeout.as_millis()
+        ))
+        .map_err(pg_error)?;
+
+    let statement = transaction.prepare(sql).map_err(pg_error)?;
+    if statement.params().len() != params.len() {
+        return Err(format!(
+            "The query takes {} parameters but {} were given",
+            statement.params().len(),
+            params.len()
+        ));
+    }
+    let values = statement
+        .params()
+        .iter()
+        .zip(params)
+        .enumerate()
+        .map(|(index, (ty, value))| pg_param(value, ty, index + 1))
+        .collect::<Result<Vec<_>, String>>()?;
+    let columns: Vec<(String, Type)> = statement
+        .columns()
+        .iter()
+        .map(|column| (column.name().to_string(), column.type_().clone()))
+        .collect();
+
+    // A portal lets the server stop after one row more than the limit, which
+    // is enough to tell whether the result was cut off
+    let values: Vec<&(dyn ToSql + Sync)> = values.iter().map(|value| value.as_ref()).collect();
+    let portal = transaction.bind(&statement, &values).map_err(pg_error)?;
+    let fetch = i32::try_from(max_rows.saturating_add(1)).unwrap_or(i32::MAX);
+    let mut results = transaction.query_portal(&portal, fetch).map_err(pg_error)?;
+    let truncated = results.len() > max_rows;
+    results.truncate(max_rows);
+    let rows = results
+        .iter()
+        .map(|row| {
+            columns
+                .iter()
+                .enumerate()
+                .map(|(index, (_, ty))| pg_value(row, index, ty))
+                .collect()
+        })
+        .collect();
+    transaction.rollback().map_err(pg_error)?;
+
+    Ok(Rows {
+        columns: columns.into_iter().map(|(name, _)| name).collect(),
+        rows,
+        truncated,
+    })
+}
+
+/// Convert a JSON argument to the type PostgreSQL inferred for parameter `$n`.
+fn pg_param(value: &Value, ty: &Type, n: usize) -> Result<Box<dyn ToSql + Sync>, String> {
+    fn parse<T: FromStr>(text: Option<&str>) -> Result<Option<T>, ()> {
+        text.map(str::parse).transpose().map_err(|_| ())
+    }
+
+    let text = match value {
+        Value::Null => None,
+        Value::String(text) => Some(text.clone()),
+        other => Some(other.to_string()),
+    };
+    // Timestamps may be written with a space instead of the T
+    let timestamp = text.as_ref().map(|text| text.replacen(' ', "T", 1));
+    let text = text.as_deref();
+
+    let param: Result<Box<dyn ToSql + Sync>, ()> = match *ty {
+        Type::BOOL => parse::<bool>(text).map(|value| Box::new(value) as _),
+        Type::INT2 => parse::<i16>(text).map(|value| Box::new(value) as _),
+        Type::INT4 => parse::<i32>(text).map(|value| Box::new(value) as _),
+        Type::INT8 => parse::<i64>(text).map(|value| Box::new(value) as _),
+        Type::OID => parse::<u32>(text).map(|value| Box::new(value) as _),
+        Type::FLOAT4 => parse::<f32>(text).map(|value| Box::new(value) as _),
+        Type::FLOAT8 => parse::<f64>(text).map(|value| Box::new(value) as _),
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME | Type::UNKNOWN => {
+            Ok(Box::new(text.map(String::from)))
+        }
+        Type::JSON | Type::JSONB => Ok(Box::new(
+            Some(value.clone()).filter(|value| !value.is_null()),
+        )),
+        Type::UUID => parse::<uuid::Uuid>(text).map(|value| Box::new(value) as _),
+        Type::DATE => parse::<NaiveDate>(text).map(|value| Box::new(value) as _),
+        Type::TIME => parse::<NaiveTime>(text).map(|value| Box::new(value) as _),
+        Type::TIMESTAMP => {
+            parse::<NaiveDateTime>(timestamp.as_deref()).map(|value| Box::new(value) as _)
+        }
+        Type::TIMESTAMPTZ => {
+            parse::<DateTime<FixedOffset>>(timestamp.as_deref()).map(|value| Box::new(value) as _)
+        }
+        _ => {
+            return Err(format!(
+                "Parameter ${} has type {}, which cannot be bound; pass it as text and cast it \
+                 in the query, e.g. ${}::text::{}",
+                n,
+                ty.name(),
+                n,
+                ty.name()
+            ))
+        }
+    };
+    param.map_err(|_| format!("Parameter ${} is not a valid {}", n, ty.name()))
+}
+
+/// Undecoded column value, for types without a Rust mapping.
+struct Raw(Vec<u8>);
+
+impl<'a> FromSql<'a> for Raw {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        Ok(Raw(raw.to_vec()))
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+}
+
+fn pg_value(row: &postgres::Row, index: usize, ty: &Type) -> Value {
+    fn get<'a, T: FromSql<'a>>(row: &'a postgres::Row, index: usize) -> Option<T> {
+        row.try_get::<_, Option<T>>(index).ok().flatten()
+    }
+
+    match *ty {
+        Type::BOOL => json!(get::<bool>(row, index)),
+        Type::INT2 => json!(get::<i16>(row, index)),
+        Type::INT4 => json!(get::<i32>(row, index)),
+        Type::INT8 => json!(get::<i64>(row, index)),
+        Type::OID => json!(get::<u32>(row, index)),
+        Type::FLOAT4 => json!(get::<f32>(row, index)),
+        Type::FLOAT8 => json!(get::<f64>(row, index)),
+        Type::JSON | Type::JSONB => get::<Value>(row, index).unwrap_or(Value::Null),
+        Type::UUID => json!(get::<uuid::Uuid>(row, index).map(|id| id.to_string())),
+        Type::DATE => json!(get::<NaiveDate>(row, index).map(|date| date.to_string())),
+        Type::TIME => json!(get::<NaiveTime>(row, index).map(|time| time.to_string())),
+        Type::TIMESTAMP => json!(get::<NaiveDateTime>(row, index).map(|time| time.to_string())),
+        Type::TIMESTAMPTZ => json!(get::<DateTime<Utc>>(row, index).map(|time| time.to_rfc3339())),
+        Type::BYTEA => json!(get::<Vec<u8>>(row, index).map(|bytes| hex(&bytes))),
+        Type::BOOL_ARRAY => json!(get::<Vec<Option<bool>>>(row, index)),
+        Type::INT2_ARRAY => json!(get::<Vec<Option<i16>>>(row, index)),
+        Type::INT4_ARRAY => json!(get::<Vec<Option<i32>>>(row, index)),
+        Type::INT8_ARRAY => json!(get::<Vec<Option<i64>>>(row, index)),
+        Type::FLOAT8_ARRAY => json!(get::<Vec<Option<f64>>>(row, index)),
+        Type::TEXT_ARRAY | Type::VARCHAR_ARRAY | Type::NAME_ARRAY => {
+            json!(get::<Vec<Option<String>>>(row, index))
+        }
+        _ if <String as FromSql>::accepts(ty) => json!(get::<String>(row, index)),
+        _ => match get::<Raw>(row, index) {
+            None => Value::Null,
+            Some(Raw(raw)) if *ty == Type::NUMERIC => json!(decode_numeric(&raw)),
+            Some(Raw(raw)) if matches!(ty.kind(), Kind::Enum(_)) => {
+                json!(String::from_utf8_lossy(&raw))
+            }
+            Some(_) => json!(format!(
+                "<{} value; cast it to text in the query>",
+                ty.name()
+            )),
+        },
+    }
+}
+
+/// Format PostgreSQL's binary NUMERIC: base-10000 digits with a weight and
+/// display scale.
+fn decode_numeric(raw: &[u8]) -> Option<String> {
+    let word = |i: usize| {
+        raw.get(i * 2..i * 2 + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    };
+    let count = word(0)? as usize;
+    let weight = word(1)? as i16 as i32;
+    let sign = word(2)?;
+    let scale = word(3)? as usize;
+    match sign {
+        0xC000 => return Some("NaN".to_string()),
+        0xD000 => return Some("Infinity".to_string()),
+        0xF000 => return Some("-Infinity".to_string()),
+        _ => {}
+    }
+    let digits = (0..count)
+        .map(|i| word(4 + i))
+        .collect::<Option<Vec<u16>>>()?;
+    let digit = |i: i32| {
+        usize::try_from(i)
+            .ok()
+            .and_then(|i| digits.get(i))
+            .copied()
+            .unwrap_or(0)
+    };
+
+    let mut text = String::new();
+    if sign == 0x4000 {
+        text.push('-');
+    }
+    if weight < 0 {
+        text.push('0');
+    }
+    for i in 0..=weight {
+        if i == 0 {
+            text.push_str(&digit(i).to_string());
+        } else {
+            text.push_str(&format!("{:04}", digit(i)));
+        }
+    }
+    if scale > 0 {
+        let mut fraction = String::new();
+        let mut i = weight + 1;
+        while fraction.len() < scale {
+            fraction.push_str(&format!("{:04}", digit(i)));
+            i += 1;
+        }
+        fraction.truncate(scale);
+        text.push('.');
+        text.push_str(&fraction);
+    }
+    Some(text)
+}
+
+fn fetch_mysql(
+    database: &Database,
+    (user, password): (Option<String>, Option<String>),
+    sql: &str,
+    params: &[Value],
+    max_rows: usize,
+    limits: &QueryLimits,
+) -> Result<Rows, String> {
+    use mysql::prelude::Queryable;
+
+    let options = mysql::OptsBuilder::new()
+        .ip_or_hostname(database.host.clone())
+        .tcp_port(database.port.unwrap_or(3306))
+        .user(user)
+        .pass(password)
+        .db_name(database.name.clone())
+        .tcp_connect_timeout(Some(limits.connect_timeout))
+        // Backstop for servers that ignore the statement time limit
+        .read_timeout(Some(limits.timeout + Duration::from_secs(1)));
+    let mysql_error = |err: mysql::Error| match err {
+        mysql::Error::MySqlError(err) if err.code == 3024 || err.code == 1969 => timed_out(limits),
+        mysql::Error::MySqlError(err) => err.message,
+        mysql::Error::DriverError(err) => err.to_string(),
+        err => err.to_string(),
+    };
+
+    let mut connection = mysql::Conn::new(options).map_err(|err| {
+        format!(
+            "Cannot connect to {}: {}",
+            database.location(),
+            mysql_error(err)
+        )
+    })?;
+    // MySQL limits SELECTs in milliseconds, MariaDB every statement in seconds
+    let millis = limits.timeout.as_millis();
+    if connection
+        .query_drop(format!("SET SESSION max_execution_time = {}", millis))
+        .is_err()
+    {
+        let seconds = limits.timeout.as_secs_f64();
+        if let Err(err) =
+            connection.query_drop(format!("SET SESSION max_statement_time = {}", seconds))
+        {
+            log::debug!(
+                "{} has no statement time limit: {}",
+                database.location(),
+                err
+            );
+        }
+    }
+
+    // The server sends at most one row more than the limit, which is enough
+    // to tell whether the result was cut off
+    connection
+        .query_drop(format!(
+            "SET SESSION sql_select_limit = {}",
+            max_rows.saturating_add(1)
+        ))
+        .map_err(mysql_error)?;
+
+    let mut transaction = connection
+        .start_transaction(
+            mysql::TxOpts::default().set_access_mode(Some(mysql::AccessMode::ReadOnly)),
+        )
+        .map_err(mysql_error)?;
+    let values: Vec<mysql::Value> = params.iter().map(mysql_param).collect();
+    let values = if values.is_empty() {
+        mysql::Params::Empty
+    } else {
+        mysql::Params::Positional(values)
+    };
+
+    let mut rows = Vec::new();
+    let mut truncated = false;
+    let columns;
+    {
+        let mut results = transaction.exec_iter(sql, values).map_err(mysql_error)?;
+        columns = results
+            .columns()
+            .as_ref()
+            .iter()
+            .map(|column| column.name_str().into_owned())
+            .collect::<Vec<_>>();
+        for row in results.by_ref() {
+            let row = row.map_err(mysql_error)?;
+            if rows.len() == max_rows {
+                truncated = true;
+                break;
+            }
+            rows.push(row.unwrap().into_iter().map(mysql_value).collect());
+        }
+    }
+    transaction.rollback().map_err(mysql_error)?;
+
+    Ok(Rows {
+        columns,
+        rows,
+        truncated,
+    })
+}
+
+fn mysql_param(value: &Value) -> mysql::Value {
+    match value {
+        Value::Null => mysql::Value::NULL,
+        Value::Bool(flag) => mysql::Value::Int(*flag as i64),
+        Value::Number(number) => match (number.as_i64(), number.as_u64()) {
+            (Some(integer), _) => mysql::Value::Int(integer),
+            (None, Some(integer)) => mysql::Value::UInt(integer),
+            _ => mysql::Value::Double(number.as_f64().unwrap_or_default()),
+        },
+        Value::String(text) => mysql::Value::Bytes(text.clone().into_bytes()),
+        other => mysql::Value::Bytes(other.to_string().into_bytes()),
+    }
+}
+
+fn mysql_value(value: mysql::Value) -> Value {
+    match value {
+        mysql::Value::NULL => Value::Null,
+        mysql::Value::Bytes(bytes) => json!(String::from_utf8_lossy(&bytes)),
+        mysql::Value::Int(integer) => json!(integer),
+        mysql::Value::UInt(integer) => json!(integer),
+        mysql::Value::Float(float) => json!(float),
+        mysql::Value::Double(float) => json!(float),
+        mysql::Value::Date(year, month, day, hour, minute, second, micros) => {
+            let date = format!("{:04}-{:02}-{:02}", year, month, day);
+            if hour == 0 && minute == 0 && second == 0 && micros == 0 {
+                json!(date)
+            } else {
+                json!(format!(
+                    "{} {:02}:{:02}:{:02}.{:06}",
+                    date, hour, minute, second, micros
+                ))
+            }
+        }
+        mysql::Value::Time(negative, days, hours, minutes, seconds, micros) => json!(format!(
+            "{}{:02}:{:02}:{:02}.{:06}",
+            if negative { "-" } else { "" },
+            days * 24 + u32::from(hours),
+            minutes,
+            seconds,
+            micros
+        )),
+    }
+}
+
+fn fetch_sqlite(
+    database: &Database,
+    sql: &str,
+    params: &[Value],
+    max_rows: usize,
+    limits: &QueryLimits,
+) -> Result<Rows, String> {
+    use rusqlite::types::{Value as SqlValue, ValueRef};
+    use rusqlite::{ErrorCode, OpenFlags};
+
+    let path = database.name.as_deref().unwrap_or_default();
+    let connection = rusqlite::Connection::open_with_flags(
+        path,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .map_err(|err| format!("Cannot open {}: {}", path, err))?;
+    connection
+        .pragma_update(None, "query_only", true)
+        .map_err(|err| err.to_string())?;
+
+    // Interrupt the statement once the time limit has passed
+    let interrupt = connection.get_interrupt_handle();
+    let timeout = limits.timeout;
+    let (finished, wait) = mpsc::channel::<()>();
+    let timer = thread::spawn(move || {
+        if let Err(mpsc::RecvTimeoutError::Timeout) = wait.recv_timeout(timeout) {
+            interrupt.interrupt();
+        }
+    });
+
+    let values = params.iter().map(|value| match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(flag) => SqlValue::Integer(*flag as i64),
+        Value::Number(number) => match number.as_i64() {
+            Some(integer) => SqlValue::Integer(integer),
+            None => SqlValue::Real(number.as_f64().unwrap_or_default()),
+        },
+        Value::String(text) => SqlValue::Text(text.clone()),
+        other => SqlValue::Text(other.to_string()),
+    });
+    let result = (|| -> rusqlite::Result<Rows> {
+        let mut statement = connection.prepare(sql)?;
+        let columns: Vec<String> = statement
+            .column_names()
+            .into_iter()
+            .map(String::from)
+            .collect();
+        let mut results = statement.query(rusqlite::params_from_iter(values))?;
+        let mut rows = Vec::new();
+        let mut truncated = false;
+        while let Some(row) = results.next()? {
+            if rows.len() == max_rows {
+                truncated = true;
+                break;
+            }
+            let mut values = Vec::with_capacity(columns.len());
+            for index in 0..columns.len() {
+                values.push(match row.get_ref(index)? {
+                    ValueRef::Null => Value::Null,
+                    ValueRef::Integer(integer) => json!(integer),
+                    ValueRef::Real(float) => json!(float),
+                    ValueRef::Text(text) => json!(String::from_utf8_lossy(text)),
+                    ValueRef::Blob(bytes) => json!(hex(bytes)),
+                });
+            }
+            rows.push(values);
+        }
+        Ok(Rows {
+            columns,
+            rows,
+            truncated,
+        })
+    })();
+    drop(finished);
+    let _ = timer.join();
+
+    result.map_err(|err| match err.sqlite_error_code() {
+        Some(ErrorCode::OperationInterrupted) => timed_out(limits),
+        _ => err.to_string(),
+    })
+}
+
+/// Binary values as PostgreSQL prints them, e.g. `\x0a1b`.
+fn hex(bytes: &[u8]) -> String {
+    let digits: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("\\x{}", digits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed() -> Vec<String> {
+        vec!["select".to_string(), "with".to_string()]
+    }
+
+    fn limits(max_rows: usize) -> QueryLimits {
+        QueryLimits {
+            connect_timeout: Duration::from_secs(1),
+            timeout: Duration::from_secs(5),
+            max_rows,
+            allowed_statements: allowed(),
+        }
+    }
+
+    #[test]
+    fn single_statement_strips_trailing_semicolon() {
+        assert_eq!(
+            single_statement("  SELECT 1;  ", &allowed()),
+            Ok("SELECT 1")
+        );
+        assert_eq!(single_statement("select 1;;", &allowed()), Ok("select 1"));
+    }
+
+    #[test]
+    fn single_statement_skips_comments() {
+        assert_eq!(
+            single_statement("-- delete\n/* drop */ select 1; -- done", &allowed()),
+            Ok("-- delete\n/* drop */ select 1")
+        );
+        assert!(single_statement("/* select */ delete from t", &allowed()).is_err());
+        assert_eq!(
+            single_statement("-- only a comment", &allowed()),
+            Err("Empty query".to_string())
+        );
+    }
+
+    #[test]
+    fn single_statement_ignores_semicolons_in_quotes() {
+        assert_eq!(
+            single_statement("select ';', \"a;b\", `c;d` from t", &allowed()),
+            Ok("select ';', \"a;b\", `c;d` from t")
+        );
+        assert_eq!(
+            single_statement("select 'it''s; fine'", &allowed()),
+            Ok("select 'it''s; fine'")
+        );
+    }
+
+    #[test]
+    fn single_statement_rejects_multiple_statements() {
+        for sql in [
+            "select 1; delete from t",
+            "select 1; 'x'",
+            "select 1;/* */drop table t",
+        ] {
+            assert_eq!(
+                single_statement(sql, &allowed()),
+                Err("Only one statement can be run at a time".to_string()),
+                "{}",
+                sql
+            );
+        }
+    }
+
+    #[test]
+    fn single_statement_checks_first_keyword() {
+        assert!(single_statement("WITH x AS (select 1) select * from x", &allowed()).is_ok());
+        assert!(single_statement("(select 1)", &allowed()).is_ok());
+        let err = single_statement("update t set a = 1", &allowed()).unwrap_err();
+        assert!(
+            err.starts_with("UPDATE statements are not allowed"),
+            "{}",
+            err
+        );
+        assert_eq!(
+            single_statement("  ", &allowed()),
+            Err("Empty query".to_string())
+        );
+    }
+
+    #[test]
+    fn single_statement_rejects_into() {
+        for sql in [
+            "SELECT * FROM users INTO OUTFILE '/tmp/users.csv'",
+            "select load_file('/etc/passwd') into dumpfile '/var/www/x.php'",
+            "SELECT * INTO backup FROM users",
+            "with t as (select 1) select * into\nnew_table from t",
+        ] {
+            let err = single_statement(sql, &allowed()).unwrap_err();
+            assert!(err.contains("INTO is not allowed"), "{}: {}", sql, err);
+        }
+        // Only the keyword counts, not quoted text or longer identifiers
+        assert!(single_statement("select 'into outfile', \"into\" from t", &allowed()).is_ok());
+        assert!(single_statement("select into_date, pinto from t", &allowed()).is_ok());
+        let err = single_statement("insert into t values (1)", &allowed()).unwrap_err();
+        assert!(
+            err.starts_with("INSERT statements are not allowed"),
+            "{}",
+            err
+        );
+    }
+
+    fn sqlite_database() -> (tempfile::TempDir, Database) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("shop.db");
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE customers (id INTEGER PRIMARY KEY, name TEXT NOT NULL, \
+                 score REAL DEFAULT 0, photo BLOB); \
+                 INSERT INTO customers (name, score, photo) VALUES \
+                 ('ada', 1.5, x'0a1b'), ('grace', 2, NULL), ('linus', NULL, NULL); \
+                 CREATE VIEW top_customers AS SELECT * FROM customers WHERE score > 1;",
+            )
+            .unwrap();
+        let database = Database::from_connection(&json!({
+            "type": "sqlite",
+            "database": path.to_str().unwrap(),
+        }))
+        .unwrap();
+        (dir, database)
+    }
+
+    fn run(database: &Database, request: Request, limits: &QueryLimits) -> Result<Value, String> {
+        tokio::runtime::Runtime::new()
+            .unwrap()
+            .block_on(execute(database, request, limits))
+    }
+
+    #[test]
+    fn sqlite_lists_tables_and_views() {
+        let (_dir, database) = sqlite_database();
+        let result = run(
+            &database,
+            Request::ListTables { schema: None },
+            &limits(100),
+        )
+        .unwrap();
+        let names: Vec<&str> = result["rows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|row| row["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(names, ["customers", "top_customers"]);
+        assert_eq!(result["rows"][1]["type"], "view");
+    }
+
+    #[test]
+    fn sqlite_describes_columns() {
+        let (_dir, database) = sqlite_database();
+        let result = run(
+            &database,
+            Request::DescribeTable {
+                schema: None,
+                table: "customers".to_string(),
+            },
+            &limits(100),
+        )
+        .unwrap();
+        assert_eq!(result["row_count"], 4);
+        let id = &result["rows"][0];
+        assert_eq!(id["name"], "id");
+        assert_eq!(id["primary_key"], 1);
+        let name = &result["rows"][1];
+        assert_eq!(name["type"], "TEXT");
+        assert_eq!(name["nullable"], 0);
+        assert_eq!(result["rows"][2]["default"], "0");
+    }
+
+    #[test]
+    fn sqlite_queries_with_params_and_row_limit() {
+        let (_dir, database) = sqlite_database();
+        let query = |sql: &str, params: Vec<Value>, max_rows: Option<usize>| {
+            run(
+                &database,
+                Request::Query {
+                    sql: sql.to_string(),
+                    params,
+                    max_rows,
+                },
+                &limits(2),
+            )
+        };
+
+        let result = query(
+            "SELECT name, score, photo FROM customers WHERE id = ?",
+            vec![json!(1)],
+            None,
+        )
+        .unwrap();
+        assert_eq!(result["columns"], json!(["name", "score", "photo"]));
+        assert_eq!(
+            result["rows"],
+            json!([{ "name": "ada", "score": 1.5, "photo": "\\x0a1b" }])
+        );
+        assert_eq!(result["truncated"], false);
+
+        let result = query("SELECT id FROM customers ORDER BY id", Vec::new(), None).unwrap();
+        assert_eq!(result["row_count"], 2);
+        assert_eq!(result["truncated"], true);
+
+        let result = query("SELECT id FROM customers", Vec::new(), Some(10)).unwrap();
+        assert_eq!(result["row_count"], 2);
+
+        let result = query("SELECT id FROM customers", Vec::new(), Some(1)).unwrap();
+        assert_eq!(result["rows"], json!([{ "id": 1 }]));
+    }
+
+    #[test]
+    fn sqlite_refuses_writes() {
+        let (_dir, database) = sqlite_database();
+        let mut limits = limits(10);
+        limits.allowed_statements.push("delete".to_string());
+        let result = run(
+            &database,
+            Request::Query {
+                sql: "DELETE FROM customers".to_string(),
+                params: Vec::new(),
+                max_rows: None,
+            },
+            &limits,
+        );
+        assert!(result.is_err());
+
+        let result = run(
+            &database,
+            Request::Query {
+                sql: "SELECT count(*) AS n FROM customers".to_string(),
+                params: Vec::new(),
+                max_rows: None,
+            },
+            &limits,
+        )
+        .unwrap();
+        assert_eq!(result["rows"][0]["n"], 3);
+    }
+}
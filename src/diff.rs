@@ -0,0 +1,362 @@
+use crate::merge;
+use crate::scanner::{EndpointInfo, ScanResults};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+// Metadata that identifies what is running on an endpoint; everything else
+// (timestamps, flow counts, TTLs) changes between runs without meaning anything.
+// `auth_type` comes from request headers in HAR files and packet captures,
+// `spec_hash` from routes learned from traffic.
+const TRACKED_METADATA: [&str; 7] = [
+    "service_name",
+    "product",
+    "version",
+    "server_version",
+    "auth_type",
+    "spec_hash",
+    "tls_sni",
+];
+
+/// Differences between two sets of scan results.
+#[derive(Debug, Default, Serialize)]
+pub struct ScanDiff {
+    pub added: Vec<EndpointInfo>,
+    pub removed: Vec<EndpointInfo>,
+    pub changed: Vec<ChangedEndpoint>,
+    pub unchanged_count: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangedEndpoint {
+    pub address: String,
+    pub port: u16,
+    pub transport: String,
+    pub changes: Vec<FieldChange>,
+    #[serde(skip)]
+    pub current: EndpointInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub before: Option<String>,
+    pub after: Option<String>,
+}
+
+impl ScanDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compare two scans, matching endpoints by address, port and transport.
+pub fn diff_scans(old: &ScanResults, new: &ScanResults) -> ScanDiff {
+    let old_index = index_endpoints(old);
+    let new_index = index_endpoints(new);
+    let mut diff = ScanDiff::default();
+
+    for (key, endpoint) in &new_index {
+        match old_index.get(key) {
+            None => diff.added.push((*endpoint).clone()),
+            Some(previous) => {
+                let changes = compare_endpoints(previous, endpoint);
+                if changes.is_empty() {
+                    diff.unchanged_count += 1;
+                } else {
+                    diff.changed.push(ChangedEndpoint {
+                        address: endpoint.address.clone(),
+                        port: endpoint.port,
+                        transport: key.2.clone(),
+                        changes,
+                        current: (*endpoint).clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    for (key, endpoint) in &old_index {
+        if !new_index.contains_key(key) {
+            diff.removed.push((*endpoint).clone());
+        }
+    }
+
+    diff
+}
+
+/// Key endpoints by address, port and transport; the first record wins when
+/// a service was reported by several discovery methods.
+fn index_endpoints(results: &ScanResults) -> BTreeMap<(String, u16, String), &EndpointInfo> {
+    let mut index = BTreeMap::new();
+    for endpoint in results {
+        index
+            .entry((
+                endpoint.address.to_lowercase(),
+                endpoint.port,
+                merge::transport(endpoint),
+            ))
+            .or_insert(endpoint);
+    }
+    index
+}
+
+fn compare_endpoints(old: &EndpointInfo, new: &EndpointInfo) -> Vec<FieldChange> {
+    let mut changes = Vec::new();
+    let mut compare = |field: &str, before: Option<String>, after: Option<String>| {
+        if before != after {
+            changes.push(FieldChange {
+                field: field.to_string(),
+                before,
+                after,
+            });
+        }
+    };
+
+    compare(
+        "type",
+        Some(old.endpoint_type.label()),
+        Some(new.endpoint_type.label()),
+    );
+    compare(
+        "protocol",
+        Some(old.protocol.name()),
+        Some(new.protocol.name()),
+    );
+    for key in TRACKED_METADATA.iter() {
+        compare(
+            key,
+            old.metadata.get(*key).cloned(),
+            new.metadata.get(*key).cloned(),
+        );
+    }
+    compare("api_routes", api_routes(old), api_routes(new));
+
+    changes
+}
+
+fn api_routes(endpoint: &EndpointInfo) -> Option<String> {
+    let model = endpoint.api_model.as_ref()?;
+    let mut routes: Vec<String> = model
+        .operations
+        .iter()
+        .map(|operation| format!("{} {}", operation.method, operation.path_template))
+        .collect();
+    routes.sort();
+    Some(routes.join(", "))
+}
+
+fn describe(endpoint: &EndpointInfo) -> String {
+    format!(
+        "{}:{}/{} ({}, {})",
+        endpoint.address,
+        endpoint.port,
+        merge::transport(endpoint),
+        endpoint.endpoint_type.label(),
+        endpoint.protocol.name()
+    )
+}
+
+fn value_or_none(value: &Option<String>) -> &str {
+    value.as_deref().unwrap_or("<none>")
+}
+
+/// Human-readable report for terminals.
+pub fn format_text(diff: &ScanDiff) -> String {
+    if diff.is_empty() {
+        return format!(
+            "No changes ({} endpoints unchanged)\n",
+            diff.unchanged_count
+        );
+    }
+
+    let mut output = format!(
+        "{} new, {} disappeared, {} changed, {} unchanged\n",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len(),
+        diff.unchanged_count
+    );
+
+    for endpoint in &diff.added {
+        output.push_str(&format!("+ {}\n", describe(endpoint)));
+    }
+    for endpoint in &diff.removed {
+        output.push_str(&format!("- {}\n", describe(endpoint)));
+    }
+    for changed in &diff.changed {
+        output.push_str(&format!("~ {}\n", describe(&changed.current)));
+        for change in &changed.changes {
+            output.push_str(&format!(
+                "    {}: {} -> {}\n",
+                change.field,
+                value_or_none(&change.before),
+                value_or_none(&change.after)
+            ));
+        }
+    }
+
+    output
+}
+
+/// Markdown report suitable for pull requests and review tickets.
+pub fn format_markdown(diff: &ScanDiff) -> String {
+    let mut output = String::from("# Scan changes\n\n");
+    output.push_str(&format!(
+        "| New | Disappeared | Changed | Unchanged |\n|---|---|---|---|\n| {} | {} | {} | {} |\n",
+        diff.added.len(),
+        diff.removed.len(),
+        diff.changed.len(),
+        diff.unchanged_count
+    ));
+
+    let endpoint_table = |title: &str, endpoints: &[EndpointInfo]| {
+        if endpoints.is_empty() {
+            return String::new();
+        }
+        let mut section = format!(
+            "\n## {}\n\n| Address | Port | Type | Protocol |\n|---|---|---|---|\n",
+            title
+        );
+        for endpoint in endpoints {
+            section.push_str(&format!(
+                "| {} | {} | {} | {} |\n",
+                endpoint.address,
+                endpoint.port,
+                endpoint.endpoint_type.label(),
+                endpoint.protocol.name()
+            ));
+        }
+        section
+    };
+    output.push_str(&endpoint_table("New endpoints", &diff.added));
+    output.push_str(&endpoint_table("Disappeared endpoints", &diff.removed));
+
+    if !diff.changed.is_empty() {
+        output.push_str(
+            "\n## Changed endpoints\n\n| Endpoint | Field | Before | After |\n|---|---|---|---|\n",
+        );
+        for changed in &diff.changed {
+            for change in &changed.changes {
+                output.push_str(&format!(
+                    "| {}:{}/{} | {} | {} | {} |\n",
+                    changed.address,
+                    changed.port,
+                    changed.transport,
+                    change.field,
+                    markdown_cell(value_or_none(&change.before)),
+                    markdown_cell(value_or_none(&change.after))
+                ));
+            }
+        }
+    }
+
+    output
+}
+
+fn markdown_cell(value: &str) -> String {
+    value.replace('|', "\\|").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{EndpointType, Protocol};
+    use std::collections::HashMap;
+
+    fn endpoint(address: &str, port: u16, protocol: Protocol) -> EndpointInfo {
+        EndpointInfo {
+            endpoint_type: EndpointType::API,
+            address: address.to_string(),
+            port,
+            protocol,
+            metadata: HashMap::new(),
+            discovered_timestamp: 0,
+            api_model: None,
+            confidence: 0.5,
+            evidence: Vec::new(),
+        }
+    }
+
+    fn with_metadata(mut endpoint: EndpointInfo, key: &str, value: &str) -> EndpointInfo {
+        endpoint.metadata.insert(key.to_string(), value.to_string());
+        endpoint
+    }
+
+    #[test]
+    fn diff_scans_reports_added_removed_and_unchanged() {
+        let old = vec![
+            endpoint("10.0.0.1", 80, Protocol::HTTP),
+            endpoint("10.0.0.2", 443, Protocol::HTTPS),
+        ];
+        let new = vec![
+            endpoint("10.0.0.1", 80, Protocol::HTTP),
+            endpoint("10.0.0.3", 8080, Protocol::HTTP),
+        ];
+        let diff = diff_scans(&old, &new);
+
+        assert_eq!(diff.added.len(), 1);
+        assert_eq!(diff.added[0].address, "10.0.0.3");
+        assert_eq!(diff.removed.len(), 1);
+        assert_eq!(diff.removed[0].address, "10.0.0.2");
+        assert!(diff.changed.is_empty());
+        assert_eq!(diff.unchanged_count, 1);
+        assert!(!diff.is_empty());
+    }
+
+    #[test]
+    fn diff_scans_reports_tracked_changes_only() {
+        let old = vec![with_metadata(
+            with_metadata(endpoint("10.0.0.1", 80, Protocol::HTTP), "version", "1.24"),
+            "flow_count",
+            "3",
+        )];
+        let new = vec![with_metadata(
+            with_metadata(endpoint("10.0.0.1", 80, Protocol::HTTP), "version", "1.25"),
+            "flow_count",
+            "9",
+        )];
+        let diff = diff_scans(&old, &new);
+
+        assert_eq!(diff.changed.len(), 1);
+        let changed = &diff.changed[0];
+        assert_eq!(changed.transport, "tcp");
+        assert_eq!(changed.changes.len(), 1);
+        assert_eq!(changed.changes[0].field, "version");
+        assert_eq!(changed.changes[0].before.as_deref(), Some("1.24"));
+        assert_eq!(changed.changes[0].after.as_deref(), Some("1.25"));
+    }
+
+    #[test]
+    fn diff_scans_keeps_transports_apart() {
+        let old = vec![endpoint("10.0.0.1", 53, Protocol::TCP)];
+        let new = vec![
+            endpoint("10.0.0.1", 53, Protocol::TCP),
+            endpoint("10.0.0.1", 53, Protocol::UDP),
+        ];
+        let diff = diff_scans(&old, &new);
+
+        assert_eq!(diff.added.len(), 1);
+        assert!(matches!(diff.added[0].protocol, Protocol::UDP));
+        assert!(diff.changed.is_empty());
+        assert_eq!(diff.unchanged_count, 1);
+    }
+
+    #[test]
+    fn diff_scans_matches_addresses_case_insensitively() {
+        let old = vec![endpoint("API.example.com", 443, Protocol::HTTPS)];
+        let new = vec![endpoint("api.example.com", 443, Protocol::HTTPS)];
+        let diff = diff_scans(&old, &new);
+
+        assert!(diff.is_empty());
+        assert_eq!(diff.unchanged_count, 1);
+    }
+
+    #[test]
+    fn format_text_summarises_empty_diff() {
+        let scan = vec![endpoint("10.0.0.1", 80, Protocol::HTTP)];
+        assert_eq!(
+            format_text(&diff_scans(&scan, &scan)),
+            "No changes (1 endpoints unchanged)\n"
+        );
+    }
+}
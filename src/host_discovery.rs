@@ -0,0 +1,301 @@
+use crate::config::HostDiscoveryConfig;
+use crate::scanner::{self, ProbeOutcome};
+use crate::throttle::Throttle;
+use futures::stream::{self, StreamExt};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+// Kernel neighbour table; entries with the ATF_COM flag have a resolved MAC address
+const ARP_TABLE: &str = "/proc/net/arp";
+const ATF_COM: u32 = 0x2;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+
+/// Find which of `candidates` are alive, using the ARP table, ICMP echo and
+/// TCP pings in that order. Each method only checks hosts not yet found alive.
+pub async fn discover_live_hosts(
+    config: &HostDiscoveryConfig,
+    candidates: &[u32],
+    throttle: &Throttle,
+    concurrency: usize,
+    stop: &AtomicBool,
+) -> HashSet<u32> {
+    let candidate_set: HashSet<u32> = candidates.iter().copied().collect();
+    let mut alive = assumed_alive(config, &candidate_set);
+    let assumed = alive.len();
+
+    let mut from_arp = 0;
+    if config.use_arp {
+        from_arp = add_new(&mut alive, arp_neighbours(&candidate_set));
+    }
+
+    let timeout = Duration::from_millis(config.timeout_ms.max(1));
+    let mut from_icmp = 0;
+    if config.use_icmp && !stop.load(Ordering::SeqCst) {
+        let remaining = remaining(candidates, &alive);
+        match icmp_sweep(&remaining, throttle, timeout, stop).await {
+            Ok(replies) => from_icmp = add_new(&mut alive, replies),
+            Err(err) => log::debug!("ICMP echo not available, skipping: {}", err),
+        }
+    }
+
+    let mut from_tcp = 0;
+    if !config.tcp_ping_ports.is_empty() && !stop.load(Ordering::SeqCst) {
+        let remaining = remaining(candidates, &alive);
+        let answered = tcp_ping(
+            &remaining,
+            &config.tcp_ping_ports,
+            throttle,
+            timeout,
+            concurrency,
+            stop,
+        )
+        .await;
+        from_tcp = add_new(&mut alive, answered);
+    }
+
+    // Pinging makes the kernel resolve hosts on local segments, which catches
+    // hosts that answer ARP but drop ICMP and TCP
+    if config.use_arp {
+        from_arp += add_new(&mut alive, arp_neighbours(&candidate_set));
+    }
+
+    log::info!(
+        "Host discovery: {} of {} hosts alive (assumed: {}, arp: {}, icmp: {}, tcp: {})",
+        alive.len(),
+        candidates.len(),
+        assumed,
+        from_arp,
+        from_icmp,
+        from_tcp
+    );
+    alive
+}
+
+fn assumed_alive(config: &HostDiscoveryConfig, candidates: &HashSet<u32>) -> HashSet<u32> {
+    let mut alive = HashSet::new();
+    for entry in &config.assume_alive {
+        match scanner::parse_network(entry) {
+            Ok((first, last)) => alive.extend(
+                candidates
+                    .iter()
+                    .filter(|host| (first..=last).contains(*host)),
+            ),
+            Err(err) => log::warn!("Ignoring assume_alive entry {}: {}", entry, err),
+        }
+    }
+    alive
+}
+
+fn remaining(candidates: &[u32], alive: &HashSet<u32>) -> Vec<u32> {
+    candidates
+        .iter()
+        .copied()
+        .filter(|host| !alive.contains(host))
+        .collect()
+}
+
+fn add_new(alive: &mut HashSet<u32>, found: HashSet<u32>) -> usize {
+    let before = alive.len();
+    alive.extend(found);
+    alive.len() - before
+}
+
+/// Candidates with a resolved entry in the kernel's ARP table. Only available
+/// on Linux; elsewhere nothing is found.
+fn arp_neighbours(candidates: &HashSet<u32>) -> HashSet<u32> {
+    let table = match fs::read_to_string(ARP_TABLE) {
+        Ok(table) => table,
+        Err(_) => return HashSet::new(),
+    };
+
+    // IP address  HW type  Flags  HW address  Mask  Device
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let address: Ipv4Addr = fields.first()?.parse().ok()?;
+            let flags = u32::from_str_radix(fields.get(2)?.trim_start_matches("0x"), 16).ok()?;
+            if flags & ATF_COM == 0 {
+                return None;
+            }
+            Some(u32::from(address))
+        })
+        .filter(|host| candidates.contains(host))
+        .collect()
+}
+
+/// Open an ICMP socket: an unprivileged ping socket where the kernel allows it
+/// (`net.ipv4.ping_group_range`), otherwise a raw socket, which needs root.
+/// Returns the socket and whether replies include the IP header.
+fn open_icmp_socket() -> io::Result<(UdpSocket, bool)> {
+    let (socket, raw) = match Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::ICMPV4)) {
+        Ok(socket) => (socket, false),
+        Err(_) => (
+            Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?,
+            true,
+        ),
+    };
+    socket.set_nonblocking(true)?;
+    let socket = UdpSocket::from_std(socket.into())?;
+    Ok((socket, raw))
+}
+
+/// Send one echo request to every host and collect the addresses that reply
+/// before `timeout` expires after the last request.
+async fn icmp_sweep(
+    hosts: &[u32],
+    throttle: &Throttle,
+    timeout: Duration,
+    stop: &AtomicBool,
+) -> io::Result<HashSet<u32>> {
+    let mut replies = HashSet::new();
+    if hosts.is_empty() {
+        return Ok(replies);
+    }
+
+    let (socket, raw) = open_icmp_socket()?;
+    let identifier = std::process::id() as u16;
+
+    let sender = async {
+        for (sequence, host) in hosts.iter().enumerate() {
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+            throttle.pace().await;
+            let packet = echo_request(identifier, sequence as u16);
+            let target = SocketAddr::new(IpAddr::V4(Ipv4Addr::from(*host)), 0);
+            if let Err(err) = socket.send_to(&packet, target).await {
+                log::debug!("ICMP echo to {} failed: {}", target.ip(), err);
+            }
+        }
+        tokio::time::sleep(timeout).await;
+    };
+
+    tokio::select! {
+        _ = sender => {}
+        result = receive_replies(&socket, raw, &mut replies) => result?,
+    }
+
+    let candidates: HashSet<u32> = hosts.iter().copied().collect();
+    replies.retain(|host| candidates.contains(host));
+    Ok(replies)
+}
+
+async fn receive_replies(
+    socket: &UdpSocket,
+    raw: bool,
+    replies: &mut HashSet<u32>,
+) -> io::Result<()> {
+    let mut buffer = [0u8; 1500];
+    loop {
+        let (len, source) = socket.recv_from(&mut buffer).await?;
+        if let IpAddr::V4(address) = source.ip() {
+            if is_echo_reply(&buffer[..len], raw) {
+                replies.insert(u32::from(address));
+            }
+        }
+    }
+}
+
+fn echo_request(identifier: u16, sequence: u16) -> Vec<u8> {
+    let mut packet = vec![ICMP_ECHO_REQUEST, 0, 0, 0];
+    packet.extend_from_slice(&identifier.to_be_bytes());
+    packet.extend_from_slice(&sequence.to_be_bytes());
+    packet.extend_from_slice(b"dmcp-host-discovery");
+
+    let checksum = internet_checksum(&packet);
+    packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    packet
+}
+
+fn is_echo_reply(data: &[u8], includes_ip_header: bool) -> bool {
+    let icmp = if includes_ip_header {
+        let header_len = data.first().map(|byte| usize::from(byte & 0x0f) * 4);
+        match header_len {
+            Some(len) if data.len() > len => &data[len..],
+            _ => return false,
+        }
+    } else {
+        data
+    };
+    icmp.first() == Some(&ICMP_ECHO_REPLY)
+}
+
+fn internet_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|chunk| {
+            let high = u32::from(chunk[0]) << 8;
+            let low = chunk.get(1).map_or(0, |byte| u32::from(*byte));
+            high | low
+        })
+        .sum();
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Connect to a few common ports on each host. A refused connection means the
+/// host sent a reset, so it counts as alive just like an open port. Probes go
+/// through the host's connection slots like the port scan, and a host's
+/// remaining ports are skipped once one of them has answered.
+async fn tcp_ping(
+    hosts: &[u32],
+    ports: &[u16],
+    throttle: &Throttle,
+    timeout: Duration,
+    concurrency: usize,
+    stop: &AtomicBool,
+) -> HashSet<u32> {
+    let targets = hosts
+        .iter()
+        .flat_map(|host| ports.iter().map(move |port| (*host, *port)));
+    let alive = Mutex::new(HashSet::new());
+
+    {
+        let alive = &alive;
+        let mut pings = stream::iter(targets)
+            .map(|(host, port)| async move {
+                if alive.lock().unwrap().contains(&host) {
+                    return;
+                }
+                let _slot = throttle.acquire(host).await;
+                // Another port may have answered while this one waited for a slot
+                if alive.lock().unwrap().contains(&host) {
+                    return;
+                }
+
+                let outcome =
+                    scanner::scan_port(IpAddr::V4(Ipv4Addr::from(host)), port, timeout).await;
+                throttle.record(host, outcome);
+                if matches!(outcome, ProbeOutcome::Open | ProbeOutcome::Closed) {
+                    alive.lock().unwrap().insert(host);
+                }
+            })
+            .buffer_unordered(concurrency.max(1));
+
+        while pings.next().await.is_some() {
+            if stop.load(Ordering::SeqCst) {
+                break;
+            }
+        }
+    }
+
+    let alive = alive.into_inner().unwrap();
+    // Hosts that never answered are not port scanned, so their state is not needed
+    for host in hosts.iter().filter(|host| !alive.contains(host)) {
+        throttle.forget(*host);
+    }
+    alive
+}
@@ -0,0 +1,542 @@
+use crate::scanner::{
+    classify_port, EndpointInfo, EndpointType, Evidence, EvidenceKind, Protocol, ScanResults,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Formats of existing scan data that can be imported instead of scanning.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportFormat {
+    Nmap,
+    Masscan,
+}
+
+impl ImportFormat {
+    pub fn from_name(name: &str) -> Option<ImportFormat> {
+        match name.to_lowercase().as_str() {
+            "nmap" | "xml" => Some(ImportFormat::Nmap),
+            "masscan" | "json" => Some(ImportFormat::Masscan),
+            _ => None,
+        }
+    }
+
+    /// Guess the format from the file contents, falling back to the extension.
+    fn detect(path: &Path, contents: &str) -> Option<ImportFormat> {
+        let trimmed = contents.trim_start();
+        if trimmed.starts_with('<') {
+            return Some(ImportFormat::Nmap);
+        }
+        if trimmed.starts_with('[') || trimmed.starts_with('{') {
+            return Some(ImportFormat::Masscan);
+        }
+
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("xml") => Some(ImportFormat::Nmap),
+            Some("json") => Some(ImportFormat::Masscan),
+            _ => None,
+        }
+    }
+}
+
+/// Import endpoints from one or more scan output files.
+///
+/// When `format` is `None` the format of each file is detected from its contents.
+pub fn import_files<P: AsRef<Path>>(
+    paths: &[P],
+    format: Option<ImportFormat>,
+) -> Result<ScanResults, Box<dyn Error>> {
+    let mut results = Vec::new();
+
+    for path in paths {
+        let path = path.as_ref();
+        log::info!("Importing scan results from {}", path.display());
+
+        let contents = fs::read_to_string(path)?;
+        let file_format = match format.or_else(|| ImportFormat::detect(path, &contents)) {
+            Some(f) => f,
+            None => {
+                return Err(format!("Unable to detect scan format of {}", path.display()).into())
+            }
+        };
+
+        let mut endpoints = match file_format {
+            ImportFormat::Nmap => parse_nmap_xml(&contents)?,
+            ImportFormat::Masscan => parse_masscan_json(&contents)?,
+        };
+        log::info!(
+            "Imported {} endpoints from {}",
+            endpoints.len(),
+            path.display()
+        );
+        results.append(&mut endpoints);
+    }
+
+    Ok(results)
+}
+
+/// Parse nmap XML output (`-oX`), including `-sV` service detection and script output.
+pub fn parse_nmap_xml(contents: &str) -> Result<ScanResults, Box<dyn Error>> {
+    // nmap always writes `<!DOCTYPE nmaprun>`; roxmltree still guards against
+    // entity expansion attacks with DTDs allowed
+    let options = roxmltree::ParsingOptions {
+        allow_dtd: true,
+        ..roxmltree::ParsingOptions::default()
+    };
+    let document = roxmltree::Document::parse_with_options(contents, options)?;
+    let root = document.root_element();
+    if root.tag_name().name() != "nmaprun" {
+        return Err("Not an nmap XML report: missing <nmaprun> root element".into());
+    }
+
+    let scan_start = root
+        .attribute("start")
+        .and_then(|s| s.parse::<u64>().ok())
+        .unwrap_or_else(|| chrono::Utc::now().timestamp() as u64);
+
+    let mut results = Vec::new();
+
+    for host in root.children().filter(|n| n.has_tag_name("host")) {
+        let host_up = host
+            .children()
+            .find(|n| n.has_tag_name("status"))
+            .and_then(|n| n.attribute("state"))
+            .is_none_or(|state| state == "up");
+        if !host_up {
+            continue;
+        }
+
+        // Prefer IP addresses over MAC addresses
+        let address = host
+            .children()
+            .filter(|n| n.has_tag_name("address"))
+            .find(|n| matches!(n.attribute("addrtype"), Some("ipv4") | Some("ipv6")))
+            .and_then(|n| n.attribute("addr"));
+        let address = match address {
+            Some(addr) => addr.to_string(),
+            None => continue,
+        };
+
+        let hostname = host
+            .descendants()
+            .find(|n| n.has_tag_name("hostname"))
+            .and_then(|n| n.attribute("name"));
+
+        let timestamp = host
+            .attribute("starttime")
+            .and_then(|s| s.parse::<u64>().ok())
+            .unwrap_or(scan_start);
+
+        let ports = host
+            .children()
+            .filter(|n| n.has_tag_name("ports"))
+            .flat_map(|n| n.children())
+            .filter(|n| n.has_tag_name("port"));
+
+        for port_node in ports {
+            let port = match port_node
+                .attribute("portid")
+                .and_then(|p| p.parse::<u16>().ok())
+            {
+                Some(p) => p,
+                None => continue,
+            };
+
+            let state = port_node
+                .children()
+                .find(|n| n.has_tag_name("state"))
+                .and_then(|n| n.attribute("state"))
+                .unwrap_or("unknown");
+            if state != "open" {
+                continue;
+            }
+
+            let transport = port_node.attribute("protocol").unwrap_or("tcp");
+
+            let mut metadata = HashMap::new();
+            metadata.insert("detected_by".to_string(), "nmap_import".to_string());
+            metadata.insert("transport".to_string(), transport.to_string());
+            if let Some(name) = hostname {
+                metadata.insert("hostname".to_string(), name.to_string());
+            }
+
+            let service = port_node.children().find(|n| n.has_tag_name("service"));
+            let service_name = service.and_then(|n| n.attribute("name"));
+            let tunnel = service.and_then(|n| n.attribute("tunnel"));
+
+            if let Some(service) = service {
+                for (attr, key) in &[
+                    ("name", "service_name"),
+                    ("product", "product"),
+                    ("version", "version"),
+                    ("extrainfo", "extra_info"),
+                    ("ostype", "os_type"),
+                    ("tunnel", "tunnel"),
+                    ("method", "detection_method"),
+                    ("conf", "detection_confidence"),
+                ] {
+                    if let Some(value) = service.attribute(*attr) {
+                        metadata.insert(key.to_string(), value.to_string());
+                    }
+                }
+            }
+
+            for script in port_node.children().filter(|n| n.has_tag_name("script")) {
+                if let (Some(id), Some(output)) =
+                    (script.attribute("id"), script.attribute("output"))
+                {
+                    metadata.insert(format!("script.{}", id), output.trim().to_string());
+                }
+            }
+
+            let (protocol, endpoint_type) = classify_service(service_name, tunnel, transport, port);
+            let evidence = nmap_evidence(service, &endpoint_type, port);
+
+            let mut endpoint = EndpointInfo {
+                endpoint_type,
+                address: address.clone(),
+                port,
+                protocol,
+                metadata,
+                discovered_timestamp: timestamp,
+                api_model: None,
+                confidence: 0.0,
+                evidence: Vec::new(),
+            };
+            for item in evidence {
+                endpoint.add_evidence(item);
+            }
+            results.push(endpoint);
+        }
+    }
+
+    Ok(results)
+}
+
+/// Evidence behind nmap's service identification. Nmap rates its own
+/// confidence from 0 to 10: probe matches score high, while `table` results
+/// only look the port up in nmap's services list.
+fn nmap_evidence(
+    service: Option<roxmltree::Node>,
+    endpoint_type: &EndpointType,
+    port: u16,
+) -> Vec<Evidence> {
+    let service = match service.filter(|n| n.attribute("name").is_some()) {
+        Some(service) => service,
+        None => return vec![Evidence::from_port("nmap_import", port, endpoint_type)],
+    };
+
+    let name = service.attribute("name").unwrap_or_default();
+    let conf = service
+        .attribute("conf")
+        .and_then(|conf| conf.parse::<f64>().ok())
+        .unwrap_or(3.0);
+    let mut evidence = Vec::new();
+    if service.attribute("method") == Some("probed") {
+        let product = service
+            .attribute("product")
+            .map_or(String::new(), |product| format!(" ({})", product));
+        evidence.push(Evidence::new(
+            EvidenceKind::BannerMatch,
+            "nmap_import",
+            format!("nmap service probe matched {}{}", name, product),
+            (conf / 10.0).min(0.9),
+        ));
+    } else {
+        evidence.push(Evidence::new(
+            EvidenceKind::PortHeuristic,
+            "nmap_import",
+            format!("nmap services table lists port {} as {}", port, name),
+            (conf / 10.0).min(0.3),
+        ));
+    }
+    if service.attribute("tunnel") == Some("ssl") {
+        evidence.push(Evidence::new(
+            EvidenceKind::HandshakeSucceeded,
+            "nmap_import",
+            "nmap completed a TLS handshake".to_string(),
+            0.6,
+        ));
+    }
+    evidence
+}
+
+/// Parse masscan JSON output (`-oJ`).
+///
+/// Older masscan releases emit trailing commas and a non-JSON `{finished: 1}` record,
+/// so when the whole document does not parse each line is parsed on its own.
+pub fn parse_masscan_json(contents: &str) -> Result<ScanResults, Box<dyn Error>> {
+    let records: Vec<Value> = match serde_json::from_str::<Value>(contents) {
+        Ok(Value::Array(items)) => items,
+        Ok(other) => vec![other],
+        Err(_) => contents
+            .lines()
+            .map(|line| line.trim().trim_end_matches(','))
+            .filter(|line| line.starts_with('{'))
+            .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+            .collect(),
+    };
+
+    // Masscan reports one record per open port (plus extra records for banners),
+    // so collapse them per address, port and transport before building endpoints.
+    let mut endpoints: Vec<EndpointInfo> = Vec::new();
+    let mut index: HashMap<(String, u16, String), usize> = HashMap::new();
+
+    for record in &records {
+        let ip = match record.get("ip").and_then(Value::as_str) {
+            Some(ip) => ip,
+            None => continue,
+        };
+
+        let timestamp = match record.get("timestamp") {
+            Some(Value::String(s)) => s.parse::<u64>().ok(),
+            Some(Value::Number(n)) => n.as_u64(),
+            _ => None,
+        }
+        .unwrap_or_else(|| chrono::Utc::now().timestamp() as u64);
+
+        let ports = match record.get("ports").and_then(Value::as_array) {
+            Some(ports) => ports,
+            None => continue,
+        };
+
+        for entry in ports {
+            let port = match entry.get("port").and_then(Value::as_u64) {
+                Some(p) if p <= u64::from(u16::MAX) => p as u16,
+                _ => continue,
+            };
+            if let Some(status) = entry.get("status").and_then(Value::as_str) {
+                if status != "open" {
+                    continue;
+                }
+            }
+
+            let transport = entry.get("proto").and_then(Value::as_str).unwrap_or("tcp");
+            let key = (ip.to_string(), port, transport.to_lowercase());
+            let position = match index.get(&key) {
+                Some(position) => *position,
+                None => {
+                    let (protocol, endpoint_type) = classify_port(port);
+
+                    let mut metadata = HashMap::new();
+                    metadata.insert("detected_by".to_string(), "masscan_import".to_string());
+                    metadata.insert("transport".to_string(), transport.to_string());
+                    if let Some(ttl) = entry.get("ttl").and_then(Value::as_u64) {
+                        metadata.insert("ttl".to_string(), ttl.to_string());
+                    }
+
+                    let evidence = Evidence::from_port("masscan_import", port, &endpoint_type);
+                    let mut endpoint = EndpointInfo {
+                        endpoint_type,
+                        address: ip.to_string(),
+                        port,
+                        protocol,
+                        metadata,
+                        discovered_timestamp: timestamp,
+                        api_model: None,
+                        confidence: 0.0,
+                        evidence: Vec::new(),
+                    };
+                    endpoint.add_evidence(evidence);
+                    endpoints.push(endpoint);
+                    index.insert(key, endpoints.len() - 1);
+                    endpoints.len() - 1
+                }
+            };
+
+            // Banner records carry the detected service name and raw banner
+            if let Some(service) = entry.get("service") {
+                let endpoint = &mut endpoints[position];
+                if let Some(name) = service.get("name").and_then(Value::as_str) {
+                    let (protocol, endpoint_type) =
+                        classify_service(Some(name), None, transport, endpoint.port);
+                    // The port guess no longer supports a different classification
+                    if endpoint_type.label() != endpoint.endpoint_type.label() {
+                        endpoint.evidence.clear();
+                    }
+                    endpoint.protocol = protocol;
+                    endpoint.endpoint_type = endpoint_type;
+                    endpoint.add_evidence(Evidence::new(
+                        EvidenceKind::BannerMatch,
+                        "masscan_import",
+                        format!("masscan banner identified {}", name),
+                        0.8,
+                    ));
+                    endpoint
+                        .metadata
+                        .insert("service_name".to_string(), name.to_string());
+                    if let Some(banner) = service.get("banner").and_then(Value::as_str) {
+                        endpoint
+                            .metadata
+                            .insert(format!("banner.{}", name), banner.trim().to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(endpoints)
+}
+
+/// Classify an endpoint from a service name reported by a scanner,
+/// falling back to port heuristics when the name is unknown.
+fn classify_service(
+    service_name: Option<&str>,
+    tunnel: Option<&str>,
+    transport: &str,
+    port: u16,
+) -> (Protocol, EndpointType) {
+    let (port_protocol, port_type) = classify_port(port);
+    let tls = tunnel == Some("ssl");
+
+    let name = match service_name {
+        Some(name) => name.to_lowercase(),
+        None => return (port_protocol, port_type),
+    };
+
+    match name.as_str() {
+        "http" | "http-proxy" | "http-alt" | "http-mgmt" | "sun-answerbook" => {
+            if tls {
+                (Protocol::HTTPS, EndpointType::API)
+            } else {
+                (Protocol::HTTP, EndpointType::API)
+            }
+        }
+        "https" | "https-alt" | "ssl/http" => (Protocol::HTTPS, EndpointType::API),
+        "mysql" | "postgresql" | "oracle" | "oracle-tns" | "mongodb" | "mongod" | "ms-sql-s"
+        | "redis" | "cassandra" | "couchdb" | "elasticsearch" => {
+            (Protocol::TCP, EndpointType::Database)
+        }
+        "mqtt" | "secure-mqtt" => (Protocol::MQTT, EndpointType::MessageQueue),
+        "amqp" | "amqps" => (Protocol::AMQP, EndpointType::MessageQueue),
+        "kafka" | "nats" | "stomp" => (Protocol::TCP, EndpointType::MessageQueue),
+        "syslog" | "syslog-conn" | "rsyslog" => {
+            let protocol = if transport == "udp" {
+                Protocol::UDP
+            } else {
+                Protocol::TCP
+            };
+            (protocol, EndpointType::Log)
+        }
+        _ => match port_type {
+            // Keep the scanner's service name instead of a generic "Unknown"
+            EndpointType::Other(_) => (port_protocol, EndpointType::Other(name)),
+            known => (port_protocol, known),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NMAP_SAMPLE: &str = include_str!("../tests/fixtures/nmap_sample.xml");
+    const MASSCAN_SAMPLE: &str = include_str!("../tests/fixtures/masscan_sample.json");
+
+    fn find<'a>(
+        results: &'a [EndpointInfo],
+        address: &str,
+        port: u16,
+        transport: &str,
+    ) -> &'a EndpointInfo {
+        results
+            .iter()
+            .find(|endpoint| {
+                endpoint.address == address
+                    && endpoint.port == port
+                    && endpoint.metadata.get("transport").map(String::as_str) == Some(transport)
+            })
+            .unwrap_or_else(|| panic!("no {}/{} on {}:{}", transport, port, address, port))
+    }
+
+    #[test]
+    fn nmap_keeps_open_ports_of_hosts_that_are_up() {
+        let results = parse_nmap_xml(NMAP_SAMPLE).unwrap();
+        let mut found: Vec<(&str, u16)> = results
+            .iter()
+            .map(|endpoint| (endpoint.address.as_str(), endpoint.port))
+            .collect();
+        found.sort_unstable();
+        assert_eq!(
+            found,
+            [
+                ("10.0.0.1", 443),
+                ("10.0.0.1", 514),
+                ("10.0.0.1", 5432),
+                ("10.0.0.3", 1883)
+            ]
+        );
+    }
+
+    #[test]
+    fn nmap_reads_service_details() {
+        let results = parse_nmap_xml(NMAP_SAMPLE).unwrap();
+
+        let web = find(&results, "10.0.0.1", 443, "tcp");
+        assert_eq!(web.protocol.name(), "https");
+        assert_eq!(web.endpoint_type.name(), "api");
+        assert_eq!(web.discovered_timestamp, 1_700_000_010);
+        assert_eq!(web.metadata["product"], "nginx");
+        assert_eq!(web.metadata["version"], "1.25.3");
+        assert_eq!(web.metadata["hostname"], "web.lan");
+        assert_eq!(web.metadata["script.http-title"], "Dashboard");
+        assert!(web
+            .evidence
+            .iter()
+            .any(|evidence| evidence.kind == EvidenceKind::BannerMatch));
+        assert!(web
+            .evidence
+            .iter()
+            .any(|evidence| evidence.kind == EvidenceKind::HandshakeSucceeded));
+
+        let database = find(&results, "10.0.0.1", 5432, "tcp");
+        assert_eq!(database.endpoint_type.name(), "database");
+        assert_eq!(database.evidence[0].kind, EvidenceKind::PortHeuristic);
+
+        let syslog = find(&results, "10.0.0.1", 514, "udp");
+        assert_eq!(syslog.protocol.name(), "udp");
+        assert_eq!(syslog.endpoint_type.name(), "log");
+
+        // Without service detection the port decides, with the scan start as timestamp
+        let mqtt = find(&results, "10.0.0.3", 1883, "tcp");
+        assert_eq!(mqtt.endpoint_type.name(), "message_queue");
+        assert_eq!(mqtt.discovered_timestamp, 1_700_000_000);
+    }
+
+    #[test]
+    fn nmap_rejects_other_documents() {
+        assert!(parse_nmap_xml("<scan/>").is_err());
+        assert!(parse_nmap_xml("not xml").is_err());
+    }
+
+    #[test]
+    fn masscan_collapses_records_per_address_port_and_transport() {
+        let results = parse_masscan_json(MASSCAN_SAMPLE).unwrap();
+        assert_eq!(results.len(), 3);
+
+        let web = find(&results, "10.0.0.5", 8080, "tcp");
+        assert_eq!(web.protocol.name(), "http");
+        assert_eq!(web.endpoint_type.name(), "api");
+        assert_eq!(web.discovered_timestamp, 1_700_000_100);
+        assert_eq!(web.metadata["service_name"], "http");
+        assert_eq!(web.metadata["ttl"], "64");
+        assert!(web.metadata["banner.http"].contains("jetty"));
+
+        let syslog_udp = find(&results, "10.0.0.5", 514, "udp");
+        assert_eq!(syslog_udp.protocol.name(), "udp");
+        assert_eq!(syslog_udp.endpoint_type.name(), "log");
+        assert_eq!(syslog_udp.metadata["banner.syslog"], "<34>1 test");
+
+        let syslog_tcp = find(&results, "10.0.0.5", 514, "tcp");
+        assert!(!syslog_tcp.metadata.contains_key("service_name"));
+    }
+
+    #[test]
+    fn masscan_reads_line_records() {
+        let lines = MASSCAN_SAMPLE.replace("[\n", "").replace("\n]", "");
+        assert!(serde_json::from_str::<Value>(&lines).is_err());
+        assert_eq!(parse_masscan_json(&lines).unwrap().len(), 3);
+    }
+}
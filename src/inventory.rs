@@ -0,0 +1,427 @@
+use crate::scanner::{EndpointInfo, ScanResults};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+use uuid::Uuid;
+
+const SCHEMA: &str = r#"
+CREATE TABLE IF NOT EXISTS scan_runs (
+    id TEXT PRIMARY KEY,
+    source TEXT NOT NULL,
+    started_at INTEGER NOT NULL,
+    finished_at INTEGER NOT NULL,
+    endpoint_count INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS endpoints (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    address TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    protocol TEXT NOT NULL,
+    type_name TEXT NOT NULL,
+    endpoint_json TEXT NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    first_run_id TEXT NOT NULL REFERENCES scan_runs(id),
+    last_run_id TEXT NOT NULL REFERENCES scan_runs(id),
+    UNIQUE (address, port, protocol)
+);
+
+CREATE TABLE IF NOT EXISTS sightings (
+    endpoint_id INTEGER NOT NULL REFERENCES endpoints(id),
+    run_id TEXT NOT NULL REFERENCES scan_runs(id),
+    seen_at INTEGER NOT NULL,
+    PRIMARY KEY (endpoint_id, run_id)
+);
+
+CREATE TABLE IF NOT EXISTS endpoint_changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    endpoint_id INTEGER NOT NULL REFERENCES endpoints(id),
+    run_id TEXT NOT NULL REFERENCES scan_runs(id),
+    changed_at INTEGER NOT NULL,
+    previous_json TEXT NOT NULL,
+    current_json TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS endpoints_last_seen ON endpoints(last_seen);
+CREATE INDEX IF NOT EXISTS endpoints_type_name ON endpoints(type_name);
+"#;
+
+/// Local system of record for every endpoint ever discovered.
+pub struct Inventory {
+    connection: Connection,
+}
+
+/// An endpoint as stored in the inventory.
+#[derive(Debug, Clone, Serialize)]
+pub struct InventoryEntry {
+    pub endpoint: EndpointInfo,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub first_run_id: String,
+    pub last_run_id: String,
+    pub sightings: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanRun {
+    pub id: String,
+    pub source: String,
+    pub started_at: u64,
+    pub finished_at: u64,
+    pub endpoint_count: u64,
+}
+
+/// A recorded change of an endpoint's classification or metadata.
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointChange {
+    pub run_id: String,
+    pub changed_at: u64,
+    pub previous: EndpointInfo,
+    pub current: EndpointInfo,
+}
+
+#[derive(Debug, Default)]
+pub struct InventoryFilter {
+    pub endpoint_type: Option<String>,
+    pub since: Option<u64>,
+    pub address: Option<String>,
+    pub port: Option<u16>,
+}
+
+impl Inventory {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Inventory, Box<dyn Error>> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(Inventory { connection })
+    }
+
+    /// Record a completed scan run, updating first/last-seen times and change history.
+    pub fn record_run(
+        &mut self,
+        results: &ScanResults,
+        source: &str,
+        started_at: u64,
+    ) -> Result<String, Box<dyn Error>> {
+        let run_id = Uuid::new_v4().to_string();
+        let now = chrono::Utc::now().timestamp() as u64;
+        let tx = self.connection.transaction()?;
+
+        tx.execute(
+            "INSERT INTO scan_runs (id, source, started_at, finished_at, endpoint_count)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                run_id,
+                source,
+                started_at as i64,
+                now as i64,
+                results.len() as i64
+            ],
+        )?;
+
+        for endpoint in results {
+            let protocol = endpoint.protocol.name();
+            let endpoint_json = stable_json(endpoint)?;
+            // Imported results keep the time they were originally observed
+            let seen_at = if endpoint.discovered_timestamp > 0 {
+                endpoint.discovered_timestamp as i64
+            } else {
+                now as i64
+            };
+
+            let existing: Option<(i64, String)> = tx
+                .query_row(
+                    "SELECT id, endpoint_json FROM endpoints
+                     WHERE address = ?1 AND port = ?2 AND protocol = ?3",
+                    params![endpoint.address, endpoint.port, protocol],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .optional()?;
+
+            let endpoint_id = match existing {
+                Some((id, previous_json)) => {
+                    if previous_json != endpoint_json {
+                        tx.execute(
+                            "INSERT INTO endpoint_changes
+                                (endpoint_id, run_id, changed_at, previous_json, current_json)
+                             VALUES (?1, ?2, ?3, ?4, ?5)",
+                            params![id, run_id, seen_at, previous_json, endpoint_json],
+                        )?;
+                    }
+                    tx.execute(
+                        "UPDATE endpoints
+                         SET type_name = ?1, endpoint_json = ?2, last_seen = MAX(last_seen, ?3), last_run_id = ?4
+                         WHERE id = ?5",
+                        params![
+                            endpoint.endpoint_type.name(),
+                            endpoint_json,
+                            seen_at,
+                            run_id,
+                            id
+                        ],
+                    )?;
+                    id
+                }
+                None => {
+                    tx.execute(
+                        "INSERT INTO endpoints
+                            (address, port, protocol, type_name, endpoint_json,
+                             first_seen, last_seen, first_run_id, last_run_id)
+                         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6, ?7, ?7)",
+                        params![
+                            endpoint.address,
+                            endpoint.port,
+                            protocol,
+                            endpoint.endpoint_type.name(),
+                            endpoint_json,
+                            seen_at,
+                            run_id
+                        ],
+                    )?;
+                    tx.last_insert_rowid()
+                }
+            };
+
+            tx.execute(
+                "INSERT OR IGNORE INTO sightings (endpoint_id, run_id, seen_at) VALUES (?1, ?2, ?3)",
+                params![endpoint_id, run_id, seen_at],
+            )?;
+        }
+
+        tx.commit()?;
+        Ok(run_id)
+    }
+
+    pub fn list(&self, filter: &InventoryFilter) -> Result<Vec<InventoryEntry>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare(
+            "SELECT e.endpoint_json, e.first_seen, e.last_seen, e.first_run_id, e.last_run_id,
+                    (SELECT COUNT(*) FROM sightings s WHERE s.endpoint_id = e.id)
+             FROM endpoints e
+             WHERE (?1 IS NULL OR e.type_name = ?1)
+               AND (?2 IS NULL OR e.last_seen >= ?2)
+               AND (?3 IS NULL OR e.address = ?3)
+               AND (?4 IS NULL OR e.port = ?4)
+             ORDER BY e.address, e.port, e.protocol",
+        )?;
+
+        let rows = statement.query_map(
+            params![
+                filter.endpoint_type,
+                filter.since.map(|since| since as i64),
+                filter.address,
+                filter.port
+            ],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, i64>(5)?,
+                ))
+            },
+        )?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            let (json, first_seen, last_seen, first_run_id, last_run_id, sightings) = row?;
+            entries.push(InventoryEntry {
+                endpoint: serde_json::from_str(&json)?,
+                first_seen: first_seen as u64,
+                last_seen: last_seen as u64,
+                first_run_id,
+                last_run_id,
+                sightings: sightings as u64,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    pub fn runs(&self, limit: usize) -> Result<Vec<ScanRun>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare(
+            "SELECT id, source, started_at, finished_at, endpoint_count
+             FROM scan_runs ORDER BY finished_at DESC, rowid DESC LIMIT ?1",
+        )?;
+        let rows = statement.query_map(params![limit as i64], |row| {
+            Ok(ScanRun {
+                id: row.get(0)?,
+                source: row.get(1)?,
+                started_at: row.get::<_, i64>(2)? as u64,
+                finished_at: row.get::<_, i64>(3)? as u64,
+                endpoint_count: row.get::<_, i64>(4)? as u64,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Metadata and classification changes recorded for one endpoint.
+    pub fn history(&self, address: &str, port: u16) -> Result<Vec<EndpointChange>, Box<dyn Error>> {
+        let mut statement = self.connection.prepare(
+            "SELECT c.run_id, c.changed_at, c.previous_json, c.current_json
+             FROM endpoint_changes c JOIN endpoints e ON e.id = c.endpoint_id
+             WHERE e.address = ?1 AND e.port = ?2
+             ORDER BY c.changed_at, c.id",
+        )?;
+        let rows = statement.query_map(params![address, port], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+
+        let mut changes = Vec::new();
+        for row in rows {
+            let (run_id, changed_at, previous, current) = row?;
+            changes.push(EndpointChange {
+                run_id,
+                changed_at: changed_at as u64,
+                previous: serde_json::from_str(&previous)?,
+                current: serde_json::from_str(&current)?,
+            });
+        }
+
+        Ok(changes)
+    }
+}
+
+/// Serialize an endpoint with sorted metadata and without its discovery time,
+/// so unchanged endpoints always produce identical JSON.
+fn stable_json(endpoint: &EndpointInfo) -> Result<String, Box<dyn Error>> {
+    let mut value = serde_json::to_value(endpoint)?;
+    if let Some(object) = value.as_object_mut() {
+        object.insert("discovered_timestamp".to_string(), serde_json::json!(0));
+        let metadata: BTreeMap<&String, &String> = endpoint.metadata.iter().collect();
+        object.insert("metadata".to_string(), serde_json::to_value(metadata)?);
+    }
+    Ok(serde_json::to_string(&value)?)
+}
+
+/// Parse a relative age such as `7d`, `12h`, `30m` or `2w`, or an RFC 3339 date,
+/// into a Unix timestamp.
+pub fn parse_since(value: &str) -> Result<u64, Box<dyn Error>> {
+    if let Ok(date) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(date.timestamp() as u64);
+    }
+
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: u64 = amount
+        .parse()
+        .map_err(|_| format!("Invalid duration: {}", value))?;
+    let unit_seconds: u64 = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" | "" => 3600,
+        "d" => 86_400,
+        "w" => 604_800,
+        _ => return Err(format!("Invalid duration unit in {}", value).into()),
+    };
+    let seconds = amount
+        .checked_mul(unit_seconds)
+        .ok_or_else(|| format!("Invalid duration: {}", value))?;
+
+    Ok((chrono::Utc::now().timestamp() as u64).saturating_sub(seconds))
+}
+
+/// Render inventory entries as an aligned text table.
+pub fn format_entries(entries: &[InventoryEntry]) -> String {
+    let mut output = format!(
+        "{:<40} {:>5}  {:<8} {:<14} {:<20} {:<20} {:>6}\n",
+        "ADDRESS", "PORT", "PROTO", "TYPE", "FIRST SEEN", "LAST SEEN", "SEEN"
+    );
+    for entry in entries {
+        output.push_str(&format!(
+            "{:<40} {:>5}  {:<8} {:<14} {:<20} {:<20} {:>6}\n",
+            entry.endpoint.address,
+            entry.endpoint.port,
+            entry.endpoint.protocol.name(),
+            entry.endpoint.endpoint_type.label(),
+            format_time(entry.first_seen),
+            format_time(entry.last_seen),
+            entry.sightings
+        ));
+    }
+    output
+}
+
+pub fn format_time(timestamp: u64) -> String {
+    chrono::DateTime::<chrono::Utc>::from_timestamp(timestamp as i64, 0).map_or_else(
+        || timestamp.to_string(),
+        |date| date.format("%Y-%m-%d %H:%M:%S").to_string(),
+    )
+}
+
+/// Accept `--type` values in the forms used elsewhere (`api`, `API`, `message-queue`).
+pub fn normalize_type_name(value: &str) -> String {
+    let normalized = value.to_lowercase().replace('-', "_");
+    match normalized.as_str() {
+        "messagequeue" | "mq" | "queue" => "message_queue".to_string(),
+        "db" => "database".to_string(),
+        "generic" => "other".to_string(),
+        _ => normalized,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> u64 {
+        chrono::Utc::now().timestamp() as u64
+    }
+
+    #[test]
+    fn parse_since_accepts_durations() {
+        for (value, seconds) in [
+            ("30s", 30),
+            ("15m", 900),
+            ("2h", 7200),
+            ("6", 21_600),
+            ("7d", 604_800),
+            ("1w", 604_800),
+        ] {
+            let expected = now() - seconds;
+            let since = parse_since(value).unwrap();
+            assert!(since.abs_diff(expected) <= 1, "{}", value);
+        }
+    }
+
+    #[test]
+    fn parse_since_accepts_rfc3339() {
+        assert_eq!(parse_since("2023-10-10T13:55:36Z").unwrap(), 1_696_946_136);
+        assert_eq!(
+            parse_since("2023-10-10T15:55:36+02:00").unwrap(),
+            1_696_946_136
+        );
+    }
+
+    #[test]
+    fn parse_since_rejects_invalid_durations() {
+        for value in ["", "d", "-5m", "5y", "1.5h", "5 minutes", "yesterday"] {
+            assert!(parse_since(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn parse_since_rejects_overflowing_durations() {
+        // Too large for u64 at all
+        assert!(parse_since("99999999999999999999s").is_err());
+        // Fits u64, overflows once multiplied by the unit
+        assert!(parse_since("30500568904944w").is_err());
+    }
+
+    #[test]
+    fn parse_since_saturates_before_the_epoch() {
+        assert_eq!(parse_since("100000000000d").unwrap(), 0);
+    }
+}
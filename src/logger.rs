@@ -1,26 +1,40 @@
 use crate::config::LoggingConfig;
-use log::{Level, LevelFilter};
-use log4rs::append::console::ConsoleAppender;
+use log::LevelFilter;
+use log4rs::append::console::{ConsoleAppender, Target};
 use log4rs::append::file::FileAppender;
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
 use std::error::Error;
 
-pub fn init() -> Result<(), Box<dyn Error>> {
+/// Log to the console. `quiet` only lets warnings and errors through, and
+/// `use_stderr` keeps stdout free for machine-readable output.
+pub fn init(quiet: bool, use_stderr: bool) -> Result<(), Box<dyn Error>> {
     // Create a default configuration for development
+    let target = if use_stderr {
+        Target::Stderr
+    } else {
+        Target::Stdout
+    };
     let console = ConsoleAppender::builder()
         .encoder(Box::new(PatternEncoder::new("{d} [{l}] {m}{n}")))
+        .target(target)
         .build();
 
+    let level = if quiet {
+        LevelFilter::Warn
+    } else {
+        LevelFilter::Info
+    };
     let config = Config::builder()
         .appender(Appender::builder().build("console", Box::new(console)))
-        .build(Root::builder().appender("console").build(LevelFilter::Info))?;
+        .build(Root::builder().appender("console").build(level))?;
 
     log4rs::init_config(config)?;
 
     Ok(())
 }
 
+#[allow(dead_code)]
 pub fn configure_from_config(logging_config: &LoggingConfig) -> Result<(), Box<dyn Error>> {
     let level = match logging_config.level.to_lowercase().as_str() {
         "trace" => LevelFilter::Trace,
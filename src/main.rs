@@ -1,18 +1,33 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgGroup, ArgMatches, SubCommand};
 use std::process;
 
 mod scanner;
 mod connector_generator;
+mod api_learning;
+mod checkpoint;
 mod config;
+mod daemon;
+mod database;
+mod diff;
+mod host_discovery;
+mod importer;
+mod inventory;
 mod logger;
+mod mcp_clients;
+mod mcp_http;
+mod mcp_server;
+mod mcp_tools;
+mod merge;
+mod passive;
+mod ports;
+mod profiles;
+mod progress;
+mod scope;
+mod signals;
+mod throttle;
+mod transform;
 
 fn main() {
-    // Initialize logger
-    logger::init().unwrap_or_else(|err| {
-        eprintln!("Failed to initialize logger: {}", err);
-        process::exit(1);
-    });
-
     // Parse command line arguments
     let matches = App::new("MCP Connector Generator")
         .version("1.0.0")
@@ -39,29 +54,516 @@ fn main() {
                 .help("Sets output directory for connector files")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("save-results")
+                .long("save-results")
+                .value_name("FILE")
+                .help("Saves the discovered endpoints as JSON for later comparison with `diff`")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("resume")
+                .long("resume")
+                .help("Continues an interrupted scan from the last checkpoint"),
+        )
+        .arg(
+            Arg::with_name("scope")
+                .long("scope")
+                .value_name("FILE")
+                .help("Sets the file listing the networks and domains you are authorized to scan")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("allow-public")
+                .long("allow-public")
+                .help("Acknowledges that networks in scope include public addresses"),
+        )
+        .arg(
+            Arg::with_name("plan")
+                .long("plan")
+                .help("Prints the hosts, ports and probes a scan would touch, then exits without scanning"),
+        )
+        .arg(
+            Arg::with_name("profile")
+                .short("p")
+                .long("profile")
+                .value_name("NAME")
+                .help("Scan profile for networks without their own (quick, deep, passive-only or one from [profiles])")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("skip-host-discovery")
+                .long("skip-host-discovery")
+                .help("Probes every address without checking first whether hosts are alive"),
+        )
+        .arg(
+            Arg::with_name("stream")
+                .long("stream")
+                .value_name("FILE")
+                .help("Writes endpoints as JSON Lines while scanning (`-` for stdout)")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("quiet")
+                .short("q")
+                .long("quiet")
+                .help("Hides the progress display and informational messages"),
+        )
+        .arg(
+            Arg::with_name("checkpoint")
+                .long("checkpoint")
+                .value_name("FILE")
+                .help("Sets the file scan progress is checkpointed to")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("import")
+                .short("i")
+                .long("import")
+                .value_name("FILE")
+                .help("Imports existing nmap XML or masscan JSON results instead of scanning")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("import-format")
+                .long("import-format")
+                .value_name("FORMAT")
+                .help("Format of imported files (detected from contents when omitted)")
+                .possible_values(&["nmap", "masscan"])
+                .takes_value(true)
+                .requires("import"),
+        )
+        .arg(
+            Arg::with_name("pcap")
+                .long("pcap")
+                .value_name("FILE")
+                .help("Passively discovers endpoints from a pcap/pcapng capture instead of scanning")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("har")
+                .long("har")
+                .value_name("FILE")
+                .help("Learns API routes from a HAR file exported from a browser session")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("access-log")
+                .long("access-log")
+                .value_name("FILE")
+                .help("Learns API routes from an nginx (combined) or Envoy access log")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
+        .arg(
+            Arg::with_name("access-log-target")
+                .long("access-log-target")
+                .value_name("HOST:PORT")
+                .help("Server that handled requests in access logs without upstream information")
+                .takes_value(true)
+                .requires("access-log"),
+        )
+        .subcommand(
+            SubCommand::with_name("daemon")
+                .about("Runs continuously, rescanning each network on its schedule and updating connectors")
+                .arg(
+                    Arg::with_name("status")
+                        .long("status")
+                        .help("Prints the status of the running daemon and exits"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compares two saved scans, or a saved scan against the inventory")
+                .arg(
+                    Arg::with_name("scans")
+                        .value_name("SCAN")
+                        .help("Old and new result files written with --save-results (only the new one with --inventory)")
+                        .required(true)
+                        .min_values(1)
+                        .max_values(2),
+                )
+                .arg(
+                    Arg::with_name("inventory")
+                        .long("inventory")
+                        .help("Compares the scan against endpoints recorded in the inventory"),
+                )
+                .arg(
+                    Arg::with_name("since")
+                        .long("since")
+                        .value_name("AGE")
+                        .help("Only inventory endpoints seen within this age (e.g. 7d)")
+                        .takes_value(true)
+                        .requires("inventory"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .help("Report format")
+                        .possible_values(&["text", "json", "markdown"])
+                        .default_value("text")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("report")
+                        .long("report")
+                        .value_name("FILE")
+                        .help("Writes the report to a file instead of stdout")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("inventory")
+                .about("Queries the persistent inventory of discovered endpoints")
+                .subcommand(
+                    SubCommand::with_name("list")
+                        .about("Lists endpoints recorded in the inventory")
+                        .arg(
+                            Arg::with_name("type")
+                                .long("type")
+                                .value_name("TYPE")
+                                .help("Only endpoints of this type (api, log, database, message_queue, other)")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("since")
+                                .long("since")
+                                .value_name("AGE")
+                                .help("Only endpoints seen within this age (e.g. 7d, 12h) or since an RFC 3339 date")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("address")
+                                .long("address")
+                                .value_name("ADDRESS")
+                                .help("Only endpoints with this address")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("port")
+                                .long("port")
+                                .value_name("PORT")
+                                .help("Only endpoints on this port")
+                                .takes_value(true),
+                        )
+                        .arg(
+                            Arg::with_name("json")
+                                .long("json")
+                                .help("Prints entries as JSON"),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("runs")
+                        .about("Lists recorded scan runs")
+                        .arg(
+                            Arg::with_name("limit")
+                                .long("limit")
+                                .value_name("COUNT")
+                                .help("Maximum number of runs to show")
+                                .default_value("20")
+                                .takes_value(true),
+                        ),
+                )
+                .subcommand(
+                    SubCommand::with_name("history")
+                        .about("Shows recorded changes for one endpoint")
+                        .arg(
+                            Arg::with_name("endpoint")
+                                .value_name("ADDRESS:PORT")
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("transform")
+                .about("Applies connector transformations to a sample payload and prints the result")
+                .arg(
+                    Arg::with_name("sample")
+                        .value_name("SAMPLE")
+                        .help("JSON file to transform, or - for stdin")
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("connector")
+                        .long("connector")
+                        .value_name("FILE")
+                        .help("Uses the transformations declared in a connector file")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("transformations")
+                        .long("transformations")
+                        .value_name("FILE")
+                        .help("Uses a JSON array of transformations")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("jq")
+                        .long("jq")
+                        .value_name("EXPRESSION")
+                        .help("Uses a single jq expression")
+                        .takes_value(true),
+                )
+                .group(
+                    ArgGroup::with_name("pipeline")
+                        .args(&["connector", "transformations", "jq"])
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("steps")
+                        .long("steps")
+                        .help("Prints the result after every step"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("serve")
+                .about("Runs an MCP server for the connectors in the output directory")
+                .arg(
+                    Arg::with_name("transport")
+                        .long("transport")
+                        .value_name("TRANSPORT")
+                        .help("Overrides server.transport")
+                        .possible_values(&["stdio", "http"])
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("port")
+                        .long("port")
+                        .value_name("PORT")
+                        .help("Overrides server.port for the HTTP transport")
+                        .takes_value(true),
+                ),
+        )
         .get_matches();
 
+    // Initialize logger, keeping stdout clean when results are streamed there
+    // or it carries MCP messages
+    let quiet = matches.is_present("quiet");
+    let stream_to_stdout =
+        matches.value_of("stream") == Some("-") || matches.subcommand_name() == Some("serve");
+    logger::init(quiet, stream_to_stdout).unwrap_or_else(|err| {
+        eprintln!("Failed to initialize logger: {}", err);
+        process::exit(1);
+    });
+
+    // Testing transformations needs no configuration
+    if let ("transform", Some(transform_matches)) = matches.subcommand() {
+        if let Err(err) = run_transform_command(transform_matches) {
+            log::error!("Transform failed: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
     // Load configuration
     let config_path = matches.value_of("config").unwrap_or("config.toml");
-    let config = match config::load_config(config_path) {
+    let mut config = match config::load_config(config_path) {
         Ok(cfg) => cfg,
         Err(err) => {
             log::error!("Failed to load configuration: {}", err);
             process::exit(1);
         }
     };
+    if let Some(scope_file) = matches.value_of("scope") {
+        config.scope.file = scope_file.to_string();
+    }
+    if matches.is_present("allow-public") {
+        config.scope.allow_public = true;
+    }
+    if matches.is_present("skip-host-discovery") {
+        config.scan.skip_host_discovery = true;
+    }
+    if let Some(profile) = matches.value_of("profile") {
+        if let Err(err) = profiles::find(&config, profile) {
+            log::error!("{}", err);
+            process::exit(1);
+        }
+        config.scan.profile = Some(profile.to_string());
+    }
+    if let Some(checkpoint_file) = matches.value_of("checkpoint") {
+        config.checkpoint.file = checkpoint_file.to_string();
+    }
 
-    // Run scanner
-    log::info!("Starting network scan");
-    let scan_results = match scanner::scan(&config) {
-        Ok(results) => results,
-        Err(err) => {
-            log::error!("Scan failed: {}", err);
+    if let ("inventory", Some(inventory_matches)) = matches.subcommand() {
+        if let Err(err) = run_inventory_command(&config, inventory_matches) {
+            log::error!("Inventory command failed: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let ("daemon", Some(daemon_matches)) = matches.subcommand() {
+        let result = if daemon_matches.is_present("status") {
+            daemon::print_status(&config)
+        } else {
+            let output_dir = matches.value_of("output").unwrap_or("./connectors");
+            if config.mcp_clients.enabled {
+                if let Err(err) =
+                    mcp_clients::write_client_configs(&config, config_path, output_dir)
+                {
+                    log::warn!("Failed to write MCP client configuration: {}", err);
+                }
+            }
+            daemon::run(&config, output_dir)
+        };
+        if let Err(err) = result {
+            log::error!("Daemon failed: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if let ("serve", Some(serve_matches)) = matches.subcommand() {
+        match serve_matches.value_of("transport") {
+            Some("http") => config.server.transport = config::ServerTransport::Http,
+            Some("stdio") => config.server.transport = config::ServerTransport::Stdio,
+            _ => {}
+        }
+        if let Some(port) = serve_matches.value_of("port") {
+            match port.parse() {
+                Ok(port) => config.server.port = port,
+                Err(_) => {
+                    log::error!("Invalid port: {}", port);
+                    process::exit(1);
+                }
+            }
+        }
+        let connectors_dir = matches.value_of("output").unwrap_or("./connectors");
+        if let Err(err) = mcp_server::serve(&config, connectors_dir) {
+            log::error!("MCP server failed: {}", err);
             process::exit(1);
         }
+        return;
+    }
+
+    if let ("diff", Some(diff_matches)) = matches.subcommand() {
+        if let Err(err) = run_diff_command(&config, diff_matches) {
+            log::error!("Diff failed: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
+    if matches.is_present("plan") {
+        match scope::print_plan(&config) {
+            Ok(true) => return,
+            Ok(false) => process::exit(1),
+            Err(err) => {
+                log::error!("Cannot plan scan: {}", err);
+                process::exit(1);
+            }
+        }
+    }
+
+    // Import existing scan data, analyze captures, or run scanner
+    let started_at = chrono::Utc::now().timestamp() as u64;
+    let offline_sources = ["import", "pcap", "har", "access-log"];
+    let mut scan_results = if offline_sources.iter().any(|arg| matches.is_present(arg)) {
+        let mut results = Vec::new();
+
+        if let Some(import_files) = matches.values_of("import") {
+            let paths: Vec<&str> = import_files.collect();
+            let format = matches
+                .value_of("import-format")
+                .and_then(importer::ImportFormat::from_name);
+
+            match importer::import_files(&paths, format) {
+                Ok(mut imported) => results.append(&mut imported),
+                Err(err) => {
+                    log::error!("Import failed: {}", err);
+                    process::exit(1);
+                }
+            }
+        }
+
+        if let Some(capture_files) = matches.values_of("pcap") {
+            let paths: Vec<&str> = capture_files.collect();
+            match passive::analyze_files(&paths) {
+                Ok(mut observed) => results.append(&mut observed),
+                Err(err) => {
+                    log::error!("Passive discovery failed: {}", err);
+                    process::exit(1);
+                }
+            }
+        }
+
+        results
+    } else {
+        log::info!("Starting network scan");
+        let options = scanner::ScanOptions {
+            resume: matches.is_present("resume"),
+            stream: matches.value_of("stream").map(String::from),
+            quiet,
+        };
+        match scanner::scan(&config, &options) {
+            Ok(results) => results,
+            Err(err) => {
+                log::error!("Scan failed: {}", err);
+                process::exit(1);
+            }
+        }
     };
+
+    // Learn API surfaces from recorded traffic and attach them to endpoints
+    let mut traffic = Vec::new();
+    for har_file in matches.values_of("har").into_iter().flatten() {
+        match api_learning::read_har(har_file) {
+            Ok(mut records) => traffic.append(&mut records),
+            Err(err) => {
+                log::error!("Failed to read HAR file {}: {}", har_file, err);
+                process::exit(1);
+            }
+        }
+    }
+    for log_file in matches.values_of("access-log").into_iter().flatten() {
+        match api_learning::read_access_log(log_file, matches.value_of("access-log-target")) {
+            Ok(mut records) => traffic.append(&mut records),
+            Err(err) => {
+                log::error!("Failed to read access log {}: {}", log_file, err);
+                process::exit(1);
+            }
+        }
+    }
+    if !traffic.is_empty() {
+        log::info!("Learning API routes from {} recorded requests", traffic.len());
+        api_learning::attach_models(&mut scan_results, &traffic);
+    }
+
+    // The same service may have been found by several discovery methods
+    let scan_results = merge::merge_endpoints(scan_results);
+
     log::info!("Scan completed: found {} endpoints", scan_results.len());
 
+    if let Some(results_path) = matches.value_of("save-results") {
+        match scanner::save_results(&scan_results, results_path) {
+            Ok(()) => log::info!("Saved scan results to {}", results_path),
+            Err(err) => log::warn!("Failed to save scan results: {}", err),
+        }
+    }
+
+    // Record the run in the persistent inventory
+    if config.inventory.enabled {
+        let source = if offline_sources.iter().any(|arg| matches.is_present(arg)) {
+            "import"
+        } else {
+            "scan"
+        };
+        match inventory::Inventory::open(&config.inventory.path)
+            .and_then(|mut inv| inv.record_run(&scan_results, source, started_at))
+        {
+            Ok(run_id) => log::info!("Recorded scan run {} in inventory", run_id),
+            Err(err) => log::warn!("Failed to update inventory: {}", err),
+        }
+    }
+
     // Generate connectors if not in scan-only mode
     if !matches.is_present("scan-only") {
         let output_dir = matches.value_of("output").unwrap_or("./connectors");
@@ -74,7 +576,169 @@ fn main() {
                 process::exit(1);
             }
         }
+
+        if config.mcp_clients.enabled {
+            match mcp_clients::write_client_configs(&config, config_path, output_dir) {
+                Ok(files) => log::info!("Wrote {} MCP client configuration files", files.len()),
+                Err(err) => log::warn!("Failed to write MCP client configuration: {}", err),
+            }
+        }
     }
 
     log::info!("Process completed successfully");
 }
+
+fn run_transform_command(matches: &ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    let steps: Vec<serde_json::Value> = if let Some(path) = matches.value_of("connector") {
+        mcp_tools::read_document(std::path::Path::new(path))?
+            .get("transformations")
+            .and_then(serde_json::Value::as_array)
+            .cloned()
+            .unwrap_or_default()
+    } else if let Some(path) = matches.value_of("transformations") {
+        serde_json::from_str(&std::fs::read_to_string(path)?)?
+    } else {
+        vec![serde_json::json!({
+            "type": "jq",
+            "expression": matches.value_of("jq").unwrap_or("."),
+        })]
+    };
+    let transformations = transform::parse_all(&steps)?;
+
+    let sample = match matches.value_of("sample") {
+        Some("-") | None => {
+            let mut input = String::new();
+            std::io::Read::read_to_string(&mut std::io::stdin(), &mut input)?;
+            input
+        }
+        Some(path) => std::fs::read_to_string(path)?,
+    };
+    // Like API responses, a payload that is not JSON is transformed as a string
+    let mut value = serde_json::from_str(&sample)
+        .unwrap_or_else(|_| serde_json::Value::String(sample.trim_end().to_string()));
+
+    for (index, transformation) in transformations.iter().enumerate() {
+        value = transformation.apply(value).map_err(|err| {
+            format!(
+                "Step {} ({}) failed: {}",
+                index + 1,
+                transformation.kind(),
+                err
+            )
+        })?;
+        if matches.is_present("steps") {
+            println!("# Step {}: {}", index + 1, transformation.kind());
+            println!("{}", serde_json::to_string_pretty(&value)?);
+        }
+    }
+
+    if !matches.is_present("steps") {
+        println!("{}", serde_json::to_string_pretty(&value)?);
+    }
+    Ok(())
+}
+
+fn run_diff_command(
+    config: &config::Config,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let scans: Vec<&str> = matches.values_of("scans").into_iter().flatten().collect();
+
+    let (old, new) = if matches.is_present("inventory") {
+        if scans.len() != 1 {
+            return Err("Expected a single scan file to compare against the inventory".into());
+        }
+        let filter = inventory::InventoryFilter {
+            since: matches
+                .value_of("since")
+                .map(inventory::parse_since)
+                .transpose()?,
+            ..inventory::InventoryFilter::default()
+        };
+        let recorded = inventory::Inventory::open(&config.inventory.path)?
+            .list(&filter)?
+            .into_iter()
+            .map(|entry| entry.endpoint)
+            .collect();
+        (recorded, scanner::load_results(scans[0])?)
+    } else {
+        if scans.len() != 2 {
+            return Err("Expected an old and a new scan file".into());
+        }
+        (
+            scanner::load_results(scans[0])?,
+            scanner::load_results(scans[1])?,
+        )
+    };
+
+    let scan_diff = diff::diff_scans(&old, &new);
+    let report = match matches.value_of("format").unwrap_or("text") {
+        "json" => serde_json::to_string_pretty(&scan_diff)?,
+        "markdown" => diff::format_markdown(&scan_diff),
+        _ => diff::format_text(&scan_diff),
+    };
+
+    match matches.value_of("report") {
+        Some(path) => std::fs::write(path, report)?,
+        None => print!("{}", report),
+    }
+
+    Ok(())
+}
+
+fn run_inventory_command(
+    config: &config::Config,
+    matches: &ArgMatches,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let inventory = inventory::Inventory::open(&config.inventory.path)?;
+
+    match matches.subcommand() {
+        ("list", Some(list_matches)) => {
+            let filter = inventory::InventoryFilter {
+                endpoint_type: list_matches
+                    .value_of("type")
+                    .map(inventory::normalize_type_name),
+                since: list_matches
+                    .value_of("since")
+                    .map(inventory::parse_since)
+                    .transpose()?,
+                address: list_matches.value_of("address").map(str::to_string),
+                port: list_matches
+                    .value_of("port")
+                    .map(str::parse::<u16>)
+                    .transpose()?,
+            };
+            let entries = inventory.list(&filter)?;
+            if list_matches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else {
+                print!("{}", inventory::format_entries(&entries));
+            }
+        }
+        ("runs", Some(runs_matches)) => {
+            let limit = runs_matches.value_of("limit").unwrap_or("20").parse()?;
+            for run in inventory.runs(limit)? {
+                println!(
+                    "{}  {:<8} {}  {} endpoints",
+                    run.id,
+                    run.source,
+                    inventory::format_time(run.finished_at),
+                    run.endpoint_count
+                );
+            }
+        }
+        ("history", Some(history_matches)) => {
+            let endpoint = history_matches.value_of("endpoint").unwrap_or_default();
+            let (address, port) = endpoint
+                .rsplit_once(':')
+                .ok_or("Expected ADDRESS:PORT")?;
+            let changes = inventory.history(address.trim_matches(|c| c == '[' || c == ']'), port.parse()?)?;
+            println!("{}", serde_json::to_string_pretty(&changes)?);
+        }
+        _ => {
+            println!("{}", matches.usage());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use crate::config::Config;
+use serde_json::{json, Value};
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Written next to the connectors, so each connector set carries its own client configuration
+const CLIENTS_DIR: &str = "mcp_clients";
+
+/// Write configuration for MCP clients that launches this tool's `serve`
+/// command over stdio for the connectors in `output_dir`. Returns the files
+/// written.
+pub fn write_client_configs(
+    config: &Config,
+    config_path: &str,
+    output_dir: &str,
+) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let clients_dir = Path::new(output_dir).join(CLIENTS_DIR);
+    fs::create_dir_all(&clients_dir)?;
+
+    // Clients start the server from their own working directory, so every
+    // path has to be absolute
+    let command = env::current_exe()?.to_string_lossy().into_owned();
+    let args = vec![
+        "--config".to_string(),
+        absolute(Path::new(config_path))?,
+        "--output".to_string(),
+        absolute(Path::new(output_dir))?,
+        "serve".to_string(),
+    ];
+    let name = &config.mcp_clients.server_name;
+
+    let documents = [
+        // Desktop MCP clients, Cursor and Windsurf
+        (
+            "mcp_servers.json",
+            json!({
+                "mcpServers": {
+                    name: { "command": command, "args": args }
+                }
+            }),
+        ),
+        // VS Code (.vscode/mcp.json)
+        (
+            "vscode_mcp.json",
+            json!({
+                "servers": {
+                    name: { "type": "stdio", "command": command, "args": args }
+                }
+            }),
+        ),
+        // Zed (settings.json)
+        (
+            "zed_settings.json",
+            json!({
+                "context_servers": {
+                    name: { "source": "custom", "command": command, "args": args, "env": {} }
+                }
+            }),
+        ),
+    ];
+
+    let mut written = Vec::new();
+    for (file_name, document) in documents.iter() {
+        let path = clients_dir.join(file_name);
+        fs::write(&path, pretty(document)?)?;
+        written.push(path);
+    }
+    Ok(written)
+}
+
+fn absolute(path: &Path) -> Result<String, Box<dyn Error>> {
+    let path = fs::canonicalize(path)
+        .map_err(|err| format!("Cannot resolve {}: {}", path.display(), err))?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+fn pretty(document: &Value) -> Result<String, Box<dyn Error>> {
+    Ok(serde_json::to_string_pretty(document)? + "\n")
+}
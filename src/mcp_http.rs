@@ -0,0 +1,884 @@
+use crate::config::ServerConfig;
+use crate::mcp_server::{self, McpServer};
+use crate::mcp_tools;
+use crate::signals;
+use futures::channel::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use futures::stream::{FuturesUnordered, StreamExt};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::header::{self, HeaderValue};
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{HeaderMap, Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::convert::Infallible;
+use std::error::Error;
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use uuid::Uuid;
+
+const SESSION_HEADER: &str = "mcp-session-id";
+const VERSION_HEADER: &str = "mcp-protocol-version";
+const LAST_EVENT_HEADER: &str = "last-event-id";
+
+const MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+// Events kept per session so a client can resume a broken stream
+const EVENT_HISTORY: usize = 256;
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+// The GET stream is stream 0; each SSE response to a POST gets the next number
+const STANDALONE_STREAM: u64 = 0;
+
+type Body = BoxBody<Bytes, Infallible>;
+
+struct Session {
+    last_seen: Instant,
+    next_stream: u64,
+    next_event: u64,
+    history: VecDeque<Event>,
+    /// POST streams still producing responses, by stream number
+    live: HashMap<u64, UnboundedSender<Bytes>>,
+}
+
+/// A message sent on an SSE stream. Its ID, `<stream>-<sequence>`, tells a
+/// resuming client's `Last-Event-ID` which stream to continue and where.
+struct Event {
+    stream: u64,
+    sequence: u64,
+    data: String,
+}
+
+impl Event {
+    fn frame(&self) -> Bytes {
+        Bytes::from(format!(
+            "id: {}-{}\nevent: message\ndata: {}\n\n",
+            self.stream, self.sequence, self.data
+        ))
+    }
+}
+
+fn parse_event_id(id: &str) -> Option<(u64, u64)> {
+    let (stream, sequence) = id.trim().split_once('-')?;
+    Some((stream.parse().ok()?, sequence.parse().ok()?))
+}
+
+/// MCP Streamable HTTP transport: one endpoint taking JSON-RPC messages by
+/// POST, answered with JSON or an SSE stream, plus GET for a standalone
+/// stream and DELETE to end a session.
+struct HttpTransport {
+    server: Arc<McpServer>,
+    settings: ServerConfig,
+    /// Resolved `server.auth_token`
+    auth_token: Option<String>,
+    sessions: Mutex<HashMap<String, Session>>,
+}
+
+/// Serve MCP over HTTP until the process is asked to stop.
+pub async fn run(server: Arc<McpServer>, settings: ServerConfig) -> Result<(), Box<dyn Error>> {
+    let auth_token = match &settings.auth_token {
+        Some(token) => {
+            let token = mcp_tools::resolve_env(token)?;
+            if token.trim().is_empty() {
+                return Err("server.auth_token is empty".into());
+            }
+            Some(token)
+        }
+        None => None,
+    };
+    // Without a token anyone who can reach the port can call every tool
+    if auth_token.is_none() && !is_loopback(&settings.bind_address) {
+        return Err(format!(
+            "Refusing to listen on {} without server.auth_token; set a token or bind to 127.0.0.1",
+            settings.bind_address
+        )
+        .into());
+    }
+
+    let listener = TcpListener::bind((settings.bind_address.as_str(), settings.port))
+        .await
+        .map_err(|err| {
+            format!(
+                "Cannot listen on {}:{}: {}",
+                settings.bind_address, settings.port, err
+            )
+        })?;
+    log::info!(
+        "MCP server listening on http://{}{}",
+        listener.local_addr()?,
+        settings.path
+    );
+
+    let transport = Arc::new(HttpTransport {
+        server,
+        settings,
+        auth_token,
+        sessions: Mutex::new(HashMap::new()),
+    });
+    serve(listener, transport, signals::shutdown_requested()).await;
+    Ok(())
+}
+
+/// Accept connections on `listener` until `shutdown` completes.
+async fn serve(
+    listener: TcpListener,
+    transport: Arc<HttpTransport>,
+    shutdown: impl Future<Output = ()>,
+) {
+    tokio::pin!(shutdown);
+
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    log::warn!("Failed to accept connection: {}", err);
+                    continue;
+                }
+            },
+            _ = &mut shutdown => {
+                log::info!("Stopping MCP server");
+                return;
+            }
+        };
+
+        let transport = transport.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |request| {
+                let transport = transport.clone();
+                async move { Ok::<_, Infallible>(transport.route(request).await) }
+            });
+            if let Err(err) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                log::debug!("Connection from {} ended: {}", peer, err);
+            }
+        });
+    }
+}
+
+impl HttpTransport {
+    async fn route(self: Arc<Self>, request: Request<Incoming>) -> Response<Body> {
+        if request.uri().path() != self.settings.path {
+            return text(StatusCode::NOT_FOUND, "Not found");
+        }
+        if !authorized(request.headers(), self.auth_token.as_deref()) {
+            let mut response = text(StatusCode::UNAUTHORIZED, "Missing or invalid bearer token");
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+            return response;
+        }
+
+        // Browsers may reach a local server from any page; only trusted
+        // origins get in, which also defeats DNS rebinding
+        if let Some(origin) = header_value(request.headers(), header::ORIGIN.as_str()) {
+            if !origin_allowed(origin, &self.settings.allowed_origins) {
+                log::warn!("Rejected request from origin {}", origin);
+                return text(StatusCode::FORBIDDEN, "Origin not allowed");
+            }
+        }
+        if let Some(version) = header_value(request.headers(), VERSION_HEADER) {
+            if !mcp_server::PROTOCOL_VERSIONS.contains(&version) {
+                return text(
+                    StatusCode::BAD_REQUEST,
+                    &format!("Unsupported MCP-Protocol-Version {}", version),
+                );
+            }
+        }
+
+        self.expire_sessions();
+        match *request.method() {
+            Method::POST => self.post(request).await,
+            Method::GET => self.get(request.headers()),
+            Method::DELETE => self.delete(request.headers()),
+            _ => {
+                let mut response = text(StatusCode::METHOD_NOT_ALLOWED, "Method not allowed");
+                response
+                    .headers_mut()
+                    .insert(header::ALLOW, HeaderValue::from_static("GET, POST, DELETE"));
+                response
+            }
+        }
+    }
+
+    async fn post(self: Arc<Self>, request: Request<Incoming>) -> Response<Body> {
+        let headers = request.headers();
+        let accept = header_value(headers, header::ACCEPT.as_str()).unwrap_or("*/*");
+        let accepts_json = accept.contains("application/json") || accept.contains("*/*");
+        let accepts_sse = accept.contains("text/event-stream");
+        if !accepts_json && !accepts_sse {
+            return text(
+                StatusCode::NOT_ACCEPTABLE,
+                "Accept must include application/json or text/event-stream",
+            );
+        }
+        let content_type = header_value(headers, header::CONTENT_TYPE.as_str()).unwrap_or("");
+        if !content_type.starts_with("application/json") {
+            return text(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "Content-Type must be application/json",
+            );
+        }
+        let session_header = header_value(headers, SESSION_HEADER).map(String::from);
+
+        let body = match Limited::new(request.into_body(), MAX_BODY_BYTES)
+            .collect()
+            .await
+        {
+            Ok(body) => body.to_bytes(),
+            Err(err) if err.is::<LengthLimitError>() => {
+                return text(StatusCode::PAYLOAD_TOO_LARGE, "Request body too large")
+            }
+            Err(err) => {
+                return text(
+                    StatusCode::BAD_REQUEST,
+                    &format!("Cannot read request body: {}", err),
+                )
+            }
+        };
+        let (messages, batch) = match serde_json::from_slice(&body) {
+            Ok(Value::Array(messages)) => (messages, true),
+            Ok(message) => (vec![message], false),
+            Err(err) => return json(StatusCode::BAD_REQUEST, &mcp_server::parse_error(&err)),
+        };
+        if messages.is_empty() {
+            return json(
+                StatusCode::BAD_REQUEST,
+                &mcp_server::invalid_request("Empty batch"),
+            );
+        }
+
+        // Initializing starts a session; everything else has to name one
+        let initializing = messages
+            .iter()
+            .any(|message| message.get("method").and_then(Value::as_str) == Some("initialize"));
+        let session_id = if initializing {
+            match self.create_session() {
+                Some(session_id) => session_id,
+                None => {
+                    return text(
+                        StatusCode::SERVICE_UNAVAILABLE,
+                        "Too many open sessions; close one or try again later",
+                    )
+                }
+            }
+        } else {
+            match self.find_session(session_header.as_deref()) {
+                Ok(session_id) => session_id,
+                Err((status, message)) => return text(status, message),
+            }
+        };
+
+        let has_requests = messages
+            .iter()
+            .any(|message| message.get("method").is_some() && message.get("id").is_some());
+        let response = if !has_requests {
+            for message in messages {
+                self.server.handle(message).await;
+            }
+            empty(StatusCode::ACCEPTED)
+        } else if accepts_sse && !(self.settings.json_response && accepts_json) {
+            self.stream_replies(&session_id, messages)
+        } else {
+            let replies: Vec<Value> = futures::future::join_all(
+                messages
+                    .into_iter()
+                    .map(|message| self.server.handle(message)),
+            )
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+            let reply = if batch {
+                Value::Array(replies)
+            } else {
+                replies.into_iter().next().unwrap_or(Value::Null)
+            };
+            json(StatusCode::OK, &reply)
+        };
+
+        if initializing {
+            with_session(response, &session_id)
+        } else {
+            response
+        }
+    }
+
+    /// Answer the requests in a POST on a new SSE stream, sending each reply
+    /// as soon as it is ready. The stream ends after the last one.
+    fn stream_replies(self: Arc<Self>, session_id: &str, messages: Vec<Value>) -> Response<Body> {
+        let (sender, receiver) = mpsc::unbounded();
+        let stream = match self.sessions.lock().unwrap().get_mut(session_id) {
+            Some(session) => {
+                session.next_stream += 1;
+                session.live.insert(session.next_stream, sender);
+                session.next_stream
+            }
+            None => return text(StatusCode::NOT_FOUND, "Unknown or expired session"),
+        };
+
+        let session_id = session_id.to_string();
+        tokio::spawn(async move {
+            let mut pending: FuturesUnordered<_> = messages
+                .into_iter()
+                .map(|message| self.server.handle(message))
+                .collect();
+            // Replies are recorded even once the client has gone, so a
+            // reconnecting client can still collect them
+            while let Some(reply) = pending.next().await {
+                if let Some(reply) = reply {
+                    self.record(&session_id, stream, reply.to_string());
+                }
+            }
+            drop(pending);
+            self.close_stream(&session_id, stream);
+        });
+
+        sse(receiver)
+    }
+
+    /// Open the standalone stream, or resume a stream after `Last-Event-ID`.
+    fn get(self: Arc<Self>, headers: &HeaderMap) -> Response<Body> {
+        let accept = header_value(headers, header::ACCEPT.as_str()).unwrap_or("");
+        if !accept.contains("text/event-stream") {
+            return text(
+                StatusCode::NOT_ACCEPTABLE,
+                "Accept must include text/event-stream",
+            );
+        }
+        let session_id = match self.find_session(header_value(headers, SESSION_HEADER)) {
+            Ok(session_id) => session_id,
+            Err((status, message)) => return text(status, message),
+        };
+
+        let (sender, receiver) = mpsc::unbounded();
+        let resumed = match header_value(headers, LAST_EVENT_HEADER) {
+            Some(last_event) => match parse_event_id(last_event) {
+                Some(position) => Some(position),
+                None => return text(StatusCode::BAD_REQUEST, "Invalid Last-Event-ID"),
+            },
+            None => None,
+        };
+
+        match resumed {
+            Some((stream, sequence)) if stream != STANDALONE_STREAM => {
+                // Replay what was missed; a stream still producing replies
+                // continues here, a finished one closes after the replay
+                let mut sessions = self.sessions.lock().unwrap();
+                if let Some(session) = sessions.get_mut(&session_id) {
+                    for event in &session.history {
+                        if event.stream == stream && event.sequence > sequence {
+                            let _ = sender.unbounded_send(event.frame());
+                        }
+                    }
+                    if let Some(live) = session.live.get_mut(&stream) {
+                        *live = sender;
+                    }
+                }
+                log::debug!("Session {} resumed stream {}", session_id, stream);
+            }
+            _ => {
+                // Nothing is sent unprompted, so the standalone stream only
+                // carries keepalives that also keep the session from expiring
+                tokio::spawn(async move {
+                    loop {
+                        tokio::time::sleep(KEEPALIVE_INTERVAL).await;
+                        if !self.touch(&session_id)
+                            || sender
+                                .unbounded_send(Bytes::from_static(b": keepalive\n\n"))
+                                .is_err()
+                        {
+                            break;
+                        }
+                    }
+                });
+            }
+        }
+
+        sse(receiver)
+    }
+
+    fn delete(&self, headers: &HeaderMap) -> Response<Body> {
+        let session_id = match header_value(headers, SESSION_HEADER) {
+            Some(session_id) => session_id,
+            None => return text(StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id header"),
+        };
+        if self.sessions.lock().unwrap().remove(session_id).is_some() {
+            log::info!("Session {} closed by client", session_id);
+            empty(StatusCode::NO_CONTENT)
+        } else {
+            text(StatusCode::NOT_FOUND, "Unknown or expired session")
+        }
+    }
+
+    /// Start a session, or `None` when `max_sessions` are already open.
+    fn create_session(&self) -> Option<String> {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.len() >= self.settings.max_sessions {
+            log::warn!(
+                "Refusing new session: {} sessions already open",
+                sessions.len()
+            );
+            return None;
+        }
+
+        let session_id = Uuid::new_v4().to_string();
+        sessions.insert(
+            session_id.clone(),
+            Session {
+                last_seen: Instant::now(),
+                next_stream: STANDALONE_STREAM,
+                next_event: 0,
+                history: VecDeque::new(),
+                live: HashMap::new(),
+            },
+        );
+        log::info!("Started session {}", session_id);
+        Some(session_id)
+    }
+
+    /// The session a request belongs to. Missing and unknown sessions are
+    /// refused as the transport requires, with 400 and 404.
+    fn find_session(&self, session_id: Option<&str>) -> Result<String, (StatusCode, &str)> {
+        let session_id =
+            session_id.ok_or((StatusCode::BAD_REQUEST, "Missing Mcp-Session-Id header"))?;
+        if self.touch(session_id) {
+            Ok(session_id.to_string())
+        } else {
+            Err((StatusCode::NOT_FOUND, "Unknown or expired session"))
+        }
+    }
+
+    fn touch(&self, session_id: &str) -> bool {
+        match self.sessions.lock().unwrap().get_mut(session_id) {
+            Some(session) => {
+                session.last_seen = Instant::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn expire_sessions(&self) {
+        let timeout = Duration::from_secs(self.settings.session_timeout_secs);
+        self.sessions.lock().unwrap().retain(|session_id, session| {
+            let active = session.last_seen.elapsed() < timeout;
+            if !active {
+                log::info!("Session {} expired", session_id);
+            }
+            active
+        });
+    }
+
+    /// Keep a reply in the session history and send it to whoever is
+    /// listening on its stream.
+    fn record(&self, session_id: &str, stream: u64, data: String) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let session = match sessions.get_mut(session_id) {
+            Some(session) => session,
+            None => return,
+        };
+
+        session.next_event += 1;
+        let event = Event {
+            stream,
+            sequence: session.next_event,
+            data,
+        };
+        if let Some(sender) = session.live.get(&stream) {
+            let _ = sender.unbounded_send(event.frame());
+        }
+        session.history.push_back(event);
+        if session.history.len() > EVENT_HISTORY {
+            session.history.pop_front();
+        }
+    }
+
+    fn close_stream(&self, session_id: &str, stream: u64) {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(session_id) {
+            session.live.remove(&stream);
+        }
+    }
+}
+
+/// Whether the request carries the bearer token, when one is required.
+fn authorized(headers: &HeaderMap, token: Option<&str>) -> bool {
+    let expected = match token {
+        Some(token) => token.as_bytes(),
+        None => return true,
+    };
+    let given = header_value(headers, header::AUTHORIZATION.as_str())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map_or(&b""[..], |(_, token)| token.trim().as_bytes());
+
+    // Compare every byte so the time taken does not reveal a matching prefix
+    given.len() == expected.len()
+        && given
+            .iter()
+            .zip(expected)
+            .fold(0, |difference, (a, b)| difference | (a ^ b))
+            == 0
+}
+
+fn origin_allowed(origin: &str, allowed_origins: &[String]) -> bool {
+    if allowed_origins
+        .iter()
+        .any(|allowed| allowed.trim_end_matches('/') == origin)
+    {
+        return true;
+    }
+
+    let authority = origin.split_once("://").map_or(origin, |(_, rest)| rest);
+    let host = match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(""),
+        None => authority.split(':').next().unwrap_or(""),
+    };
+    matches!(host, "localhost" | "127.0.0.1" | "::1")
+}
+
+fn is_loopback(address: &str) -> bool {
+    address.eq_ignore_ascii_case("localhost")
+        || address
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .parse::<IpAddr>()
+            .is_ok_and(|address| address.is_loopback())
+}
+
+fn header_value<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn empty(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Full::new(Bytes::new()).boxed());
+    *response.status_mut() = status;
+    response
+}
+
+fn text(status: StatusCode, message: &str) -> Response<Body> {
+    let mut response = Response::new(Full::new(Bytes::from(format!("{}\n", message))).boxed());
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    response
+}
+
+fn json(status: StatusCode, value: &Value) -> Response<Body> {
+    let mut response = Response::new(Full::new(Bytes::from(value.to_string())).boxed());
+    *response.status_mut() = status;
+    response.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    response
+}
+
+fn sse(receiver: UnboundedReceiver<Bytes>) -> Response<Body> {
+    let body = StreamBody::new(receiver.map(|bytes| Ok::<_, Infallible>(Frame::data(bytes))));
+    let mut response = Response::new(BodyExt::boxed(body));
+    let headers = response.headers_mut();
+    headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/event-stream"),
+    );
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+    response
+}
+
+fn with_session(mut response: Response<Body>, session_id: &str) -> Response<Body> {
+    if let Ok(value) = HeaderValue::from_str(session_id) {
+        response.headers_mut().insert(SESSION_HEADER, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_event_id_reads_stream_and_sequence() {
+        assert_eq!(parse_event_id("3-17"), Some((3, 17)));
+        assert_eq!(parse_event_id(" 0-0\n"), Some((0, 0)));
+        for id in ["", "3", "3-", "-17", "a-1", "1-b", "1-2-3", "-1-2"] {
+            assert_eq!(parse_event_id(id), None, "{:?}", id);
+        }
+    }
+
+    #[test]
+    fn origin_allowed_accepts_localhost() {
+        for origin in [
+            "http://localhost",
+            "http://localhost:3000",
+            "https://127.0.0.1:8443",
+            "http://[::1]:8765",
+        ] {
+            assert!(origin_allowed(origin, &[]), "{}", origin);
+        }
+    }
+
+    #[test]
+    fn origin_allowed_rejects_other_hosts() {
+        for origin in [
+            "http://evil.example",
+            "http://localhost.evil.example",
+            "http://127.0.0.1.nip.io",
+            "http://[::2]",
+            "null",
+        ] {
+            assert!(!origin_allowed(origin, &[]), "{}", origin);
+        }
+    }
+
+    #[test]
+    fn origin_allowed_accepts_configured_origins() {
+        let allowed = vec!["https://agents.example.com/".to_string()];
+        assert!(origin_allowed("https://agents.example.com", &allowed));
+        assert!(!origin_allowed("http://agents.example.com", &allowed));
+        assert!(!origin_allowed("https://agents.example.com:8443", &allowed));
+    }
+
+    #[test]
+    fn authorized_checks_bearer_token() {
+        let mut headers = HeaderMap::new();
+        assert!(authorized(&headers, None));
+        assert!(!authorized(&headers, Some("secret")));
+
+        for (value, expected) in [
+            ("Bearer secret", true),
+            ("bearer  secret ", true),
+            ("Bearer secre", false),
+            ("Bearer secrets", false),
+            ("Basic secret", false),
+            ("secret", false),
+        ] {
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_static(value));
+            assert_eq!(authorized(&headers, Some("secret")), expected, "{}", value);
+        }
+    }
+
+    #[test]
+    fn is_loopback_recognises_loopback_addresses() {
+        for address in ["127.0.0.1", "127.1.2.3", "::1", "[::1]", "localhost"] {
+            assert!(is_loopback(address), "{}", address);
+        }
+        for address in ["0.0.0.0", "::", "10.0.0.5", "example.com"] {
+            assert!(!is_loopback(address), "{}", address);
+        }
+    }
+
+    /// Serve an empty connector directory on an ephemeral loopback port.
+    async fn start(dir: &std::path::Path, settings: ServerConfig) -> String {
+        let config = crate::config::load_config(dir.join("config.toml")).unwrap();
+        let server = McpServer::load(&config, dir.to_str().unwrap()).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}{}", listener.local_addr().unwrap(), settings.path);
+        let transport = Arc::new(HttpTransport {
+            server: Arc::new(server),
+            settings,
+            auth_token: None,
+            sessions: Mutex::new(HashMap::new()),
+        });
+        tokio::spawn(serve(listener, transport, futures::future::pending()));
+        url
+    }
+
+    async fn post(
+        client: &reqwest::Client,
+        url: &str,
+        session: Option<&str>,
+        accept: &str,
+        body: Value,
+    ) -> reqwest::Response {
+        let mut request = client
+            .post(url)
+            .header(header::ACCEPT, accept)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.to_string());
+        if let Some(session) = session {
+            request = request.header(SESSION_HEADER, session);
+        }
+        request.send().await.unwrap()
+    }
+
+    async fn initialize(client: &reqwest::Client, url: &str) -> reqwest::Response {
+        post(
+            client,
+            url,
+            None,
+            "application/json, text/event-stream",
+            serde_json::json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "initialize",
+                "params": { "protocolVersion": mcp_server::PROTOCOL_VERSIONS[0] },
+            }),
+        )
+        .await
+    }
+
+    /// `(id, data)` of every event in an SSE body.
+    fn events(body: &str) -> Vec<(String, Value)> {
+        body.split("\n\n")
+            .filter_map(|event| {
+                let field = |name: &str| {
+                    event
+                        .lines()
+                        .find_map(|line| line.strip_prefix(name))
+                        .map(str::to_string)
+                };
+                Some((
+                    field("id: ")?,
+                    serde_json::from_str(&field("data: ")?).unwrap(),
+                ))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn http_transport_serves_sessions_and_resumes_streams() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let url = start(dir.path(), ServerConfig::default()).await;
+            let client = reqwest::Client::new();
+
+            let response = initialize(&client, &url).await;
+            assert_eq!(response.status(), 200);
+            assert_eq!(
+                response.headers()[header::CONTENT_TYPE],
+                "text/event-stream"
+            );
+            let session = response.headers()[SESSION_HEADER]
+                .to_str()
+                .unwrap()
+                .to_string();
+            let replies = events(&response.text().await.unwrap());
+            assert_eq!(replies.len(), 1);
+            assert_eq!(replies[0].0, "1-1");
+            assert_eq!(
+                replies[0].1["result"]["protocolVersion"],
+                mcp_server::PROTOCOL_VERSIONS[0]
+            );
+
+            let notification = serde_json::json!({
+                "jsonrpc": "2.0",
+                "method": "notifications/initialized",
+            });
+            let response = post(
+                &client,
+                &url,
+                Some(&session),
+                "application/json, text/event-stream",
+                notification,
+            )
+            .await;
+            assert_eq!(response.status(), 202);
+
+            let list = serde_json::json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" });
+            let response = post(
+                &client,
+                &url,
+                Some(&session),
+                "application/json, text/event-stream",
+                list.clone(),
+            )
+            .await;
+            let replies = events(&response.text().await.unwrap());
+            assert_eq!(replies.len(), 1);
+            assert_eq!(replies[0].0, "2-2");
+            assert_eq!(replies[0].1["id"], 2);
+            assert!(replies[0].1["result"]["tools"].is_array());
+
+            // Clients accepting only JSON get a plain reply
+            let response = post(&client, &url, Some(&session), "application/json", list).await;
+            assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+            let reply: Value = response.json().await.unwrap();
+            assert!(reply["result"]["tools"].is_array());
+
+            // Standalone stream
+            let standalone = client
+                .get(&url)
+                .header(header::ACCEPT, "text/event-stream")
+                .header(SESSION_HEADER, &session)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(standalone.status(), 200);
+            assert_eq!(
+                standalone.headers()[header::CONTENT_TYPE],
+                "text/event-stream"
+            );
+            drop(standalone);
+
+            // Resuming the finished tools/list stream replays what was missed
+            let resume = |last_event: &'static str| {
+                client
+                    .get(&url)
+                    .header(header::ACCEPT, "text/event-stream")
+                    .header(SESSION_HEADER, &session)
+                    .header(LAST_EVENT_HEADER, last_event)
+                    .send()
+            };
+            let replayed = events(&resume("2-1").await.unwrap().text().await.unwrap());
+            assert_eq!(replayed.len(), 1);
+            assert_eq!(replayed[0].0, "2-2");
+            assert_eq!(replayed[0].1["id"], 2);
+            assert!(events(&resume("2-2").await.unwrap().text().await.unwrap()).is_empty());
+            assert_eq!(resume("two").await.unwrap().status(), 400);
+
+            let ping = serde_json::json!({ "jsonrpc": "2.0", "id": 3, "method": "ping" });
+            let response = post(&client, &url, None, "application/json", ping.clone()).await;
+            assert_eq!(response.status(), 400);
+
+            let response = client
+                .delete(&url)
+                .header(SESSION_HEADER, &session)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 204);
+            let response = post(&client, &url, Some(&session), "application/json", ping).await;
+            assert_eq!(response.status(), 404);
+        });
+        runtime.shutdown_background();
+    }
+
+    #[test]
+    fn http_transport_caps_open_sessions() {
+        let dir = tempfile::tempdir().unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let settings = ServerConfig {
+                max_sessions: 2,
+                ..ServerConfig::default()
+            };
+            let url = start(dir.path(), settings).await;
+            let client = reqwest::Client::new();
+
+            let first = initialize(&client, &url).await;
+            assert_eq!(first.status(), 200);
+            assert_eq!(initialize(&client, &url).await.status(), 200);
+            assert_eq!(initialize(&client, &url).await.status(), 503);
+
+            let response = client
+                .delete(&url)
+                .header(SESSION_HEADER, first.headers()[SESSION_HEADER].clone())
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), 204);
+            assert_eq!(initialize(&client, &url).await.status(), 200);
+        });
+        runtime.shutdown_background();
+    }
+}
@@ -0,0 +1,305 @@
+use crate::config::{Config, ServerTransport};
+use crate::mcp_http;
+use crate::mcp_tools::{self, Connector, Tool, ToolContext};
+use serde_json::{json, Value};
+use std::error::Error;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+// Newest first; a client asking for anything else is offered the newest
+pub const PROTOCOL_VERSIONS: [&str; 3] = ["2025-06-18", "2025-03-26", "2024-11-05"];
+
+// JSON-RPC 2.0 and MCP error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const RESOURCE_NOT_FOUND: i64 = -32002;
+
+const RESOURCE_SCHEME: &str = "connector://";
+
+/// Model Context Protocol server exposing a connector directory as tools
+/// and resources. Independent of the transport carrying the messages.
+pub struct McpServer {
+    connectors: Vec<Connector>,
+    tools: Vec<Tool>,
+    context: ToolContext,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl McpServer {
+    pub fn load(config: &Config, connectors_dir: &str) -> Result<Self, Box<dyn Error>> {
+        let connectors = mcp_tools::load_connectors(Path::new(connectors_dir))?;
+        let tools = mcp_tools::tools_for(&connectors);
+        log::info!(
+            "Serving {} connectors as {} tools from {}",
+            connectors.len(),
+            tools.len(),
+            connectors_dir
+        );
+        Ok(McpServer {
+            connectors,
+            tools,
+            context: ToolContext::new(config)?,
+        })
+    }
+
+    /// Handle one JSON-RPC message. Notifications and responses get no reply.
+    pub async fn handle(&self, message: Value) -> Option<Value> {
+        let id = message.get("id").cloned();
+        let method = match message.get("method").and_then(Value::as_str) {
+            Some(method) => method,
+            // Responses to requests we never send, or garbage with an id
+            None if message.get("result").is_some() || message.get("error").is_some() => {
+                return None
+            }
+            None => {
+                return Some(error_response(
+                    id.unwrap_or(Value::Null),
+                    RpcError::new(INVALID_REQUEST, "Missing method"),
+                ))
+            }
+        };
+
+        let id = match id {
+            Some(id) => id,
+            None => {
+                log::debug!("Received notification {}", method);
+                return None;
+            }
+        };
+
+        let params = message.get("params").cloned().unwrap_or_else(|| json!({}));
+        let result = match method {
+            "initialize" => Ok(self.initialize(&params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(self.list_tools()),
+            "tools/call" => self.call_tool(&params).await,
+            "resources/list" => Ok(self.list_resources()),
+            "resources/read" => self.read_resource(&params),
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Method not found: {}", method),
+            )),
+        };
+
+        Some(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(err) => error_response(id, err),
+        })
+    }
+
+    fn initialize(&self, params: &Value) -> Value {
+        let requested = params.get("protocolVersion").and_then(Value::as_str);
+        let version = requested
+            .filter(|version| PROTOCOL_VERSIONS.contains(version))
+            .unwrap_or(PROTOCOL_VERSIONS[0]);
+        if let Some(client) = params.get("clientInfo") {
+            log::info!("Client connected: {}", client);
+        }
+
+        json!({
+            "protocolVersion": version,
+            "capabilities": {
+                "tools": { "listChanged": false },
+                "resources": { "listChanged": false },
+            },
+            "serverInfo": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "instructions": "Tools and resources for network endpoints discovered by scanning. \
+                Each connector is available as a resource; tools act on the endpoints.",
+        })
+    }
+
+    fn list_tools(&self) -> Value {
+        let tools: Vec<Value> = self.tools.iter().map(Tool::definition).collect();
+        json!({ "tools": tools })
+    }
+
+    async fn call_tool(&self, params: &Value) -> Result<Value, RpcError> {
+        let name = params
+            .get("name")
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing tool name"))?;
+        let tool = self
+            .tools
+            .iter()
+            .find(|tool| tool.name == name)
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("Unknown tool: {}", name)))?;
+        let arguments = params
+            .get("arguments")
+            .cloned()
+            .unwrap_or_else(|| json!({}));
+
+        log::info!("Calling tool {}", name);
+        let result = match mcp_tools::call(tool, &arguments, &self.context).await {
+            Ok(output) => json!({
+                "content": [{ "type": "text", "text": text_of(&output) }],
+                "structuredContent": output,
+                "isError": false,
+            }),
+            Err(message) => json!({
+                "content": [{ "type": "text", "text": message }],
+                "isError": true,
+            }),
+        };
+        Ok(result)
+    }
+
+    fn list_resources(&self) -> Value {
+        let resources: Vec<Value> = self
+            .connectors
+            .iter()
+            .map(|connector| {
+                json!({
+                    "uri": format!("{}{}", RESOURCE_SCHEME, connector.id),
+                    "name": connector.name,
+                    "description": format!("{} connector ({})", connector.kind, connector.file),
+                    "mimeType": "application/json",
+                })
+            })
+            .collect();
+        json!({ "resources": resources })
+    }
+
+    fn read_resource(&self, params: &Value) -> Result<Value, RpcError> {
+        let uri = params
+            .get("uri")
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "Missing resource uri"))?;
+        let connector = uri
+            .strip_prefix(RESOURCE_SCHEME)
+            .and_then(|id| self.connectors.iter().find(|connector| connector.id == id))
+            .ok_or_else(|| {
+                RpcError::new(RESOURCE_NOT_FOUND, format!("Resource not found: {}", uri))
+            })?;
+
+        Ok(json!({
+            "contents": [{
+                "uri": uri,
+                "mimeType": "application/json",
+                "text": text_of(&connector.document),
+            }]
+        }))
+    }
+}
+
+fn text_of(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+/// Error reply for input that is not JSON.
+pub fn parse_error(err: &serde_json::Error) -> Value {
+    error_response(
+        Value::Null,
+        RpcError::new(PARSE_ERROR, format!("Parse error: {}", err)),
+    )
+}
+
+/// Error reply for JSON that is not a usable JSON-RPC message.
+pub fn invalid_request(message: &str) -> Value {
+    error_response(Value::Null, RpcError::new(INVALID_REQUEST, message))
+}
+
+/// Handle one line of input: a message or, for older clients, a batch.
+pub async fn handle_text(server: &McpServer, text: &str) -> Option<Value> {
+    let message: Value = match serde_json::from_str(text) {
+        Ok(message) => message,
+        Err(err) => return Some(parse_error(&err)),
+    };
+
+    match message {
+        Value::Array(batch) if batch.is_empty() => Some(invalid_request("Empty batch")),
+        Value::Array(batch) => {
+            let mut replies = Vec::new();
+            for message in batch {
+                if let Some(reply) = server.handle(message).await {
+                    replies.push(reply);
+                }
+            }
+            if replies.is_empty() {
+                None
+            } else {
+                Some(Value::Array(replies))
+            }
+        }
+        message => server.handle(message).await,
+    }
+}
+
+/// Serve the connectors in `connectors_dir` on the transport configured in
+/// `[server]`: stdio until the client closes stdin, or Streamable HTTP until
+/// the process is stopped.
+pub fn serve(config: &Config, connectors_dir: &str) -> Result<(), Box<dyn Error>> {
+    let server = Arc::new(McpServer::load(config, connectors_dir)?);
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = match config.server.transport {
+        ServerTransport::Stdio => runtime.block_on(run_stdio(server)),
+        ServerTransport::Http => runtime.block_on(mcp_http::run(server, config.server.clone())),
+    };
+    // A transformation that timed out may still hold a blocking thread, which
+    // would otherwise keep the process from exiting
+    runtime.shutdown_timeout(Duration::from_secs(1));
+    result
+}
+
+async fn run_stdio(server: Arc<McpServer>) -> Result<(), Box<dyn Error>> {
+    // Requests are handled concurrently so a slow tool call does not hold up
+    // pings; one writer keeps replies from interleaving
+    let (replies, mut outgoing) = mpsc::unbounded_channel::<Value>();
+    let writer = tokio::spawn(async move {
+        let mut stdout = tokio::io::stdout();
+        while let Some(reply) = outgoing.recv().await {
+            let mut line = reply.to_string();
+            line.push('\n');
+            if stdout.write_all(line.as_bytes()).await.is_err() || stdout.flush().await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let server = server.clone();
+        let replies = replies.clone();
+        tokio::spawn(async move {
+            if let Some(reply) = handle_text(&server, &line).await {
+                let _ = replies.send(reply);
+            }
+        });
+    }
+
+    // Let requests still in flight finish before exiting
+    drop(replies);
+    writer.await?;
+    Ok(())
+}
@@ -0,0 +1,811 @@
+use crate::config::{Config, DatabaseDefaults};
+use crate::database::{self, Database, QueryLimits};
+use crate::transform::{self, TransformLimits, Transformation};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+// Characters of an error response shown to the model
+const ERROR_EXCERPT_CHARS: usize = 500;
+
+/// A connector document loaded from a connector directory.
+#[derive(Debug, Clone)]
+pub struct Connector {
+    pub id: String,
+    pub file: String,
+    pub name: String,
+    pub kind: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub document: Value,
+}
+
+/// Load every connector in `dir`, whatever output format it was written in.
+/// Files that cannot be parsed are skipped with a warning.
+pub fn load_connectors(dir: &Path) -> Result<Vec<Connector>, Box<dyn Error>> {
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .map_err(|err| format!("Cannot read connector directory {}: {}", dir.display(), err))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("mcp_connector_"))
+        })
+        .collect();
+    paths.sort();
+
+    let mut connectors = Vec::new();
+    for path in paths {
+        match read_document(&path) {
+            Ok(document) => connectors.push(Connector::from_document(&path, document)),
+            Err(err) => log::warn!("Skipping connector {}: {}", path.display(), err),
+        }
+    }
+    Ok(connectors)
+}
+
+/// Read a connector file in any of the output formats.
+pub fn read_document(path: &Path) -> Result<Value, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    Ok(match path.extension().and_then(|ext| ext.to_str()) {
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&contents)?,
+        Some("toml") => toml::from_str(&contents)?,
+        _ => serde_json::from_str(&contents)?,
+    })
+}
+
+impl Connector {
+    fn from_document(path: &Path, document: Value) -> Self {
+        let file = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let text = |key: &str| document.get(key).and_then(Value::as_str).map(String::from);
+
+        // Built-in templates keep the address under a type-specific section
+        let address = ["endpoint", "connection", "source"]
+            .iter()
+            .filter_map(|section| document.get(*section))
+            .find(|section| section.get("host").is_some());
+        let host = address
+            .and_then(|section| section.get("host"))
+            .and_then(Value::as_str)
+            .map(String::from);
+        let port = address
+            .and_then(|section| section.get("port"))
+            .and_then(Value::as_u64)
+            .and_then(|port| u16::try_from(port).ok());
+
+        Connector {
+            id: text("connector_id").unwrap_or_else(|| file.clone()),
+            name: text("name").unwrap_or_else(|| file.clone()),
+            kind: text("type").unwrap_or_else(|| "generic".to_string()),
+            file,
+            host,
+            port,
+            document,
+        }
+    }
+
+    /// Short identifier used in tool names, e.g. `api_10_0_0_5_8080`.
+    /// Connectors without an address, like SQLite files, use their id.
+    fn slug(&self) -> String {
+        let label = match (&self.host, self.port) {
+            (Some(host), Some(port)) => format!("{}_{}_{}", self.kind, host, port),
+            _ => format!("{}_{}", self.kind, self.id),
+        };
+        label
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_lowercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    }
+}
+
+/// A tool offered to MCP clients and what calling it does.
+#[derive(Debug, Clone)]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+    pub action: ToolAction,
+}
+
+#[derive(Debug, Clone)]
+pub enum ToolAction {
+    /// Open a TCP connection to the connector's endpoint and report the result
+    CheckConnection { host: String, port: u16 },
+    /// Send an HTTP request to an API connector
+    HttpRequest(Box<HttpOperation>),
+    /// Run a read-only statement on a database connector
+    QueryDatabase(Box<DatabaseOperation>),
+}
+
+/// How to build the HTTP request for an API connector tool. Operations
+/// learned from traffic fix the method and path; the connector's generic
+/// request tool takes them from the arguments.
+#[derive(Debug, Clone)]
+pub struct HttpOperation {
+    base_url: String,
+    base_path: String,
+    method: Option<String>,
+    path: Option<String>,
+    path_params: Vec<String>,
+    query_params: Vec<String>,
+    authentication: Value,
+    transformations: Vec<Transformation>,
+}
+
+/// Which database tool was called, on which database.
+#[derive(Debug, Clone)]
+pub struct DatabaseOperation {
+    database: Database,
+    kind: DatabaseToolKind,
+    transformations: Vec<Transformation>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DatabaseToolKind {
+    ListTables,
+    DescribeTable,
+    Query,
+}
+
+/// Clients and limits shared by every tool call.
+pub struct ToolContext {
+    http: reqwest::Client,
+    connect_timeout: Duration,
+    max_response_bytes: usize,
+    transform_limits: TransformLimits,
+    query_limits: QueryLimits,
+    databases: HashMap<String, DatabaseDefaults>,
+}
+
+impl ToolContext {
+    pub fn new(config: &Config) -> Result<Self, Box<dyn Error>> {
+        let connect_timeout = Duration::from_millis(config.scan.timeout_ms.max(1));
+        let http = reqwest::Client::builder()
+            .connect_timeout(connect_timeout)
+            .timeout(Duration::from_millis(
+                config.server.request_timeout_ms.max(1),
+            ))
+            // A redirect could lead away from the scanned endpoint; the
+            // client sees it and decides
+            .redirect(reqwest::redirect::Policy::none())
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .build()?;
+
+        Ok(ToolContext {
+            http,
+            connect_timeout,
+            max_response_bytes: config.server.max_response_bytes,
+            transform_limits: TransformLimits::new(config),
+            query_limits: QueryLimits::new(config),
+            databases: config.server.databases.clone(),
+        })
+    }
+}
+
+impl Tool {
+    /// The tool as listed in a `tools/list` response.
+    pub fn definition(&self) -> Value {
+        json!({
+            "name": self.name,
+            "description": self.description,
+            "inputSchema": self.input_schema,
+        })
+    }
+}
+
+/// Tools for a set of connectors.
+pub fn tools_for(connectors: &[Connector]) -> Vec<Tool> {
+    let mut tools = Vec::new();
+    for connector in connectors {
+        // SQLite connectors name a file rather than an address
+        if connector.kind == "database" {
+            tools.extend(database_tools(connector));
+        }
+        let (host, port) = match (&connector.host, connector.port) {
+            (Some(host), Some(port)) => (host, port),
+            _ => continue,
+        };
+
+        tools.push(Tool {
+            name: tool_name(&format!("check_{}", connector.slug())),
+            description: format!(
+                "Check that the {} endpoint {}:{} ({}) accepts connections",
+                connector.kind, host, port, connector.name
+            ),
+            input_schema: json!({ "type": "object", "properties": {} }),
+            action: ToolAction::CheckConnection {
+                host: host.clone(),
+                port,
+            },
+        });
+        if connector.kind == "api" {
+            tools.extend(api_tools(connector, host, port));
+        }
+    }
+    tools
+}
+
+/// Tool names are limited to 64 characters.
+fn tool_name(name: &str) -> String {
+    name.chars().take(64).collect()
+}
+
+/// A generic request tool for an API connector, plus one tool per operation
+/// learned from traffic.
+fn api_tools(connector: &Connector, host: &str, port: u16) -> Vec<Tool> {
+    let endpoint = &connector.document["endpoint"];
+    let scheme = match endpoint.get("protocol").and_then(Value::as_str) {
+        Some("https") => "https",
+        _ => "http",
+    };
+    let authority = if host.contains(':') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let transformations = match transformations_of(connector, "API") {
+        Some(transformations) => transformations,
+        None => return Vec::new(),
+    };
+    let base = HttpOperation {
+        base_url: format!("{}://{}", scheme, authority),
+        base_path: endpoint
+            .get("base_path")
+            .and_then(Value::as_str)
+            .unwrap_or("")
+            .trim_end_matches('/')
+            .to_string(),
+        method: None,
+        path: None,
+        path_params: Vec::new(),
+        query_params: Vec::new(),
+        authentication: endpoint
+            .get("authentication")
+            .cloned()
+            .unwrap_or(Value::Null),
+        transformations,
+    };
+
+    let headers_schema = json!({
+        "type": "object",
+        "description": "Extra request headers",
+        "additionalProperties": { "type": "string" },
+    });
+    let slug = connector.slug();
+    let mut tools = vec![Tool {
+        name: tool_name(&format!("request_{}", slug)),
+        description: format!(
+            "Send an HTTP request to the API at {}{}/ ({})",
+            base.base_url, base.base_path, connector.name
+        ),
+        input_schema: json!({
+            "type": "object",
+            "properties": {
+                "method": {
+                    "type": "string",
+                    "enum": ["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD", "OPTIONS"],
+                    "default": "GET",
+                },
+                "path": {
+                    "type": "string",
+                    "description": format!("Path below {}/", base.base_path),
+                },
+                "query": {
+                    "type": "object",
+                    "description": "Query parameters",
+                    "additionalProperties": { "type": "string" },
+                },
+                "headers": headers_schema,
+                "body": { "description": "JSON request body" },
+            },
+            "required": ["path"],
+        }),
+        action: ToolAction::HttpRequest(Box::new(base.clone())),
+    }];
+
+    let operations = connector.document.get("tools").and_then(Value::as_array);
+    for operation in operations.into_iter().flatten() {
+        let text = |key: &str| operation.get(key).and_then(Value::as_str);
+        let (name, method, path) = match (text("name"), text("method"), text("path")) {
+            (Some(name), Some(method), Some(path)) => (name, method, path),
+            _ => continue,
+        };
+        let path_params = string_list(operation.get("path_params"));
+        let query_params = string_list(operation.get("query_params"));
+
+        let mut properties = Map::new();
+        for param in &path_params {
+            properties.insert(
+                param.clone(),
+                json!({ "type": "string", "description": format!("Path parameter {{{}}}", param) }),
+            );
+        }
+        for param in &query_params {
+            properties.insert(
+                param.clone(),
+                json!({ "type": "string", "description": "Query parameter" }),
+            );
+        }
+        properties.insert("headers".to_string(), headers_schema.clone());
+        if let Some(shape) = operation.get("request_body").filter(|body| !body.is_null()) {
+            properties.insert(
+                "body".to_string(),
+                json!({ "description": format!("JSON request body, observed shape: {}", shape) }),
+            );
+        }
+
+        tools.push(Tool {
+            name: tool_name(&format!("{}_{}", slug, name)),
+            description: format!(
+                "{} on {}",
+                text("description").unwrap_or(name),
+                connector.name
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": properties,
+                "required": path_params,
+            }),
+            action: ToolAction::HttpRequest(Box::new(HttpOperation {
+                method: Some(method.to_uppercase()),
+                path: Some(path.to_string()),
+                path_params,
+                query_params,
+                ..base.clone()
+            })),
+        });
+    }
+    tools
+}
+
+/// Tools listing, describing and querying the database behind a database
+/// connector. Engines other than PostgreSQL, MySQL and SQLite get none.
+fn database_tools(connector: &Connector) -> Vec<Tool> {
+    let database = match Database::from_connection(&connector.document["connection"]) {
+        Ok(database) => database,
+        Err(err) => {
+            log::debug!("No SQL tools for {}: {}", connector.file, err);
+            return Vec::new();
+        }
+    };
+    let transformations = match transformations_of(connector, "SQL") {
+        Some(transformations) => transformations,
+        None => return Vec::new(),
+    };
+    let engine = database.engine;
+    let target = format!(
+        "the {} database {} ({})",
+        engine.label(),
+        database.location(),
+        connector.name
+    );
+    let slug = connector.slug();
+    let operation = |kind| {
+        ToolAction::QueryDatabase(Box::new(DatabaseOperation {
+            database: database.clone(),
+            kind,
+            transformations: transformations.clone(),
+        }))
+    };
+    let schema = json!({
+        "type": "string",
+        "description": "Schema name; defaults to all schemas when listing and the current one otherwise",
+    });
+
+    vec![
+        Tool {
+            name: tool_name(&format!("list_tables_{}", slug)),
+            description: format!("List the schemas, tables and views of {}", target),
+            input_schema: json!({
+                "type": "object",
+                "properties": { "schema": schema },
+            }),
+            action: operation(DatabaseToolKind::ListTables),
+        },
+        Tool {
+            name: tool_name(&format!("describe_table_{}", slug)),
+            description: format!("List the columns of a table in {}", target),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "table": { "type": "string" },
+                    "schema": schema,
+                },
+                "required": ["table"],
+            }),
+            action: operation(DatabaseToolKind::DescribeTable),
+        },
+        Tool {
+            name: tool_name(&format!("query_{}", slug)),
+            description: format!(
+                "Run one read-only SQL statement on {}. Pass values as params \
+                 with {} placeholders instead of writing them into the SQL. \
+                 SELECT ... INTO is rejected. The statement runs with the grants of \
+                 the configured database account, which should be a least-privilege \
+                 read-only role; functions with side effects, such as locks or \
+                 session settings, are not blocked",
+                target,
+                engine.placeholders()
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "sql": { "type": "string" },
+                    "params": {
+                        "type": "array",
+                        "description": "Values for the placeholders, in order",
+                    },
+                    "max_rows": {
+                        "type": "integer",
+                        "minimum": 1,
+                        "description": "Return at most this many rows",
+                    },
+                },
+                "required": ["sql"],
+            }),
+            action: operation(DatabaseToolKind::Query),
+        },
+    ]
+}
+
+/// Parse a connector's `transformations`. Results must never reach the model
+/// untransformed, so a connector whose transformations do not parse offers
+/// no tools that return its data.
+fn transformations_of(connector: &Connector, tools: &str) -> Option<Vec<Transformation>> {
+    let steps = connector
+        .document
+        .get("transformations")
+        .and_then(Value::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    match transform::parse_all(steps) {
+        Ok(transformations) => Some(transformations),
+        Err(err) => {
+            log::warn!(
+                "Skipping {} tools of {}: invalid {}",
+                tools,
+                connector.file,
+                err
+            );
+            None
+        }
+    }
+}
+
+fn string_list(value: Option<&Value>) -> Vec<String> {
+    value
+        .and_then(Value::as_array)
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(Value::as_str)
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Run a tool. `Err` carries a message for the client, reported as a tool
+/// error rather than a protocol error.
+pub async fn call(tool: &Tool, arguments: &Value, context: &ToolContext) -> Result<Value, String> {
+    match &tool.action {
+        ToolAction::CheckConnection { host, port } => {
+            let started = Instant::now();
+            let address = format!("{}:{}", host, port);
+            match tokio::time::timeout(context.connect_timeout, TcpStream::connect(&address)).await
+            {
+                Ok(Ok(_)) => Ok(json!({
+                    "reachable": true,
+                    "address": address,
+                    "latency_ms": started.elapsed().as_millis() as u64,
+                })),
+                Ok(Err(err)) => Err(format!("{} is not reachable: {}", address, err)),
+                Err(_) => Err(format!(
+                    "{} did not answer within {} ms",
+                    address,
+                    context.connect_timeout.as_millis()
+                )),
+            }
+        }
+        ToolAction::HttpRequest(operation) => send_request(operation, arguments, context).await,
+        ToolAction::QueryDatabase(operation) => query_database(operation, arguments, context).await,
+    }
+}
+
+async fn query_database(
+    operation: &DatabaseOperation,
+    arguments: &Value,
+    context: &ToolContext,
+) -> Result<Value, String> {
+    let text = |name: &str| {
+        arguments
+            .get(name)
+            .filter(|value| !value.is_null())
+            .map(argument_text)
+    };
+    let request = match operation.kind {
+        DatabaseToolKind::ListTables => database::Request::ListTables {
+            schema: text("schema"),
+        },
+        DatabaseToolKind::DescribeTable => database::Request::DescribeTable {
+            schema: text("schema"),
+            table: text("table").ok_or("Missing argument table")?,
+        },
+        DatabaseToolKind::Query => database::Request::Query {
+            sql: text("sql").ok_or("Missing argument sql")?,
+            params: match arguments.get("params") {
+                None | Some(Value::Null) => Vec::new(),
+                Some(Value::Array(params)) => params.clone(),
+                Some(_) => return Err("params must be an array".to_string()),
+            },
+            max_rows: arguments
+                .get("max_rows")
+                .and_then(Value::as_u64)
+                .map(|rows| rows.max(1) as usize),
+        },
+    };
+
+    let database = operation.database.with_defaults(&context.databases)?;
+    let mut result = database::execute(&database, request, &context.query_limits).await?;
+    let rows = result["rows"].take();
+    result["rows"] =
+        transform::apply_with_timeout(&operation.transformations, rows, &context.transform_limits)
+            .await
+            .map_err(|err| format!("Transforming the query result failed: {}", err))?;
+    Ok(result)
+}
+
+async fn send_request(
+    operation: &HttpOperation,
+    arguments: &Value,
+    context: &ToolContext,
+) -> Result<Value, String> {
+    let method = match &operation.method {
+        Some(method) => method.clone(),
+        None => arguments
+            .get("method")
+            .map(argument_text)
+            .unwrap_or_else(|| "GET".to_string())
+            .to_uppercase(),
+    };
+    let method = reqwest::Method::from_bytes(method.as_bytes())
+        .map_err(|_| format!("Invalid HTTP method {}", method))?;
+    let path = match &operation.path {
+        Some(template) => fill_path(template, &operation.path_params, arguments)?,
+        None => {
+            let path = arguments
+                .get("path")
+                .map(argument_text)
+                .ok_or("Missing argument path")?;
+            format!("{}/{}", operation.base_path, path.trim_start_matches('/'))
+        }
+    };
+    let url = format!("{}{}", operation.base_url, path);
+
+    let mut request = context.http.request(method.clone(), &url);
+    let mut query: Vec<(String, String)> = operation
+        .query_params
+        .iter()
+        .filter_map(|name| {
+            arguments
+                .get(name)
+                .filter(|value| !value.is_null())
+                .map(|value| (name.clone(), argument_text(value)))
+        })
+        .collect();
+    if let Some(extra) = arguments.get("query").and_then(Value::as_object) {
+        query.extend(
+            extra
+                .iter()
+                .map(|(name, value)| (name.clone(), argument_text(value))),
+        );
+    }
+    if !query.is_empty() {
+        request = request.query(&query);
+    }
+    if let Some(headers) = arguments.get("headers").and_then(Value::as_object) {
+        for (name, value) in headers {
+            request = request.header(name.as_str(), argument_text(value));
+        }
+    }
+    if let Some(body) = arguments.get("body").filter(|body| !body.is_null()) {
+        request = request.json(body);
+    }
+    request = authenticate(request, &operation.authentication)?;
+
+    log::debug!("Sending {} {}", method, url);
+    let mut response = request
+        .send()
+        .await
+        .map_err(|err| format!("{} {} failed: {}", method, url, err))?;
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+
+    // Read no more than the limit, whatever the server claims
+    let limit = context.max_response_bytes;
+    let too_large = || {
+        format!(
+            "Response from {} exceeds server.max_response_bytes ({} bytes)",
+            url, limit
+        )
+    };
+    if response
+        .content_length()
+        .is_some_and(|length| length > limit as u64)
+    {
+        return Err(too_large());
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|err| format!("Reading the response from {} failed: {}", url, err))?
+    {
+        if body.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let body = if body.is_empty() {
+        Value::Null
+    } else {
+        serde_json::from_slice(&body)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()))
+    };
+    if !status.is_success() {
+        // Error bodies can carry the same fields the transformations remove,
+        // so they are transformed too and only an excerpt is returned
+        let detail = match transform::apply_with_timeout(
+            &operation.transformations,
+            body,
+            &context.transform_limits,
+        )
+        .await
+        {
+            Ok(Value::Null) => "empty response".to_string(),
+            Ok(Value::String(text)) => excerpt(&text),
+            Ok(other) => excerpt(&other.to_string()),
+            Err(_) => {
+                "response body withheld, the connector's transformations failed on it".to_string()
+            }
+        };
+        return Err(format!(
+            "{} {} returned {}: {}",
+            method, url, status, detail
+        ));
+    }
+
+    let body =
+        transform::apply_with_timeout(&operation.transformations, body, &context.transform_limits)
+            .await
+            .map_err(|err| format!("Transforming the response from {} failed: {}", url, err))?;
+    Ok(json!({
+        "status": status.as_u16(),
+        "content_type": content_type,
+        "body": body,
+    }))
+}
+
+/// The first `ERROR_EXCERPT_CHARS` characters of an error body.
+fn excerpt(text: &str) -> String {
+    match text.char_indices().nth(ERROR_EXCERPT_CHARS) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Apply the connector's `authentication` block.
+fn authenticate(
+    request: reqwest::RequestBuilder,
+    authentication: &Value,
+) -> Result<reqwest::RequestBuilder, String> {
+    let kind = authentication
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or("none");
+    let param = |name: &str| auth_param(authentication, name);
+
+    Ok(match kind {
+        "none" => request,
+        "basic" => request.basic_auth(param("username")?, Some(param("password")?)),
+        "bearer" => request.bearer_auth(param("token")?),
+        "api_key" => {
+            let key = param("value")?;
+            let name = param("name").unwrap_or_else(|_| "X-API-Key".to_string());
+            match param("in").as_deref() {
+                Ok("query") => request.query(&[(name, key)]),
+                _ => request.header(name.as_str(), key),
+            }
+        }
+        other => return Err(format!("Unsupported authentication type '{}'", other)),
+    })
+}
+
+/// A parameter of an `authentication` block. A value written as `${NAME}` is
+/// read from the environment, so secrets can stay out of connector files.
+pub fn auth_param(authentication: &Value, name: &str) -> Result<String, String> {
+    let kind = authentication
+        .get("type")
+        .and_then(Value::as_str)
+        .unwrap_or("none");
+    let value = authentication
+        .get("params")
+        .and_then(|params| params.get(name))
+        .and_then(Value::as_str)
+        .ok_or_else(|| format!("{} authentication needs params.{}", kind, name))?;
+    resolve_env(value)
+}
+
+/// A setting written as `${NAME}` is read from the environment; anything else
+/// is used as is.
+pub fn resolve_env(value: &str) -> Result<String, String> {
+    match value
+        .strip_prefix("${")
+        .and_then(|rest| rest.strip_suffix('}'))
+    {
+        Some(variable) => {
+            env::var(variable).map_err(|_| format!("Environment variable {} is not set", variable))
+        }
+        None => Ok(value.to_string()),
+    }
+}
+
+/// Substitute `{name}` placeholders with percent-encoded arguments.
+fn fill_path(template: &str, params: &[String], arguments: &Value) -> Result<String, String> {
+    let mut path = template.to_string();
+    for name in params {
+        let value = arguments
+            .get(name)
+            .filter(|value| !value.is_null())
+            .ok_or_else(|| format!("Missing argument {}", name))?;
+        path = path.replace(
+            &format!("{{{}}}", name),
+            &encode_segment(&argument_text(value)),
+        );
+    }
+    Ok(path)
+}
+
+fn encode_segment(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| {
+            if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+                (byte as char).to_string()
+            } else {
+                format!("%{:02X}", byte)
+            }
+        })
+        .collect()
+}
+
+fn argument_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,409 @@
+use crate::scanner::{combined_confidence, EndpointInfo, Protocol, ScanResults};
+use std::collections::BTreeMap;
+
+// Metadata holding comma-separated lists; merged records keep the union of
+// every source's values instead of picking one
+const LIST_METADATA: [&str; 5] = [
+    "detected_by",
+    "http_hosts",
+    "dns_names",
+    "tls_sni",
+    "hostname",
+];
+
+/// Discovery methods recorded in an endpoint's `detected_by` metadata.
+pub fn detected_by(endpoint: &EndpointInfo) -> Vec<&str> {
+    endpoint
+        .metadata
+        .get("detected_by")
+        .map(|methods| {
+            methods
+                .split(',')
+                .map(str::trim)
+                .filter(|method| !method.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Transport the endpoint listens on, used to tell a TCP and a UDP service on
+/// the same port apart.
+pub fn transport(endpoint: &EndpointInfo) -> String {
+    match endpoint.metadata.get("transport") {
+        Some(transport) => transport.to_lowercase(),
+        None if matches!(endpoint.protocol, Protocol::UDP) => "udp".to_string(),
+        None => "tcp".to_string(),
+    }
+}
+
+/// Combine records of the same service found by several discovery methods,
+/// keyed on address, port and transport, then group each host's ports into
+/// logical services. Results come out in a stable order.
+pub fn merge_endpoints(endpoints: ScanResults) -> ScanResults {
+    let found = endpoints.len();
+    let mut merged: BTreeMap<(String, u16, String), EndpointInfo> = BTreeMap::new();
+    for endpoint in endpoints {
+        let key = (
+            endpoint.address.to_lowercase(),
+            endpoint.port,
+            transport(&endpoint),
+        );
+        let endpoint = match merged.remove(&key) {
+            Some(existing) => merge_pair(existing, endpoint),
+            None => endpoint,
+        };
+        merged.insert(key, endpoint);
+    }
+
+    let mut results: ScanResults = merged.into_values().collect();
+    if results.len() < found {
+        log::info!(
+            "Merged {} discovered records into {} endpoints",
+            found,
+            results.len()
+        );
+    }
+    group_services(&mut results);
+    results
+}
+
+fn merge_pair(first: EndpointInfo, second: EndpointInfo) -> EndpointInfo {
+    // The record with the more confident classification is the base; the
+    // other contributes metadata it does not have
+    let (mut base, other) = if second.confidence > first.confidence {
+        (second, first)
+    } else {
+        (first, second)
+    };
+
+    // Agreeing sources strengthen the classification; a conflicting one is
+    // outweighed and its evidence dropped
+    if base.endpoint_type.label() == other.endpoint_type.label() {
+        for item in other.evidence {
+            if !base.evidence.contains(&item) {
+                base.evidence.push(item);
+            }
+        }
+        base.confidence = combined_confidence(&base.evidence);
+    } else {
+        log::debug!(
+            "Conflicting classification for {}:{}: {} ({:.2}, {}) over {} ({:.2}, {})",
+            base.address,
+            base.port,
+            base.endpoint_type.label(),
+            base.confidence,
+            detected_by(&base).join(","),
+            other.endpoint_type.label(),
+            other.confidence,
+            detected_by(&other).join(",")
+        );
+    }
+
+    // A generic transport says less than an application protocol seen by any source
+    if matches!(base.protocol, Protocol::TCP | Protocol::UDP)
+        && !matches!(other.protocol, Protocol::TCP | Protocol::UDP)
+    {
+        base.protocol = other.protocol.clone();
+    }
+
+    for (key, value) in other.metadata {
+        if LIST_METADATA.contains(&key.as_str()) {
+            let combined = match base.metadata.get(&key) {
+                Some(existing) => join_unique(existing, &value),
+                None => value,
+            };
+            base.metadata.insert(key, combined);
+        } else {
+            base.metadata.entry(key).or_insert(value);
+        }
+    }
+
+    base.discovered_timestamp = base.discovered_timestamp.min(other.discovered_timestamp);
+    if base.api_model.is_none() {
+        base.api_model = other.api_model;
+    }
+    base
+}
+
+fn join_unique(existing: &str, added: &str) -> String {
+    let mut values: Vec<&str> = existing
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .collect();
+    for value in added.split(',').map(str::trim) {
+        if !value.is_empty() && !values.contains(&value) {
+            values.push(value);
+        }
+    }
+    values.join(",")
+}
+
+/// Ports that one service commonly listens on together, used to group ports
+/// no source named a service for.
+const KNOWN_PORT_GROUPS: &[(&str, &[u16])] = &[
+    ("http", &[80, 443]),
+    ("http-alt", &[8080, 8443]),
+    ("amqp", &[5671, 5672, 15672]),
+    ("mqtt", &[1883, 8883]),
+    ("elasticsearch", &[9200, 9300]),
+    ("ldap", &[389, 636]),
+    ("imap", &[143, 993]),
+    ("pop3", &[110, 995]),
+    ("docker", &[2375, 2376]),
+];
+
+/// Tag ports of one host that belong to the same service, e.g. HTTP and
+/// HTTPS of one web server, with a shared `service_group` and the group's
+/// `service_ports`. Ports are grouped by service name when a source reported
+/// one, otherwise by known port pairs; other ports stay ungrouped.
+fn group_services(results: &mut ScanResults) {
+    let mut groups: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (index, endpoint) in results.iter().enumerate() {
+        let identity = endpoint
+            .metadata
+            .get("service_name")
+            .or_else(|| endpoint.metadata.get("product"))
+            .map(|name| name.to_lowercase())
+            .or_else(|| {
+                KNOWN_PORT_GROUPS
+                    .iter()
+                    .find(|(_, ports)| ports.contains(&endpoint.port))
+                    .map(|(name, _)| name.to_string())
+            });
+        if let Some(identity) = identity {
+            let group = format!("{}/{}", endpoint.address.to_lowercase(), identity);
+            groups.entry(group).or_default().push(index);
+        }
+    }
+
+    for (group, members) in groups {
+        if members.len() < 2 {
+            continue;
+        }
+        let mut ports: Vec<u16> = members.iter().map(|index| results[*index].port).collect();
+        ports.sort_unstable();
+        ports.dedup();
+        let ports = ports
+            .iter()
+            .map(u16::to_string)
+            .collect::<Vec<_>>()
+            .join(",");
+
+        for index in members {
+            let metadata = &mut results[index].metadata;
+            metadata.insert("service_group".to_string(), group.clone());
+            metadata.insert("service_ports".to_string(), ports.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::{EndpointType, Evidence, EvidenceKind};
+    use std::collections::HashMap;
+
+    fn endpoint(
+        address: &str,
+        port: u16,
+        endpoint_type: EndpointType,
+        protocol: Protocol,
+        source: &str,
+        evidence: (EvidenceKind, f64),
+    ) -> EndpointInfo {
+        let mut metadata = HashMap::new();
+        metadata.insert("detected_by".to_string(), source.to_string());
+        let mut endpoint = EndpointInfo {
+            endpoint_type,
+            address: address.to_string(),
+            port,
+            protocol,
+            metadata,
+            discovered_timestamp: 100,
+            api_model: None,
+            confidence: 0.0,
+            evidence: Vec::new(),
+        };
+        endpoint.add_evidence(Evidence::new(
+            evidence.0,
+            source,
+            format!("seen by {}", source),
+            evidence.1,
+        ));
+        endpoint
+    }
+
+    fn web(address: &str, port: u16) -> EndpointInfo {
+        endpoint(
+            address,
+            port,
+            EndpointType::API,
+            Protocol::HTTP,
+            "port_scan",
+            (EvidenceKind::PortHeuristic, 0.3),
+        )
+    }
+
+    #[test]
+    fn merge_combines_sources_of_one_service() {
+        let mut scanned = endpoint(
+            "10.0.0.5",
+            8080,
+            EndpointType::API,
+            Protocol::TCP,
+            "port_scan",
+            (EvidenceKind::PortHeuristic, 0.3),
+        );
+        scanned
+            .metadata
+            .insert("dns_names".to_string(), "a.local".to_string());
+        scanned
+            .metadata
+            .insert("product".to_string(), "nginx".to_string());
+        scanned.discovered_timestamp = 50;
+        let mut announced = endpoint(
+            "10.0.0.5",
+            8080,
+            EndpointType::API,
+            Protocol::HTTP,
+            "mdns",
+            (EvidenceKind::ServiceAnnouncement, 0.8),
+        );
+        announced
+            .metadata
+            .insert("dns_names".to_string(), "b.local,a.local".to_string());
+        announced
+            .metadata
+            .insert("product".to_string(), "envoy".to_string());
+
+        let merged = merge_endpoints(vec![scanned, announced]);
+
+        assert_eq!(merged.len(), 1);
+        let endpoint = &merged[0];
+        assert_eq!(detected_by(endpoint), vec!["mdns", "port_scan"]);
+        assert_eq!(endpoint.metadata["dns_names"], "b.local,a.local");
+        // Single-valued metadata comes from the more confident record
+        assert_eq!(endpoint.metadata["product"], "envoy");
+        assert!(matches!(endpoint.protocol, Protocol::HTTP));
+        assert_eq!(endpoint.evidence.len(), 2);
+        assert!((endpoint.confidence - 0.86).abs() < 1e-9);
+        assert_eq!(endpoint.discovered_timestamp, 50);
+    }
+
+    #[test]
+    fn merge_keeps_the_more_confident_classification() {
+        let mut guessed = endpoint(
+            "10.0.0.5",
+            9200,
+            EndpointType::Database,
+            Protocol::TCP,
+            "port_scan",
+            (EvidenceKind::PortHeuristic, 0.3),
+        );
+        guessed
+            .metadata
+            .insert("hostname".to_string(), "search-1".to_string());
+        let observed = endpoint(
+            "10.0.0.5",
+            9200,
+            EndpointType::Log,
+            Protocol::HTTP,
+            "passive",
+            (EvidenceKind::TrafficObserved, 0.9),
+        );
+
+        // Order of the inputs does not matter
+        for inputs in [
+            vec![guessed.clone(), observed.clone()],
+            vec![observed.clone(), guessed.clone()],
+        ] {
+            let merged = merge_endpoints(inputs);
+            assert_eq!(merged.len(), 1);
+            let endpoint = &merged[0];
+            assert_eq!(endpoint.endpoint_type.label(), "log");
+            assert_eq!(endpoint.evidence.len(), 1);
+            assert_eq!(endpoint.evidence[0].kind, EvidenceKind::TrafficObserved);
+            assert!((endpoint.confidence - 0.9).abs() < 1e-9);
+            assert_eq!(endpoint.metadata["hostname"], "search-1");
+            assert_eq!(detected_by(endpoint), vec!["passive", "port_scan"]);
+        }
+    }
+
+    #[test]
+    fn merge_keeps_transports_apart() {
+        let tcp = endpoint(
+            "10.0.0.53",
+            53,
+            EndpointType::Other("DNS".to_string()),
+            Protocol::TCP,
+            "port_scan",
+            (EvidenceKind::PortHeuristic, 0.3),
+        );
+        let udp = endpoint(
+            "10.0.0.53",
+            53,
+            EndpointType::Other("DNS".to_string()),
+            Protocol::UDP,
+            "passive",
+            (EvidenceKind::TrafficObserved, 0.9),
+        );
+        let mut tagged = tcp.clone();
+        tagged
+            .metadata
+            .insert("transport".to_string(), "UDP".to_string());
+
+        let merged = merge_endpoints(vec![tcp, udp, tagged]);
+
+        let transports: Vec<String> = merged.iter().map(transport).collect();
+        assert_eq!(transports, vec!["tcp", "udp"]);
+        assert_eq!(detected_by(&merged[1]), vec!["passive", "port_scan"]);
+    }
+
+    #[test]
+    fn merge_groups_ports_of_one_service() {
+        let mut first = web("10.0.0.5", 9000);
+        first
+            .metadata
+            .insert("service_name".to_string(), "MinIO".to_string());
+        let mut second = web("10.0.0.5", 9001);
+        second
+            .metadata
+            .insert("service_name".to_string(), "minio".to_string());
+
+        let merged = merge_endpoints(vec![
+            web("10.0.0.5", 443),
+            web("10.0.0.5", 80),
+            web("10.0.0.6", 80),
+            web("10.0.0.5", 22),
+            first,
+            second,
+        ]);
+        let group = |address: &str, port: u16| {
+            let endpoint = merged
+                .iter()
+                .find(|endpoint| endpoint.address == address && endpoint.port == port)
+                .unwrap();
+            (
+                endpoint.metadata.get("service_group").cloned(),
+                endpoint.metadata.get("service_ports").cloned(),
+            )
+        };
+
+        let http = (
+            Some("10.0.0.5/http".to_string()),
+            Some("80,443".to_string()),
+        );
+        assert_eq!(group("10.0.0.5", 80), http);
+        assert_eq!(group("10.0.0.5", 443), http);
+        let minio = (
+            Some("10.0.0.5/minio".to_string()),
+            Some("9000,9001".to_string()),
+        );
+        assert_eq!(group("10.0.0.5", 9000), minio);
+        assert_eq!(group("10.0.0.5", 9001), minio);
+        // A lone port, or the same port on another host, is not grouped
+        assert_eq!(group("10.0.0.5", 22), (None, None));
+        assert_eq!(group("10.0.0.6", 80), (None, None));
+    }
+}
@@ -0,0 +1,1250 @@
+use crate::api_learning;
+use crate::scanner::{
+    classify_port, EndpointInfo, EndpointType, Evidence, EvidenceKind, Protocol, ScanResults,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+// Only the start of each stream is needed to identify protocols and request lines
+const MAX_STREAM_BYTES: usize = 256 * 1024;
+// Keep metadata lists readable
+const MAX_LISTED_VALUES: usize = 50;
+
+const LINKTYPE_NULL: u32 = 0;
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+
+/// A captured frame together with its capture time and link-layer type.
+struct Frame<'a> {
+    timestamp: u64,
+    linktype: u32,
+    data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+struct Peer {
+    ip: IpAddr,
+    port: u16,
+}
+
+/// One direction of a TCP connection, reassembled by sequence number.
+#[derive(Default)]
+struct HalfStream {
+    initial_seq: Option<u32>,
+    segments: BTreeMap<u32, Vec<u8>>,
+    buffered: usize,
+}
+
+impl HalfStream {
+    fn add_segment(&mut self, seq: u32, syn: bool, payload: &[u8]) {
+        if syn {
+            // The SYN consumes one sequence number
+            self.initial_seq = Some(seq.wrapping_add(1));
+            return;
+        }
+        if payload.is_empty() || self.buffered >= MAX_STREAM_BYTES {
+            return;
+        }
+        if self.initial_seq.is_none() {
+            // Capture started mid-connection
+            self.initial_seq = Some(seq);
+        }
+        let offset = seq.wrapping_sub(self.initial_seq.unwrap_or(seq));
+        if offset as usize > MAX_STREAM_BYTES {
+            return;
+        }
+        self.buffered += payload.len();
+        self.segments
+            .entry(offset)
+            .or_insert_with(|| payload.to_vec());
+    }
+
+    /// Concatenate the contiguous prefix of the stream, dropping retransmissions.
+    fn assemble(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (offset, segment) in &self.segments {
+            let offset = *offset as usize;
+            if offset > data.len() {
+                break;
+            }
+            let overlap = data.len() - offset;
+            if overlap < segment.len() {
+                data.extend_from_slice(&segment[overlap..]);
+            }
+        }
+        data
+    }
+}
+
+/// A TCP connection keyed by (client, server).
+struct Flow {
+    client: Peer,
+    server: Peer,
+    // true when the server side was confirmed by a SYN/SYN-ACK exchange
+    handshake_seen: bool,
+    to_server: HalfStream,
+    to_client: HalfStream,
+    first_seen: u64,
+}
+
+/// Everything observed about one server endpoint across all flows.
+#[derive(Default)]
+struct Observation {
+    protocol: Option<&'static str>,
+    first_seen: u64,
+    flows: usize,
+    clients: BTreeSet<IpAddr>,
+    http_hosts: BTreeSet<String>,
+    http_methods: BTreeSet<String>,
+    http_paths: BTreeSet<String>,
+    http_server: Option<String>,
+    /// Credential schemes seen on HTTP requests
+    http_auth: BTreeSet<String>,
+    tls_sni: BTreeSet<String>,
+    mqtt_topics: BTreeSet<String>,
+    sql_dialect: Option<&'static str>,
+    server_version: Option<String>,
+    databases: BTreeSet<String>,
+    dns_queries: usize,
+    transport: &'static str,
+}
+
+/// Read pcap or pcapng captures and derive endpoints from the observed traffic.
+pub fn analyze_files<P: AsRef<Path>>(paths: &[P]) -> Result<ScanResults, Box<dyn Error>> {
+    let mut analyzer = Analyzer::default();
+
+    for path in paths {
+        let path = path.as_ref();
+        log::info!("Reading packet capture {}", path.display());
+        let bytes = fs::read(path)?;
+        let frames = read_capture(&bytes)?;
+        log::info!("Read {} packets from {}", frames.len(), path.display());
+
+        for frame in &frames {
+            analyzer.process_frame(frame);
+        }
+    }
+
+    Ok(analyzer.finish())
+}
+
+fn read_capture(bytes: &[u8]) -> Result<Vec<Frame<'_>>, Box<dyn Error>> {
+    if bytes.len() < 4 {
+        return Err("Capture file is too short".into());
+    }
+    match &bytes[0..4] {
+        [0x0a, 0x0d, 0x0d, 0x0a] => read_pcapng(bytes),
+        _ => read_pcap(bytes),
+    }
+}
+
+fn read_pcap(bytes: &[u8]) -> Result<Vec<Frame<'_>>, Box<dyn Error>> {
+    if bytes.len() < 24 {
+        return Err("pcap file header is truncated".into());
+    }
+    // Microsecond and nanosecond variants differ only in the sub-second field,
+    // which is not needed for first-seen timestamps
+    let little_endian = match bytes[0..4] {
+        [0xd4, 0xc3, 0xb2, 0xa1] | [0x4d, 0x3c, 0xb2, 0xa1] => true,
+        [0xa1, 0xb2, 0xc3, 0xd4] | [0xa1, 0xb2, 0x3c, 0x4d] => false,
+        _ => return Err("Unrecognised capture format (expected pcap or pcapng)".into()),
+    };
+    let field = |offset: usize| read_u32(bytes, offset, little_endian);
+
+    let linktype = field(20).unwrap_or(LINKTYPE_ETHERNET) & 0x0fff_ffff;
+    let mut frames = Vec::new();
+    let mut offset = 24;
+
+    while offset + 16 <= bytes.len() {
+        let ts_sec = field(offset).unwrap_or(0) as u64;
+        let caplen = field(offset + 8).unwrap_or(0) as usize;
+        let start = offset + 16;
+        let end = start + caplen;
+        if end > bytes.len() {
+            log::warn!("pcap file ends with a truncated packet");
+            break;
+        }
+        frames.push(Frame {
+            timestamp: ts_sec,
+            linktype,
+            data: &bytes[start..end],
+        });
+        offset = end;
+    }
+
+    Ok(frames)
+}
+
+fn read_pcapng(bytes: &[u8]) -> Result<Vec<Frame<'_>>, Box<dyn Error>> {
+    let mut frames = Vec::new();
+    let mut little_endian = true;
+    // (linktype, timestamp units per second) for each interface in the current section
+    let mut interfaces: Vec<(u32, u64)> = Vec::new();
+    let mut offset = 0;
+
+    while offset + 12 <= bytes.len() {
+        let block_type = read_u32(bytes, offset, little_endian).unwrap_or(0);
+
+        if block_type == 0x0a0d_0d0a {
+            // Section header: the byte-order magic decides endianness for the section
+            little_endian = match bytes.get(offset + 8..offset + 12) {
+                Some([0x4d, 0x3c, 0x2b, 0x1a]) => true,
+                Some([0x1a, 0x2b, 0x3c, 0x4d]) => false,
+                _ => return Err("Invalid pcapng byte-order magic".into()),
+            };
+            interfaces.clear();
+        }
+
+        let block_len = read_u32(bytes, offset + 4, little_endian).unwrap_or(0) as usize;
+        if block_len < 12 || offset + block_len > bytes.len() {
+            log::warn!("pcapng file ends with a truncated block");
+            break;
+        }
+        let body = &bytes[offset + 8..offset + block_len - 4];
+
+        match block_type {
+            // Interface description block
+            1 if body.len() >= 8 => {
+                let linktype = read_u16(body, 0, little_endian).unwrap_or(1) as u32;
+                let resolution = pcapng_ts_resolution(&body[8..], little_endian);
+                interfaces.push((linktype, resolution));
+            }
+            // Enhanced packet block
+            6 if body.len() >= 20 => {
+                let interface = read_u32(body, 0, little_endian).unwrap_or(0) as usize;
+                let ts_high = read_u32(body, 4, little_endian).unwrap_or(0) as u64;
+                let ts_low = read_u32(body, 8, little_endian).unwrap_or(0) as u64;
+                let caplen = read_u32(body, 12, little_endian).unwrap_or(0) as usize;
+                let (linktype, resolution) =
+                    interfaces.get(interface).copied().unwrap_or((1, 1_000_000));
+                if let Some(data) = body.get(20..20 + caplen) {
+                    frames.push(Frame {
+                        timestamp: ((ts_high << 32) | ts_low) / resolution.max(1),
+                        linktype,
+                        data,
+                    });
+                }
+            }
+            // Simple packet block, always from the first interface
+            3 if body.len() >= 4 => {
+                let (linktype, _) = interfaces.first().copied().unwrap_or((1, 1_000_000));
+                let origlen = read_u32(body, 0, little_endian).unwrap_or(0) as usize;
+                let caplen = origlen.min(body.len() - 4);
+                frames.push(Frame {
+                    timestamp: 0,
+                    linktype,
+                    data: &body[4..4 + caplen],
+                });
+            }
+            _ => {}
+        }
+
+        offset += block_len;
+    }
+
+    Ok(frames)
+}
+
+/// Units per second from the `if_tsresol` option, defaulting to microseconds.
+fn pcapng_ts_resolution(options: &[u8], little_endian: bool) -> u64 {
+    let mut offset = 0;
+    while offset + 4 <= options.len() {
+        let code = read_u16(options, offset, little_endian).unwrap_or(0);
+        let len = read_u16(options, offset + 2, little_endian).unwrap_or(0) as usize;
+        if code == 0 {
+            break;
+        }
+        if code == 9 && len >= 1 {
+            if let Some(value) = options.get(offset + 4) {
+                let exponent = u32::from(value & 0x7f);
+                return if value & 0x80 == 0 {
+                    10u64.checked_pow(exponent).unwrap_or(1_000_000)
+                } else {
+                    2u64.checked_pow(exponent).unwrap_or(1_000_000)
+                };
+            }
+        }
+        offset += 4 + ((len + 3) & !3);
+    }
+    1_000_000
+}
+
+#[derive(Default)]
+struct Analyzer {
+    flows: HashMap<(Peer, Peer), Flow>,
+    // Flows keyed by either direction, pointing at the (client, server) key
+    flow_index: HashMap<(Peer, Peer), (Peer, Peer)>,
+    udp_servers: HashMap<Peer, Observation>,
+    dns_names: HashMap<IpAddr, BTreeSet<String>>,
+}
+
+impl Analyzer {
+    fn process_frame(&mut self, frame: &Frame<'_>) {
+        let packet = match strip_link_layer(frame.linktype, frame.data) {
+            Some(packet) => packet,
+            None => return,
+        };
+        let (src, dst, transport, payload) = match parse_ip(packet) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+
+        match transport {
+            6 => self.process_tcp(src, dst, payload, frame.timestamp),
+            17 => self.process_udp(src, dst, payload, frame.timestamp),
+            _ => {}
+        }
+    }
+
+    fn process_tcp(&mut self, src_ip: IpAddr, dst_ip: IpAddr, segment: &[u8], timestamp: u64) {
+        if segment.len() < 20 {
+            return;
+        }
+        let src = Peer {
+            ip: src_ip,
+            port: u16::from_be_bytes([segment[0], segment[1]]),
+        };
+        let dst = Peer {
+            ip: dst_ip,
+            port: u16::from_be_bytes([segment[2], segment[3]]),
+        };
+        let seq = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+        let header_len = ((segment[12] >> 4) as usize) * 4;
+        let flags = segment[13];
+        let syn = flags & 0x02 != 0;
+        let ack = flags & 0x10 != 0;
+        let rst = flags & 0x04 != 0;
+        let payload = segment.get(header_len..).unwrap_or(&[]);
+
+        if rst && payload.is_empty() {
+            return;
+        }
+
+        let key = match self.flow_index.get(&(src, dst)) {
+            Some(key) => *key,
+            None => {
+                // A bare SYN comes from the client, a SYN-ACK from the server.
+                // Without a handshake assume the lower (well-known) port is the server.
+                let (client, server, handshake_seen) = if syn && !ack {
+                    (src, dst, true)
+                } else if syn && ack {
+                    (dst, src, true)
+                } else if src.port < dst.port {
+                    (dst, src, false)
+                } else {
+                    (src, dst, false)
+                };
+                let key = (client, server);
+                self.flow_index.insert((client, server), key);
+                self.flow_index.insert((server, client), key);
+                self.flows.insert(
+                    key,
+                    Flow {
+                        client,
+                        server,
+                        handshake_seen,
+                        to_server: HalfStream::default(),
+                        to_client: HalfStream::default(),
+                        first_seen: timestamp,
+                    },
+                );
+                key
+            }
+        };
+
+        if let Some(flow) = self.flows.get_mut(&key) {
+            if syn {
+                flow.handshake_seen = true;
+            }
+            if src == flow.client {
+                flow.to_server.add_segment(seq, syn, payload);
+            } else {
+                flow.to_client.add_segment(seq, syn, payload);
+            }
+        }
+    }
+
+    fn process_udp(&mut self, src_ip: IpAddr, dst_ip: IpAddr, datagram: &[u8], timestamp: u64) {
+        if datagram.len() < 8 {
+            return;
+        }
+        let src_port = u16::from_be_bytes([datagram[0], datagram[1]]);
+        let dst_port = u16::from_be_bytes([datagram[2], datagram[3]]);
+        let payload = &datagram[8..];
+
+        if src_port == 53 || src_port == 5353 {
+            let server = Peer {
+                ip: src_ip,
+                port: src_port,
+            };
+            let answers = parse_dns_answers(payload);
+            for (name, ip) in answers {
+                self.dns_names.entry(ip).or_default().insert(name);
+            }
+            let observation = self
+                .udp_servers
+                .entry(server)
+                .or_insert_with(|| Observation {
+                    first_seen: timestamp,
+                    transport: "udp",
+                    ..Observation::default()
+                });
+            observation.protocol = Some("dns");
+            observation.dns_queries += 1;
+            observation.clients.insert(dst_ip);
+        } else if dst_port == 514 {
+            let server = Peer {
+                ip: dst_ip,
+                port: dst_port,
+            };
+            let observation = self
+                .udp_servers
+                .entry(server)
+                .or_insert_with(|| Observation {
+                    first_seen: timestamp,
+                    transport: "udp",
+                    ..Observation::default()
+                });
+            observation.protocol = Some("syslog");
+            observation.clients.insert(src_ip);
+        }
+    }
+
+    fn finish(self) -> ScanResults {
+        let mut observations: BTreeMap<Peer, Observation> = BTreeMap::new();
+
+        for flow in self.flows.values() {
+            let to_server = flow.to_server.assemble();
+            let to_client = flow.to_client.assemble();
+
+            // Ignore connection attempts that never carried data or completed a handshake
+            if to_server.is_empty() && to_client.is_empty() && !flow.handshake_seen {
+                continue;
+            }
+
+            let observation = observations
+                .entry(flow.server)
+                .or_insert_with(|| Observation {
+                    first_seen: flow.first_seen,
+                    transport: "tcp",
+                    ..Observation::default()
+                });
+            observation.first_seen = observation.first_seen.min(flow.first_seen);
+            observation.flows += 1;
+            observation.clients.insert(flow.client.ip);
+            inspect_tcp_streams(observation, &to_server, &to_client);
+        }
+
+        for (server, observation) in self.udp_servers {
+            observations.insert(server, observation);
+        }
+
+        let dns_names = self.dns_names;
+        observations
+            .into_iter()
+            .map(|(server, observation)| {
+                to_endpoint(server, observation, dns_names.get(&server.ip))
+            })
+            .collect()
+    }
+}
+
+/// Identify the application protocol of a TCP connection from its first bytes.
+fn inspect_tcp_streams(observation: &mut Observation, to_server: &[u8], to_client: &[u8]) {
+    if looks_like_http_request(to_server) {
+        observation.protocol = Some("http");
+        parse_http_requests(observation, to_server);
+        if let Some(server) = http_response_header(to_client, "server") {
+            observation.http_server = Some(server);
+        }
+    } else if to_server.len() > 5 && to_server[0] == 0x16 && to_server[1] == 0x03 {
+        observation.protocol = Some("tls");
+        if let Some(sni) = parse_tls_sni(to_server) {
+            observation.tls_sni.insert(sni);
+        }
+    } else if to_server.starts_with(b"AMQP") {
+        observation.protocol = Some("amqp");
+    } else if let Some(topics) = parse_mqtt(to_server) {
+        observation.protocol = Some("mqtt");
+        observation.mqtt_topics.extend(topics);
+    } else if let Some(database) = parse_postgres_startup(to_server) {
+        observation.protocol = Some("postgresql");
+        observation.sql_dialect = Some("postgresql");
+        if let Some(database) = database {
+            observation.databases.insert(database);
+        }
+    } else if let Some(version) = parse_mysql_greeting(to_client) {
+        observation.protocol = Some("mysql");
+        observation.sql_dialect = Some("mysql");
+        observation.server_version = Some(version);
+    } else if observation.protocol.is_none() && !to_server.is_empty() {
+        observation.protocol = Some("tcp");
+    }
+}
+
+fn to_endpoint(
+    server: Peer,
+    observation: Observation,
+    dns_names: Option<&BTreeSet<String>>,
+) -> EndpointInfo {
+    let (port_protocol, port_type) = classify_port(server.port);
+    let (protocol, endpoint_type) = match observation.protocol {
+        Some("http") => (Protocol::HTTP, EndpointType::API),
+        Some("tls") if matches!(port_type, EndpointType::API) => {
+            (Protocol::HTTPS, EndpointType::API)
+        }
+        // TLS on other ports wraps the port's own protocol, e.g. MQTT on 8883
+        Some("tls") => (port_protocol, port_type),
+        Some("mqtt") => (Protocol::MQTT, EndpointType::MessageQueue),
+        Some("amqp") => (Protocol::AMQP, EndpointType::MessageQueue),
+        Some("postgresql") | Some("mysql") => (Protocol::TCP, EndpointType::Database),
+        Some("syslog") => (Protocol::UDP, EndpointType::Log),
+        Some("dns") => (Protocol::UDP, EndpointType::Other("DNS".to_string())),
+        _ => (port_protocol, port_type),
+    };
+
+    // A TLS handshake shows the protocol but not what runs inside it, so the
+    // type still rests on the port number
+    let mut evidence = Vec::new();
+    match observation.protocol {
+        Some("tls") => {
+            evidence.push(Evidence::new(
+                EvidenceKind::HandshakeSucceeded,
+                "passive_pcap",
+                "observed a TLS handshake".to_string(),
+                0.5,
+            ));
+            evidence.push(Evidence::from_port(
+                "passive_pcap",
+                server.port,
+                &endpoint_type,
+            ));
+        }
+        Some(observed) if observed != "tcp" => evidence.push(Evidence::new(
+            EvidenceKind::TrafficObserved,
+            "passive_pcap",
+            format!(
+                "observed {} traffic from {} clients",
+                observed,
+                observation.clients.len()
+            ),
+            0.85,
+        )),
+        _ => evidence.push(Evidence::from_port(
+            "passive_pcap",
+            server.port,
+            &endpoint_type,
+        )),
+    }
+
+    let mut metadata = HashMap::new();
+    metadata.insert("detected_by".to_string(), "passive_pcap".to_string());
+    metadata.insert("transport".to_string(), observation.transport.to_string());
+    if let Some(observed) = observation.protocol {
+        metadata.insert("observed_protocol".to_string(), observed.to_string());
+    }
+    if observation.protocol == Some("tls") {
+        metadata.insert("tls".to_string(), "true".to_string());
+    }
+    if observation.flows > 0 {
+        metadata.insert("flow_count".to_string(), observation.flows.to_string());
+    }
+    if observation.dns_queries > 0 {
+        metadata.insert(
+            "dns_responses".to_string(),
+            observation.dns_queries.to_string(),
+        );
+    }
+    metadata.insert(
+        "client_count".to_string(),
+        observation.clients.len().to_string(),
+    );
+
+    let mut insert_list = |key: &str, values: &BTreeSet<String>| {
+        if !values.is_empty() {
+            let listed: Vec<&str> = values
+                .iter()
+                .take(MAX_LISTED_VALUES)
+                .map(String::as_str)
+                .collect();
+            metadata.insert(key.to_string(), listed.join(","));
+        }
+    };
+    insert_list("http_hosts", &observation.http_hosts);
+    insert_list("http_methods", &observation.http_methods);
+    insert_list("http_paths", &observation.http_paths);
+    insert_list("tls_sni", &observation.tls_sni);
+    insert_list("mqtt_topics", &observation.mqtt_topics);
+    insert_list("databases", &observation.databases);
+    if let Some(names) = dns_names {
+        insert_list("dns_names", names);
+    }
+
+    if let Some(auth_type) = api_learning::summarize_auth(&observation.http_auth) {
+        metadata.insert("auth_type".to_string(), auth_type);
+    }
+    if let Some(server_header) = observation.http_server {
+        metadata.insert("http_server".to_string(), server_header);
+    }
+    if let Some(dialect) = observation.sql_dialect {
+        metadata.insert("sql_dialect".to_string(), dialect.to_string());
+    }
+    if let Some(version) = observation.server_version {
+        metadata.insert("server_version".to_string(), version);
+    }
+
+    let mut endpoint = EndpointInfo {
+        endpoint_type,
+        address: server.ip.to_string(),
+        port: server.port,
+        protocol,
+        metadata,
+        discovered_timestamp: observation.first_seen,
+        api_model: None,
+        confidence: 0.0,
+        evidence: Vec::new(),
+    };
+    for item in evidence {
+        endpoint.add_evidence(item);
+    }
+    endpoint
+}
+
+fn strip_link_layer(linktype: u32, data: &[u8]) -> Option<&[u8]> {
+    match linktype {
+        LINKTYPE_ETHERNET => {
+            let mut offset = 12;
+            let mut ethertype = u16::from_be_bytes([*data.get(12)?, *data.get(13)?]);
+            // Skip 802.1Q / 802.1ad VLAN tags
+            while ethertype == 0x8100 || ethertype == 0x88a8 {
+                offset += 4;
+                ethertype = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]);
+            }
+            match ethertype {
+                0x0800 | 0x86dd => data.get(offset + 2..),
+                _ => None,
+            }
+        }
+        LINKTYPE_LINUX_SLL => data.get(16..),
+        LINKTYPE_NULL => data.get(4..),
+        LINKTYPE_RAW | LINKTYPE_IPV4 | LINKTYPE_IPV6 => Some(data),
+        _ => None,
+    }
+}
+
+/// Returns (source, destination, transport protocol number, transport payload).
+fn parse_ip(packet: &[u8]) -> Option<(IpAddr, IpAddr, u8, &[u8])> {
+    match packet.first()? >> 4 {
+        4 => {
+            // Truncated captures can end anywhere, so check the fixed header first
+            let header = packet.get(..20)?;
+            let header_len = ((header[0] & 0x0f) as usize) * 4;
+            let total_len = u16::from_be_bytes([header[2], header[3]]) as usize;
+            let fragment = u16::from_be_bytes([header[6], header[7]]);
+            // Only the first fragment carries the transport header
+            if fragment & 0x1fff != 0 {
+                return None;
+            }
+            let src = Ipv4Addr::new(header[12], header[13], header[14], header[15]);
+            let dst = Ipv4Addr::new(header[16], header[17], header[18], header[19]);
+            let end = total_len.min(packet.len()).max(header_len);
+            Some((
+                IpAddr::V4(src),
+                IpAddr::V4(dst),
+                header[9],
+                packet.get(header_len..end)?,
+            ))
+        }
+        6 => {
+            let payload_len = u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]) as usize;
+            let mut next_header = *packet.get(6)?;
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(packet.get(8..24)?);
+            dst.copy_from_slice(packet.get(24..40)?);
+            let end = (40 + payload_len).min(packet.len());
+            let mut offset = 40;
+            // Walk hop-by-hop, routing and destination option headers
+            while matches!(next_header, 0 | 43 | 60) {
+                next_header = *packet.get(offset)?;
+                offset += (*packet.get(offset + 1)? as usize + 1) * 8;
+            }
+            Some((
+                IpAddr::V6(Ipv6Addr::from(src)),
+                IpAddr::V6(Ipv6Addr::from(dst)),
+                next_header,
+                packet.get(offset..end)?,
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn looks_like_http_request(data: &[u8]) -> bool {
+    const METHODS: [&[u8]; 9] = [
+        b"GET ",
+        b"POST ",
+        b"PUT ",
+        b"DELETE ",
+        b"PATCH ",
+        b"HEAD ",
+        b"OPTIONS ",
+        b"CONNECT ",
+        b"TRACE ",
+    ];
+    METHODS.iter().any(|method| data.starts_with(method))
+}
+
+/// Walk pipelined/keep-alive HTTP/1.x requests and record methods, paths and hosts.
+fn parse_http_requests(observation: &mut Observation, mut data: &[u8]) {
+    while looks_like_http_request(data) {
+        let header_end = match find(data, b"\r\n\r\n") {
+            Some(end) => end,
+            None => break,
+        };
+        let head = String::from_utf8_lossy(&data[..header_end]);
+        let mut lines = head.split("\r\n");
+        let request_line = lines.next().unwrap_or("");
+        let mut parts = request_line.split_whitespace();
+
+        if let (Some(method), Some(target)) = (parts.next(), parts.next()) {
+            observation.http_methods.insert(method.to_string());
+            let path = target.split('?').next().unwrap_or(target);
+            // Absolute-form targets are sent to proxies
+            let path = match path.find("://") {
+                Some(scheme_end) => path[scheme_end + 3..]
+                    .find('/')
+                    .map_or("/", |start| &path[scheme_end + 3 + start..]),
+                None => path,
+            };
+            if path.starts_with('/') && observation.http_paths.len() < MAX_LISTED_VALUES * 4 {
+                observation.http_paths.insert(path.to_string());
+            }
+        }
+
+        let mut content_length = 0;
+        let headers: Vec<(&str, &str)> = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect();
+        for (name, value) in &headers {
+            if name.eq_ignore_ascii_case("host") {
+                observation.http_hosts.insert(value.to_string());
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse::<usize>().unwrap_or(0);
+            }
+        }
+        observation.http_auth.insert(
+            api_learning::auth_scheme(headers.into_iter()).unwrap_or_else(|| "none".to_string()),
+        );
+
+        // Content-Length comes from the capture and may be anything
+        match (header_end + 4).checked_add(content_length) {
+            Some(next) if next < data.len() => data = &data[next..],
+            _ => break,
+        }
+    }
+}
+
+fn http_response_header(data: &[u8], header: &str) -> Option<String> {
+    if !data.starts_with(b"HTTP/") {
+        return None;
+    }
+    let header_end = find(data, b"\r\n\r\n").unwrap_or(data.len());
+    let head = String::from_utf8_lossy(&data[..header_end]);
+    head.split("\r\n").skip(1).find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case(header) {
+            Some(value.trim().to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// Extract the server_name extension from a TLS ClientHello.
+fn parse_tls_sni(data: &[u8]) -> Option<String> {
+    // Record header (5) + handshake header (4)
+    if *data.get(5)? != 0x01 {
+        return None;
+    }
+    let mut offset = 9;
+    offset += 2 + 32; // client version + random
+    offset += 1 + *data.get(offset)? as usize; // session id
+    offset += 2 + u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]) as usize; // cipher suites
+    offset += 1 + *data.get(offset)? as usize; // compression methods
+    let extensions_len = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]) as usize;
+    offset += 2;
+    let extensions_end = (offset + extensions_len).min(data.len());
+
+    while offset + 4 <= extensions_end {
+        let ext_type = u16::from_be_bytes([data[offset], data[offset + 1]]);
+        let ext_len = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        let body = data.get(offset + 4..offset + 4 + ext_len)?;
+        if ext_type == 0 && body.len() >= 5 && body[2] == 0 {
+            let name_len = u16::from_be_bytes([body[3], body[4]]) as usize;
+            let name = body.get(5..5 + name_len)?;
+            return String::from_utf8(name.to_vec()).ok();
+        }
+        offset += 4 + ext_len;
+    }
+
+    None
+}
+
+/// Parse an MQTT client stream, returning published and subscribed topics.
+fn parse_mqtt(data: &[u8]) -> Option<Vec<String>> {
+    // The first packet must be CONNECT with the MQTT (3.1.1/5) or MQIsdp (3.1) protocol name
+    if *data.first()? != 0x10 {
+        return None;
+    }
+    let (_, header_len) = mqtt_remaining_length(&data[1..])?;
+    let name_start = 1 + header_len;
+    let name_len =
+        u16::from_be_bytes([*data.get(name_start)?, *data.get(name_start + 1)?]) as usize;
+    let name = data.get(name_start + 2..name_start + 2 + name_len)?;
+    if name != b"MQTT" && name != b"MQIsdp" {
+        return None;
+    }
+
+    let mut topics = Vec::new();
+    let mut offset = 0;
+    while offset < data.len() {
+        let packet_type = data[offset] >> 4;
+        let (remaining, len_bytes) = match mqtt_remaining_length(&data[offset + 1..]) {
+            Some(parsed) => parsed,
+            None => break,
+        };
+        let body_start = offset + 1 + len_bytes;
+        let body = match data.get(body_start..body_start + remaining) {
+            Some(body) => body,
+            None => break,
+        };
+
+        match packet_type {
+            // PUBLISH: topic name first
+            3 => {
+                if let Some(topic) = mqtt_string(body, 0) {
+                    topics.push(topic);
+                }
+            }
+            // SUBSCRIBE: packet id then (topic filter, options) pairs; MQTT 5 adds
+            // properties which are skipped heuristically by requiring valid UTF-8
+            8 => {
+                let mut position = 2;
+                while let Some(topic) = mqtt_string(body, position) {
+                    position += 2 + topic.len() + 1;
+                    topics.push(topic);
+                }
+            }
+            _ => {}
+        }
+        offset = body_start + remaining;
+    }
+
+    Some(topics)
+}
+
+fn mqtt_remaining_length(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (index, byte) in data.iter().take(4).enumerate() {
+        value |= ((byte & 0x7f) as usize) << (7 * index);
+        if byte & 0x80 == 0 {
+            return Some((value, index + 1));
+        }
+    }
+    None
+}
+
+fn mqtt_string(data: &[u8], offset: usize) -> Option<String> {
+    let len = u16::from_be_bytes([*data.get(offset)?, *data.get(offset + 1)?]) as usize;
+    if len == 0 {
+        return None;
+    }
+    let bytes = data.get(offset + 2..offset + 2 + len)?;
+    String::from_utf8(bytes.to_vec()).ok()
+}
+
+/// Recognise a PostgreSQL StartupMessage (optionally after an SSLRequest),
+/// returning the requested database name when it is visible.
+fn parse_postgres_startup(data: &[u8]) -> Option<Option<String>> {
+    let length = read_u32(data, 0, false)?;
+    let code = read_u32(data, 4, false)?;
+
+    match (length, code) {
+        // SSLRequest or GSSENCRequest: the rest of the stream is encrypted
+        (8, 80_877_103) | (8, 80_877_104) => Some(None),
+        (_, 196_608) => {
+            let params = data.get(8..(length as usize).min(data.len()))?;
+            let fields: Vec<&[u8]> = params.split(|b| *b == 0).collect();
+            let database = fields
+                .windows(2)
+                .find(|pair| pair[0] == b"database")
+                .map(|pair| String::from_utf8_lossy(pair[1]).to_string());
+            Some(database)
+        }
+        _ => None,
+    }
+}
+
+/// Recognise the MySQL/MariaDB initial handshake sent by the server.
+fn parse_mysql_greeting(data: &[u8]) -> Option<String> {
+    // 3-byte length, sequence id 0, protocol version 10
+    if data.len() < 6 || data[3] != 0 || data[4] != 0x0a {
+        return None;
+    }
+    let version_end = data[5..].iter().position(|b| *b == 0)?;
+    let version = std::str::from_utf8(&data[5..5 + version_end]).ok()?;
+    if version.is_empty() || !version.chars().all(|c| c.is_ascii_graphic()) {
+        return None;
+    }
+    Some(version.to_string())
+}
+
+/// Return (name, address) pairs for A and AAAA answers in a DNS response.
+fn parse_dns_answers(data: &[u8]) -> Vec<(String, IpAddr)> {
+    let mut answers = Vec::new();
+    if data.len() < 12 || data[2] & 0x80 == 0 {
+        return answers;
+    }
+    let questions = u16::from_be_bytes([data[4], data[5]]) as usize;
+    let answer_count = u16::from_be_bytes([data[6], data[7]]) as usize;
+    let mut offset = 12;
+
+    for _ in 0..questions {
+        match dns_name(data, offset) {
+            Some((_, next)) => offset = next + 4,
+            None => return answers,
+        }
+    }
+
+    for _ in 0..answer_count {
+        let (name, next) = match dns_name(data, offset) {
+            Some(parsed) => parsed,
+            None => break,
+        };
+        let header = match data.get(next..next + 10) {
+            Some(header) => header,
+            None => break,
+        };
+        let record_type = u16::from_be_bytes([header[0], header[1]]);
+        let rdlen = u16::from_be_bytes([header[8], header[9]]) as usize;
+        let rdata = match data.get(next + 10..next + 10 + rdlen) {
+            Some(rdata) => rdata,
+            None => break,
+        };
+        match (record_type, rdlen) {
+            (1, 4) => answers.push((
+                name,
+                IpAddr::V4(Ipv4Addr::new(rdata[0], rdata[1], rdata[2], rdata[3])),
+            )),
+            (28, 16) => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(rdata);
+                answers.push((name, IpAddr::V6(Ipv6Addr::from(octets))));
+            }
+            _ => {}
+        }
+        offset = next + 10 + rdlen;
+    }
+
+    answers
+}
+
+/// Decode a (possibly compressed) DNS name, returning it and the offset after it.
+fn dns_name(data: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let len = *data.get(offset)? as usize;
+        if len == 0 {
+            offset += 1;
+            break;
+        }
+        if len & 0xc0 == 0xc0 {
+            let pointer = ((len & 0x3f) << 8) | *data.get(offset + 1)? as usize;
+            if end.is_none() {
+                end = Some(offset + 2);
+            }
+            jumps += 1;
+            if jumps > 16 {
+                return None;
+            }
+            offset = pointer;
+            continue;
+        }
+        let label = data.get(offset + 1..offset + 1 + len)?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        offset += 1 + len;
+    }
+
+    Some((labels.join("."), end.unwrap_or(offset)))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn read_u16(bytes: &[u8], offset: usize, little_endian: bool) -> Option<u16> {
+    let raw = [*bytes.get(offset)?, *bytes.get(offset + 1)?];
+    Some(if little_endian {
+        u16::from_le_bytes(raw)
+    } else {
+        u16::from_be_bytes(raw)
+    })
+}
+
+fn read_u32(bytes: &[u8], offset: usize, little_endian: bool) -> Option<u32> {
+    let raw = [
+        *bytes.get(offset)?,
+        *bytes.get(offset + 1)?,
+        *bytes.get(offset + 2)?,
+        *bytes.get(offset + 3)?,
+    ];
+    Some(if little_endian {
+        u32::from_le_bytes(raw)
+    } else {
+        u32::from_be_bytes(raw)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ipv4_tcp(
+        src: [u8; 4],
+        dst: [u8; 4],
+        ports: (u16, u16),
+        flags: u8,
+        payload: &[u8],
+    ) -> Vec<u8> {
+        let mut tcp = Vec::new();
+        tcp.extend_from_slice(&ports.0.to_be_bytes());
+        tcp.extend_from_slice(&ports.1.to_be_bytes());
+        tcp.extend_from_slice(&1u32.to_be_bytes());
+        tcp.extend_from_slice(&0u32.to_be_bytes());
+        tcp.extend_from_slice(&[0x50, flags, 0xff, 0xff, 0, 0, 0, 0]);
+        tcp.extend_from_slice(payload);
+
+        let mut packet = vec![0x45, 0];
+        packet.extend_from_slice(&(20 + tcp.len() as u16).to_be_bytes());
+        packet.extend_from_slice(&[0, 0, 0, 0, 64, 6, 0, 0]);
+        packet.extend_from_slice(&src);
+        packet.extend_from_slice(&dst);
+        packet.extend_from_slice(&tcp);
+        packet
+    }
+
+    fn ethernet(packet: &[u8]) -> Vec<u8> {
+        let mut frame = vec![0; 12];
+        frame.extend_from_slice(&[0x08, 0x00]);
+        frame.extend_from_slice(packet);
+        frame
+    }
+
+    fn pcap(frames: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        bytes.extend_from_slice(&[0; 8]);
+        bytes.extend_from_slice(&65535u32.to_le_bytes());
+        bytes.extend_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+        for (index, frame) in frames.iter().enumerate() {
+            bytes.extend_from_slice(&(1_700_000_000 + index as u32).to_le_bytes());
+            bytes.extend_from_slice(&0u32.to_le_bytes());
+            bytes.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+            bytes.extend_from_slice(frame);
+        }
+        bytes
+    }
+
+    fn http_session() -> Vec<Vec<u8>> {
+        let client = [10, 0, 0, 1];
+        let server = [10, 0, 0, 2];
+        vec![
+            ethernet(&ipv4_tcp(client, server, (40000, 8080), 0x02, b"")),
+            ethernet(&ipv4_tcp(server, client, (8080, 40000), 0x12, b"")),
+            ethernet(&ipv4_tcp(
+                client,
+                server,
+                (40000, 8080),
+                0x18,
+                b"GET /api/users HTTP/1.1\r\nHost: app\r\n\r\n",
+            )),
+        ]
+    }
+
+    #[test]
+    fn parse_ip_reads_ipv4() {
+        let packet = ipv4_tcp([10, 0, 0, 1], [10, 0, 0, 2], (1, 2), 0x02, b"hi");
+        let (src, dst, transport, payload) = parse_ip(&packet).unwrap();
+        assert_eq!(src, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)));
+        assert_eq!(dst, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(transport, 6);
+        assert_eq!(payload.len(), 22);
+    }
+
+    #[test]
+    fn parse_ip_rejects_truncated_headers() {
+        let packet = ipv4_tcp([10, 0, 0, 1], [10, 0, 0, 2], (1, 2), 0x02, b"");
+        for len in 0..20 {
+            assert!(parse_ip(&packet[..len]).is_none(), "IPv4 prefix of {}", len);
+        }
+        // A payload cut short by the capture is returned as far as it goes
+        let (_, _, _, payload) = parse_ip(&packet[..30]).unwrap();
+        assert_eq!(payload.len(), 10);
+
+        let mut ipv6 = vec![0x60, 0, 0, 0, 0, 0, 6, 64];
+        ipv6.extend_from_slice(&[0; 32]);
+        for len in 0..40 {
+            assert!(parse_ip(&ipv6[..len]).is_none(), "IPv6 prefix of {}", len);
+        }
+        assert!(parse_ip(&ipv6).is_some());
+
+        // An extension header that runs past the end of the packet
+        ipv6[6] = 0;
+        ipv6.extend_from_slice(&[6, 4]);
+        assert!(parse_ip(&ipv6).is_none());
+    }
+
+    #[test]
+    fn parse_ip_skips_later_fragments() {
+        let mut packet = ipv4_tcp([10, 0, 0, 1], [10, 0, 0, 2], (1, 2), 0x02, b"");
+        packet[7] = 1;
+        assert!(parse_ip(&packet).is_none());
+    }
+
+    #[test]
+    fn strip_link_layer_handles_short_frames() {
+        for len in 0..14 {
+            assert!(strip_link_layer(LINKTYPE_ETHERNET, &[0x08; 14][..len]).is_none());
+        }
+        // VLAN tag without the inner ethertype
+        let mut tagged = vec![0; 12];
+        tagged.extend_from_slice(&[0x81, 0x00, 0, 1]);
+        assert!(strip_link_layer(LINKTYPE_ETHERNET, &tagged).is_none());
+        assert!(strip_link_layer(LINKTYPE_LINUX_SLL, &[0; 10]).is_none());
+    }
+
+    #[test]
+    fn read_pcap_reads_frames() {
+        let frames = http_session();
+        let bytes = pcap(&frames);
+        let read = read_capture(&bytes).unwrap();
+        assert_eq!(read.len(), 3);
+        assert_eq!(read[2].data, &frames[2][..]);
+        assert_eq!(read[0].timestamp, 1_700_000_000);
+    }
+
+    #[test]
+    fn read_pcap_stops_at_truncated_records() {
+        let bytes = pcap(&http_session());
+        assert!(read_capture(&bytes[..3]).is_err());
+        assert!(read_capture(&bytes[..23]).is_err());
+        assert!(read_capture(&[0xff; 24]).is_err());
+
+        let first_record = 24 + 16 + http_session()[0].len();
+        assert_eq!(read_capture(&bytes[..first_record]).unwrap().len(), 1);
+        assert_eq!(read_capture(&bytes[..first_record + 20]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn read_pcapng_stops_at_truncated_blocks() {
+        let frame = &http_session()[2];
+        let mut bytes = Vec::new();
+        let mut block = |block_type: u32, body: &[u8]| {
+            let len = 12 + body.len() as u32;
+            bytes.extend_from_slice(&block_type.to_le_bytes());
+            bytes.extend_from_slice(&len.to_le_bytes());
+            bytes.extend_from_slice(body);
+            bytes.extend_from_slice(&len.to_le_bytes());
+        };
+        block(
+            0x0a0d_0d0a,
+            &[
+                0x4d, 0x3c, 0x2b, 0x1a, 1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+            ],
+        );
+        block(1, &[1, 0, 0, 0, 0xff, 0xff, 0, 0]);
+        let mut packet = vec![0; 12];
+        packet.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        packet.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+        packet.extend_from_slice(frame);
+        packet.resize((packet.len() + 3) & !3, 0);
+        block(6, &packet);
+
+        assert_eq!(read_capture(&bytes).unwrap().len(), 1);
+        for len in 4..bytes.len() {
+            if let Ok(frames) = read_capture(&bytes[..len]) {
+                assert!(frames.is_empty(), "prefix of {} bytes", len);
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_captures_do_not_panic() {
+        let bytes = pcap(&http_session());
+        for len in 4..=bytes.len() {
+            let mut analyzer = Analyzer::default();
+            if let Ok(frames) = read_capture(&bytes[..len]) {
+                for frame in &frames {
+                    analyzer.process_frame(frame);
+                }
+            }
+            analyzer.finish();
+        }
+
+        // Every frame cut short at every length
+        for frame in http_session() {
+            for len in 0..frame.len() {
+                let mut analyzer = Analyzer::default();
+                analyzer.process_frame(&Frame {
+                    timestamp: 0,
+                    linktype: LINKTYPE_ETHERNET,
+                    data: &frame[..len],
+                });
+                analyzer.finish();
+            }
+        }
+    }
+
+    #[test]
+    fn parse_http_requests_stops_on_huge_content_length() {
+        let mut observation = Observation::default();
+        let data = format!(
+            "POST /a HTTP/1.1\r\nContent-Length: {}\r\n\r\nGET /b HTTP/1.1\r\n\r\n",
+            usize::MAX
+        );
+        parse_http_requests(&mut observation, data.as_bytes());
+        assert_eq!(observation.http_paths.len(), 1);
+        assert!(observation.http_paths.contains("/a"));
+
+        let data = "POST /a HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}GET /b HTTP/1.1\r\n\r\n";
+        let mut observation = Observation::default();
+        parse_http_requests(&mut observation, data.as_bytes());
+        assert!(observation.http_paths.contains("/b"));
+    }
+
+    #[test]
+    fn http_session_is_found() {
+        let bytes = pcap(&http_session());
+        let mut analyzer = Analyzer::default();
+        for frame in &read_capture(&bytes).unwrap() {
+            analyzer.process_frame(frame);
+        }
+        let results = analyzer.finish();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].address, "10.0.0.2");
+        assert_eq!(results[0].port, 8080);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::config::{
+    Config, HostDiscoveryConfig, NetworkEntry, PortSpec, RateLimitConfig, ScanProfile,
+};
+use serde::Serialize;
+
+/// Names of the profiles available without any configuration.
+pub const BUILTIN_PROFILES: [&str; 3] = ["quick", "deep", "passive-only"];
+
+/// Scan settings after applying a profile and per-network overrides to `[scan]`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ScanSettings {
+    pub profile: Option<String>,
+    pub port_scan: bool,
+    pub ports: Vec<PortSpec>,
+    pub concurrency: usize,
+    pub timeout_ms: u64,
+    pub use_mdns: bool,
+    pub use_upnp: bool,
+    pub host_discovery: HostDiscoveryConfig,
+    pub rate_limit: RateLimitConfig,
+}
+
+fn builtin(name: &str) -> Option<ScanProfile> {
+    let expressions = |specs: &[&str]| {
+        specs
+            .iter()
+            .map(|spec| PortSpec::Expression(spec.to_string()))
+            .collect::<Vec<_>>()
+    };
+
+    match name {
+        // Common service ports only, short timeouts, no multicast discovery
+        "quick" => Some(ScanProfile {
+            ports: Some(expressions(&["web", "databases", "messaging", "22"])),
+            timeout_ms: Some(500),
+            use_mdns: Some(false),
+            use_upnp: Some(false),
+            ..ScanProfile::default()
+        }),
+        // Top 1000 ports plus service sets on every address, including hosts
+        // that do not answer pings
+        "deep" => Some(ScanProfile {
+            ports: Some(expressions(&[
+                "top-1000",
+                "databases",
+                "messaging",
+                "logging",
+            ])),
+            timeout_ms: Some(2000),
+            use_mdns: Some(true),
+            use_upnp: Some(true),
+            host_discovery: Some(HostDiscoveryConfig {
+                enabled: false,
+                ..HostDiscoveryConfig::default()
+            }),
+            ..ScanProfile::default()
+        }),
+        // No connections to scanned hosts; only mDNS and UPnP announcements
+        "passive-only" => Some(ScanProfile {
+            port_scan: Some(false),
+            use_mdns: Some(true),
+            use_upnp: Some(true),
+            ..ScanProfile::default()
+        }),
+        _ => None,
+    }
+}
+
+/// Look up a profile defined in `[profiles.<name>]`, falling back to the
+/// built-in profiles so they can be overridden by name.
+pub fn find(config: &Config, name: &str) -> Result<ScanProfile, String> {
+    if let Some(profile) = config.profiles.get(name) {
+        return Ok(profile.clone());
+    }
+    builtin(name).ok_or_else(|| {
+        let mut known: Vec<&str> = config.profiles.keys().map(String::as_str).collect();
+        known.extend(BUILTIN_PROFILES.iter());
+        known.sort_unstable();
+        known.dedup();
+        format!(
+            "unknown scan profile '{}' (available: {})",
+            name,
+            known.join(", ")
+        )
+    })
+}
+
+/// Settings for one network, or for the scan as a whole when `network` is
+/// `None`. A network's own profile takes precedence over `scan.profile`, and
+/// its own port list over both.
+pub fn settings_for(
+    config: &Config,
+    network: Option<&NetworkEntry>,
+) -> Result<ScanSettings, String> {
+    let scan = &config.scan;
+    let profile_name = network
+        .and_then(NetworkEntry::profile)
+        .or(scan.profile.as_deref());
+
+    let mut settings = ScanSettings {
+        profile: profile_name.map(String::from),
+        port_scan: true,
+        ports: scan.ports.clone(),
+        concurrency: scan.concurrency,
+        timeout_ms: scan.timeout_ms,
+        use_mdns: scan.use_mdns,
+        use_upnp: scan.use_upnp,
+        host_discovery: scan.host_discovery.clone(),
+        rate_limit: scan.rate_limit.clone(),
+    };
+
+    if let Some(name) = profile_name {
+        let profile = find(config, name)?;
+        if let Some(port_scan) = profile.port_scan {
+            settings.port_scan = port_scan;
+        }
+        if let Some(ports) = profile.ports {
+            settings.ports = ports;
+        }
+        if let Some(concurrency) = profile.concurrency {
+            settings.concurrency = concurrency;
+        }
+        if let Some(timeout_ms) = profile.timeout_ms {
+            settings.timeout_ms = timeout_ms;
+        }
+        if let Some(use_mdns) = profile.use_mdns {
+            settings.use_mdns = use_mdns;
+        }
+        if let Some(use_upnp) = profile.use_upnp {
+            settings.use_upnp = use_upnp;
+        }
+        if let Some(host_discovery) = profile.host_discovery {
+            settings.host_discovery = host_discovery;
+        }
+        if let Some(rate_limit) = profile.rate_limit {
+            settings.rate_limit = rate_limit;
+        }
+    }
+
+    if scan.skip_host_discovery {
+        settings.host_discovery.enabled = false;
+    }
+    if let Some(ports) = network.and_then(NetworkEntry::port_override) {
+        settings.ports = ports.to_vec();
+    }
+
+    Ok(settings)
+}
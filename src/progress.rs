@@ -0,0 +1,173 @@
+use crate::scanner::{EndpointInfo, ScanEvent};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+// How often the progress line is refreshed on a terminal, and how often a
+// progress line is printed when stderr is redirected to a file
+const TERMINAL_REFRESH: Duration = Duration::from_millis(250);
+const LOG_REFRESH: Duration = Duration::from_secs(30);
+
+/// Live scan progress on stderr: hosts and probes completed, probe rate, ETA
+/// and the number of open ports found.
+pub struct ProgressReporter {
+    hosts_total: u64,
+    hosts_done: u64,
+    probes_total: u64,
+    probes_done: u64,
+    probes_at_start: u64,
+    open_ports: usize,
+    started: Instant,
+    last_draw: Option<Instant>,
+    interactive: bool,
+}
+
+impl ProgressReporter {
+    pub fn new() -> Self {
+        ProgressReporter {
+            hosts_total: 0,
+            hosts_done: 0,
+            probes_total: 0,
+            probes_done: 0,
+            probes_at_start: 0,
+            open_ports: 0,
+            started: Instant::now(),
+            last_draw: None,
+            interactive: io::stderr().is_terminal(),
+        }
+    }
+
+    pub fn update(&mut self, event: &ScanEvent) {
+        match event {
+            ScanEvent::Planned {
+                hosts_total,
+                hosts_done,
+                probes_total,
+                probes_done,
+                endpoints,
+            } => {
+                self.hosts_total = *hosts_total;
+                self.hosts_done = *hosts_done;
+                self.probes_total = *probes_total;
+                self.probes_done = *probes_done;
+                self.probes_at_start = self.probes_done;
+                self.open_ports = *endpoints;
+                self.started = Instant::now();
+            }
+            ScanEvent::Probed { host_done } => {
+                self.probes_done += 1;
+                if *host_done {
+                    self.hosts_done += 1;
+                }
+            }
+            ScanEvent::Skipped { hosts, probes } => {
+                self.hosts_done += hosts;
+                self.probes_done += probes;
+                // Skipped probes were never sent, so they do not count towards the rate
+                self.probes_at_start += probes;
+            }
+            ScanEvent::Found(_) => self.open_ports += 1,
+        }
+
+        let refresh = if self.interactive {
+            TERMINAL_REFRESH
+        } else {
+            LOG_REFRESH
+        };
+        if self
+            .last_draw
+            .is_none_or(|drawn| drawn.elapsed() >= refresh)
+        {
+            self.draw();
+        }
+    }
+
+    /// Print the final totals and leave the cursor on a fresh line.
+    pub fn finish(&mut self) {
+        self.draw();
+        if self.interactive {
+            eprintln!();
+        }
+    }
+
+    fn draw(&mut self) {
+        self.last_draw = Some(Instant::now());
+
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let probed = self.probes_done.saturating_sub(self.probes_at_start);
+        let rate = if elapsed > 0.0 {
+            probed as f64 / elapsed
+        } else {
+            0.0
+        };
+        let percent = if self.probes_total > 0 {
+            self.probes_done as f64 * 100.0 / self.probes_total as f64
+        } else {
+            100.0
+        };
+        let eta = if rate > 0.0 {
+            format_duration(self.probes_total.saturating_sub(self.probes_done) as f64 / rate)
+        } else {
+            "-".to_string()
+        };
+
+        let line = format!(
+            "{}/{} hosts, {}/{} probes ({:.1}%), {:.0} probes/s, ETA {}, {} open",
+            self.hosts_done,
+            self.hosts_total,
+            self.probes_done,
+            self.probes_total,
+            percent,
+            rate,
+            eta,
+            self.open_ports
+        );
+        if self.interactive {
+            // Redraw in place, clearing whatever was left of the previous line
+            eprint!("\r\x1b[2K{}", line);
+        } else {
+            eprintln!("Progress: {}", line);
+        }
+    }
+}
+
+impl Default for ProgressReporter {
+    fn default() -> Self {
+        ProgressReporter::new()
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    let seconds = seconds.round() as u64;
+    match seconds {
+        0..=59 => format!("{}s", seconds),
+        60..=3599 => format!("{}m{:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h{:02}m", seconds / 3600, (seconds % 3600) / 60),
+    }
+}
+
+/// Endpoints written as JSON Lines as soon as they are found, to stdout (`-`)
+/// or a file.
+pub struct ResultStream {
+    writer: Box<dyn Write>,
+}
+
+impl ResultStream {
+    pub fn open(target: &str) -> Result<Self, Box<dyn Error>> {
+        let writer: Box<dyn Write> = if target == "-" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(BufWriter::new(File::create(target)?))
+        };
+        Ok(ResultStream { writer })
+    }
+
+    /// Write one endpoint and flush, so consumers see it immediately.
+    pub fn write(&mut self, endpoint: &EndpointInfo) -> Result<(), Box<dyn Error>> {
+        serde_json::to_writer(&mut self.writer, endpoint)?;
+        self.writer.write_all(b"\n")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
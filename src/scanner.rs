@@ -1,9 +1,23 @@
-use crate::config::Config;
+use crate::api_learning::ApiModel;
+use crate::checkpoint::{self, HostSet, ScanState};
+use crate::config::{Config, NetworkEntry};
+use crate::host_discovery;
+use crate::ports;
+use crate::profiles::{self, ScanSettings};
+use crate::progress::{ProgressReporter, ResultStream};
+use crate::scope;
+use crate::signals;
+use crate::throttle::Throttle;
+use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::error::Error;
+use std::fs;
 use std::net::{IpAddr, Ipv4Addr};
-use std::time::Duration;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::net::TcpStream;
 use tokio::time::timeout;
 
@@ -15,9 +29,104 @@ pub struct EndpointInfo {
     pub protocol: Protocol,
     pub metadata: HashMap<String, String>,
     pub discovered_timestamp: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_model: Option<ApiModel>,
+    /// How sure the classification is, from 0.0 to 1.0, combined from `evidence`
+    #[serde(default)]
+    pub confidence: f64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub evidence: Vec<Evidence>,
+}
+
+/// One observation supporting an endpoint's type and protocol.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Evidence {
+    pub kind: EvidenceKind,
+    /// Discovery method that made the observation, e.g. `port_scan`
+    pub source: String,
+    pub detail: String,
+    pub confidence: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EvidenceKind {
+    /// The port number is conventionally used by the service
+    PortHeuristic,
+    /// A banner or service fingerprint identified the service
+    BannerMatch,
+    /// The service announced itself over mDNS or UPnP
+    ServiceAnnouncement,
+    /// Application protocol traffic to the endpoint was seen
+    TrafficObserved,
+    /// A TLS or protocol handshake with the endpoint completed
+    HandshakeSucceeded,
+}
+
+impl Evidence {
+    pub fn new(kind: EvidenceKind, source: &str, detail: String, confidence: f64) -> Self {
+        Evidence {
+            kind,
+            source: source.to_string(),
+            detail,
+            confidence: confidence.clamp(0.0, 1.0),
+        }
+    }
+
+    /// Evidence from the port number alone, which is weak for well-known
+    /// ports and weaker still when the port says nothing about the service.
+    pub fn from_port(source: &str, port: u16, endpoint_type: &EndpointType) -> Self {
+        match endpoint_type {
+            EndpointType::Other(service) if service == "Unknown" => Evidence::new(
+                EvidenceKind::PortHeuristic,
+                source,
+                format!("no well-known service on port {}", port),
+                0.1,
+            ),
+            known => Evidence::new(
+                EvidenceKind::PortHeuristic,
+                source,
+                format!("port {} is commonly {}", port, known.label()),
+                0.3,
+            ),
+        }
+    }
+}
+
+impl EndpointInfo {
+    /// Record evidence for the current classification and update the
+    /// combined confidence. Duplicate observations are ignored.
+    pub fn add_evidence(&mut self, evidence: Evidence) {
+        if !self.evidence.contains(&evidence) {
+            self.evidence.push(evidence);
+        }
+        self.confidence = combined_confidence(&self.evidence);
+    }
+}
+
+/// Combine evidence into one confidence value. Different kinds of evidence
+/// are treated as independent, so the classification is wrong only if every
+/// kind is misleading; several items of one kind (two tools reading the same
+/// port number) count only as the strongest of them.
+pub fn combined_confidence(evidence: &[Evidence]) -> f64 {
+    if evidence.is_empty() {
+        return 0.0;
+    }
+
+    let mut strongest: HashMap<EvidenceKind, f64> = HashMap::new();
+    for item in evidence {
+        let best = strongest.entry(item.kind).or_insert(0.0);
+        *best = best.max(item.confidence);
+    }
+    let doubt: f64 = strongest
+        .values()
+        .map(|confidence| 1.0 - confidence)
+        .product();
+    ((1.0 - doubt) * 1000.0).round() / 1000.0
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum EndpointType {
     API,
     Log,
@@ -27,6 +136,7 @@ pub enum EndpointType {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Protocol {
     HTTP,
     HTTPS,
@@ -37,133 +147,656 @@ pub enum Protocol {
     Other(String),
 }
 
+impl EndpointType {
+    /// Short lowercase name used in reports, filters and storage.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EndpointType::API => "api",
+            EndpointType::Log => "log",
+            EndpointType::Database => "database",
+            EndpointType::MessageQueue => "message_queue",
+            EndpointType::Other(_) => "other",
+        }
+    }
+
+    /// Name including the service carried by `Other`, e.g. `other:MediaServer`.
+    pub fn label(&self) -> String {
+        match self {
+            EndpointType::Other(service) => format!("other:{}", service),
+            known => known.name().to_string(),
+        }
+    }
+}
+
+impl Protocol {
+    /// Lowercase protocol name, e.g. `https` or the name carried by `Other`.
+    pub fn name(&self) -> String {
+        match self {
+            Protocol::HTTP => "http".to_string(),
+            Protocol::HTTPS => "https".to_string(),
+            Protocol::TCP => "tcp".to_string(),
+            Protocol::UDP => "udp".to_string(),
+            Protocol::MQTT => "mqtt".to_string(),
+            Protocol::AMQP => "amqp".to_string(),
+            Protocol::Other(name) => name.to_lowercase(),
+        }
+    }
+}
+
 pub type ScanResults = Vec<EndpointInfo>;
 
-pub async fn scan_port(ip: IpAddr, port: u16) -> bool {
+/// Write scan results as JSON so later runs can be compared against them.
+pub fn save_results<P: AsRef<Path>>(results: &ScanResults, path: P) -> Result<(), Box<dyn Error>> {
+    fs::write(path, serde_json::to_string_pretty(results)?)?;
+    Ok(())
+}
+
+pub fn load_results<P: AsRef<Path>>(path: P) -> Result<ScanResults, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
+
+/// Result of a single TCP connection attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProbeOutcome {
+    Open,
+    /// Refused: the host answered, but nothing listens on the port
+    Closed,
+    TimedOut,
+    /// Reset, unreachable or any other error
+    Failed,
+}
+
+pub async fn scan_port(ip: IpAddr, port: u16, timeout_duration: Duration) -> ProbeOutcome {
     let addr = format!("{}:{}", ip, port);
-    let timeout_duration = Duration::from_secs(1);
 
     match timeout(timeout_duration, TcpStream::connect(&addr)).await {
-        Ok(Ok(_)) => true,
-        _ => false,
+        Ok(Ok(_)) => ProbeOutcome::Open,
+        Ok(Err(err)) if err.kind() == std::io::ErrorKind::ConnectionRefused => ProbeOutcome::Closed,
+        Ok(Err(_)) => ProbeOutcome::Failed,
+        Err(_) => ProbeOutcome::TimedOut,
     }
 }
 
-async fn probe_endpoint(ip: IpAddr, port: u16) -> Option<EndpointInfo> {
-    if scan_port(ip, port).await {
-        // Basic endpoint detection logic - in a real-world scenario, you would implement
-        // more sophisticated detection mechanisms based on response headers, banners, etc.
-        let protocol = match port {
-            80 => Protocol::HTTP,
-            443 => Protocol::HTTPS,
-            1883 | 8883 => Protocol::MQTT,
-            5672 => Protocol::AMQP,
-            _ => Protocol::TCP,
-        };
+/// Guess protocol and endpoint type from a well-known port number.
+pub fn classify_port(port: u16) -> (Protocol, EndpointType) {
+    // Basic endpoint detection logic - in a real-world scenario, you would implement
+    // more sophisticated detection mechanisms based on response headers, banners, etc.
+    let protocol = match port {
+        80 => Protocol::HTTP,
+        443 => Protocol::HTTPS,
+        1883 | 8883 => Protocol::MQTT,
+        5672 => Protocol::AMQP,
+        _ => Protocol::TCP,
+    };
+
+    let endpoint_type = match port {
+        80 | 443 | 8080 | 8443 => EndpointType::API,
+        514 | 1468 | 10514 => EndpointType::Log,
+        3306 | 5432 | 1521 | 27017 => EndpointType::Database,
+        1883 | 8883 | 5672 => EndpointType::MessageQueue,
+        _ => EndpointType::Other(String::from("Unknown")),
+    };
+
+    (protocol, endpoint_type)
+}
 
-        let endpoint_type = match port {
-            80 | 443 | 8080 | 8443 => EndpointType::API,
-            514 | 1468 | 10514 => EndpointType::Log,
-            3306 | 5432 | 1521 | 27017 => EndpointType::Database,
-            1883 | 8883 | 5672 => EndpointType::MessageQueue,
-            _ => EndpointType::Other(String::from("Unknown")),
-        };
+async fn probe_endpoint(
+    ip: IpAddr,
+    port: u16,
+    timeout_duration: Duration,
+) -> (ProbeOutcome, Option<EndpointInfo>) {
+    let outcome = scan_port(ip, port, timeout_duration).await;
+    if outcome == ProbeOutcome::Open {
+        let (protocol, endpoint_type) = classify_port(port);
 
         let mut metadata = HashMap::new();
         metadata.insert("detected_by".to_string(), "port_scan".to_string());
 
-        Some(EndpointInfo {
+        let evidence = Evidence::from_port("port_scan", port, &endpoint_type);
+        let mut endpoint = EndpointInfo {
             endpoint_type,
             address: ip.to_string(),
             port,
             protocol,
             metadata,
             discovered_timestamp: chrono::Utc::now().timestamp() as u64,
-        })
+            api_model: None,
+            confidence: 0.0,
+            evidence: Vec::new(),
+        };
+        endpoint.add_evidence(evidence);
+        (outcome, Some(endpoint))
     } else {
-        None
+        (outcome, None)
     }
 }
 
-pub fn scan(config: &Config) -> Result<ScanResults, Box<dyn Error>> {
-    let runtime = tokio::runtime::Runtime::new()?;
+/// Options for a scan run from the command line.
+#[derive(Debug, Default)]
+pub struct ScanOptions {
+    /// Continue from the last checkpoint instead of starting over
+    pub resume: bool,
+    /// Write endpoints as JSON Lines to this file (`-` for stdout) as they are found
+    pub stream: Option<String>,
+    /// Suppress the progress display
+    pub quiet: bool,
+}
 
-    runtime.block_on(async {
-        let mut results = Vec::new();
+/// Reported while a scan runs, for progress display, streaming and checkpointing.
+pub enum ScanEvent<'a> {
+    /// Sent once before probing starts
+    Planned {
+        hosts_total: u64,
+        hosts_done: u64,
+        probes_total: u64,
+        probes_done: u64,
+        endpoints: usize,
+    },
+    /// A host/port pair was probed; `host_done` is set when it was the host's last port
+    Probed { host_done: bool },
+    /// Hosts found dead during host discovery, so their ports are not probed
+    Skipped { hosts: u64, probes: u64 },
+    /// A new endpoint was found
+    Found(&'a EndpointInfo),
+}
 
-        for network in &config.scan.networks {
-            log::info!("Scanning network: {}", network);
+/// Scan the configured networks, checkpointing progress so the scan can be
+/// continued with `resume` after a crash or interruption. SIGINT/SIGTERM stop
+/// the scan early and return the partial results.
+pub fn scan(config: &Config, options: &ScanOptions) -> Result<ScanResults, Box<dyn Error>> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(scan_with_checkpoints(config, options))
+}
 
-            // Parse network range (simplified for this example)
-            // Assuming network is in format "192.168.1.0/24"
-            let parts: Vec<&str> = network.split('/').collect();
-            if parts.len() != 2 {
-                log::warn!("Invalid network format: {}", network);
-                continue;
+async fn scan_with_checkpoints(
+    config: &Config,
+    options: &ScanOptions,
+) -> Result<ScanResults, Box<dyn Error>> {
+    let checkpoint_path = &config.checkpoint.file;
+    let plans = plan_networks(config, &config.scan.networks);
+    scope::enforce(config, &plans)?;
+
+    let mut state = if options.resume {
+        match checkpoint::load(checkpoint_path, &plans)? {
+            Some(state) => {
+                log::info!(
+                    "Resuming scan from {}: {} hosts done, {} endpoints found so far",
+                    checkpoint_path,
+                    state.completed.count(),
+                    state.results.len()
+                );
+                state
             }
-
-            let base_ip_parts: Vec<&str> = parts[0].split('.').collect();
-            if base_ip_parts.len() != 4 {
-                log::warn!("Invalid IP format: {}", parts[0]);
-                continue;
+            None => {
+                log::info!(
+                    "No checkpoint found at {}, starting a new scan",
+                    checkpoint_path
+                );
+                ScanState::new()
             }
+        }
+    } else {
+        ScanState::new()
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let signal_task = {
+        let stop = stop.clone();
+        tokio::spawn(async move {
+            signals::shutdown_requested().await;
+            log::warn!("Interrupted, waiting for in-flight probes before saving progress");
+            stop.store(true, Ordering::SeqCst);
+        })
+    };
+
+    let mut stream = match &options.stream {
+        Some(target) => {
+            let mut stream = ResultStream::open(target)?;
+            // Endpoints restored from a checkpoint are part of this scan's output too
+            for endpoint in &state.results {
+                stream.write(endpoint)?;
+            }
+            Some(stream)
+        }
+        None => None,
+    };
+    let mut progress = if options.quiet {
+        None
+    } else {
+        Some(ProgressReporter::new())
+    };
+
+    let interval = Duration::from_secs(config.checkpoint.interval_secs);
+    let mut last_saved = Instant::now();
+    let mut observer = |state: &ScanState, event: ScanEvent| {
+        if let (Some(writer), ScanEvent::Found(endpoint)) = (&mut stream, &event) {
+            if let Err(err) = writer.write(endpoint) {
+                log::warn!("Failed to stream results, disabling output: {}", err);
+                stream = None;
+            }
+        }
+        if let Some(progress) = &mut progress {
+            progress.update(&event);
+        }
+        if matches!(event, ScanEvent::Probed { .. }) && last_saved.elapsed() >= interval {
+            if let Err(err) = checkpoint::save(checkpoint_path, &plans, state) {
+                log::warn!("Failed to write checkpoint {}: {}", checkpoint_path, err);
+            }
+            last_saved = Instant::now();
+        }
+    };
+
+    let discovery = Discovery::for_networks(config, &config.scan.networks)?;
+    let scanned = scan_into(plans.clone(), discovery, &mut state, &stop, &mut observer).await;
+    signal_task.abort();
+    if let Some(progress) = &mut progress {
+        progress.finish();
+    }
+
+    if let Err(err) = scanned {
+        // Keep whatever was finished so the failed scan can be resumed
+        checkpoint::save(checkpoint_path, &plans, &state)?;
+        return Err(err);
+    }
+
+    if stop.load(Ordering::SeqCst) {
+        checkpoint::save(checkpoint_path, &plans, &state)?;
+        log::warn!(
+            "Scan interrupted after {} hosts with {} endpoints found; progress saved to {}, continue with --resume",
+            state.completed.count(),
+            state.results.len(),
+            checkpoint_path
+        );
+    } else {
+        checkpoint::remove(checkpoint_path);
+    }
+
+    Ok(state.results)
+}
+
+/// Scan the given networks, followed by whichever of mDNS and UPnP
+/// discovery `discovery` enables.
+pub async fn scan_networks(
+    config: &Config,
+    networks: &[NetworkEntry],
+    discovery: Discovery,
+) -> Result<ScanResults, Box<dyn Error>> {
+    let plans = plan_networks(config, networks);
+    scope::enforce(config, &plans)?;
+
+    let mut state = ScanState::new();
+    let stop = AtomicBool::new(false);
+    scan_into(plans, discovery, &mut state, &stop, &mut |_, _| {}).await?;
+    Ok(state.results)
+}
 
-            let subnet_mask = parts[1].parse::<u8>().unwrap_or(24);
-            if subnet_mask > 32 {
-                log::warn!("Invalid subnet mask: {}", subnet_mask);
-                continue;
+/// Probe every host/port pair not yet completed in `state`, adding findings as
+/// they arrive. `observer` is told about every probe and finding, and the scan
+/// stops early once `stop` is set.
+async fn scan_into(
+    plans: Vec<NetworkPlan>,
+    discovery: Discovery,
+    state: &mut ScanState,
+    stop: &AtomicBool,
+    observer: &mut dyn FnMut(&ScanState, ScanEvent),
+) -> Result<(), Box<dyn Error>> {
+    use futures::stream::{self, StreamExt};
+
+    let already_done = state.completed.clone();
+    observer(
+        state,
+        ScanEvent::Planned {
+            hosts_total: plans.iter().map(NetworkPlan::host_count).sum(),
+            hosts_done: plans
+                .iter()
+                .map(|plan| already_done.count_in(plan.first, plan.last))
+                .sum(),
+            probes_total: plans
+                .iter()
+                .map(|plan| plan.host_count() * plan.ports.len() as u64)
+                .sum(),
+            probes_done: plans
+                .iter()
+                .map(|plan| already_done.count_in(plan.first, plan.last) * plan.ports.len() as u64)
+                .sum(),
+            endpoints: state.results.len(),
+        },
+    );
+
+    for plan in plans {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        match &plan.settings.profile {
+            Some(profile) => log::info!("Scanning network: {} (profile {})", plan.cidr, profile),
+            None => log::info!("Scanning network: {}", plan.cidr),
+        }
+
+        let (first, last, ports) = (plan.first, plan.last, &plan.ports);
+        let settings = &plan.settings;
+        let concurrency = settings.concurrency.max(1);
+        let throttle = Throttle::new(&settings.rate_limit);
+        let probe_timeout = Duration::from_millis(settings.timeout_ms.max(1));
+
+        // Excluded addresses are never contacted, not even by host discovery
+        let mut excluded = 0;
+        for (first, last) in &plan.excluded {
+            for host in *first..=*last {
+                if !state.completed.contains(host) {
+                    state.completed.insert(host);
+                    excluded += 1;
+                }
             }
+        }
+        if excluded > 0 {
+            observer(
+                state,
+                ScanEvent::Skipped {
+                    hosts: excluded,
+                    probes: excluded * ports.len() as u64,
+                },
+            );
+        }
 
-            // For simplicity, only handling /24 networks in this example
-            if subnet_mask != 24 {
-                log::warn!("Only /24 networks are supported in this example");
-                continue;
+        // Dead hosts are marked done without probing their ports
+        let candidates: Vec<u32> = if settings.host_discovery.enabled {
+            (first..=last)
+                .filter(|host| !state.completed.contains(*host))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        if !candidates.is_empty() {
+            let alive = host_discovery::discover_live_hosts(
+                &settings.host_discovery,
+                &candidates,
+                &throttle,
+                concurrency,
+                stop,
+            )
+            .await;
+            if stop.load(Ordering::SeqCst) {
+                break;
             }
 
-            let base_ip = format!(
-                "{}.{}.{}.",
-                base_ip_parts[0], base_ip_parts[1], base_ip_parts[2]
+            let mut skipped = 0;
+            for host in candidates {
+                if !alive.contains(&host) {
+                    state.completed.insert(host);
+                    skipped += 1;
+                }
+            }
+            observer(
+                state,
+                ScanEvent::Skipped {
+                    hosts: skipped,
+                    probes: skipped * ports.len() as u64,
+                },
             );
+        }
 
-            let mut tasks = Vec::new();
+        let done = state.completed.clone();
+        let targets = ordered_targets(
+            first,
+            last,
+            ports,
+            &done,
+            settings.rate_limit.randomize_order,
+        );
+        let throttle = &throttle;
+        let mut probes = stream::iter(targets)
+            .map(|(host, port)| async move {
+                let _slot = throttle.acquire(host).await;
+                let ip = IpAddr::V4(Ipv4Addr::from(host));
+                let (outcome, endpoint) = probe_endpoint(ip, port, probe_timeout).await;
+                throttle.record(host, outcome);
+                (host, endpoint)
+            })
+            .buffer_unordered(concurrency);
+
+        // A host counts as done once all of its ports have been probed
+        let mut pending: HashMap<u32, usize> = HashMap::new();
+        while let Some((host, endpoint)) = probes.next().await {
+            if let Some(endpoint) = endpoint {
+                observer(state, ScanEvent::Found(&endpoint));
+                state.results.push(endpoint);
+            }
 
-            // Scan all hosts in the subnet
-            for host in 1..255 {
-                let ip_str = format!("{}{}", base_ip, host);
-                let ip: IpAddr = ip_str.parse()?;
+            let remaining = pending.entry(host).or_insert(ports.len());
+            *remaining -= 1;
+            let host_done = *remaining == 0;
+            if host_done {
+                pending.remove(&host);
+                state.completed.insert(host);
+                throttle.forget(host);
+            }
 
-                // Scan common ports
-                for port in &config.scan.ports {
-                    tasks.push(probe_endpoint(ip, *port));
-                }
+            observer(state, ScanEvent::Probed { host_done });
+            if stop.load(Ordering::SeqCst) {
+                break;
             }
+        }
+    }
 
-            // Run tasks concurrently with a limit
-            use futures::stream::{self, StreamExt};
-            let mut endpoints = stream::iter(tasks)
-                .buffer_unordered(config.scan.concurrency)
-                .filter_map(|result| async move { result })
-                .collect::<Vec<EndpointInfo>>()
-                .await;
+    if stop.load(Ordering::SeqCst) || state.discovery_done {
+        return Ok(());
+    }
 
-            results.append(&mut endpoints);
+    // Perform additional discovery methods if configured
+    let mut discovered = Vec::new();
+    if discovery.mdns {
+        if let Some(mut mdns_results) = discover_mdns().await {
+            discovered.append(&mut mdns_results);
         }
+    }
 
-        // Perform additional discovery methods if configured
-        if config.scan.use_mdns {
-            if let Some(mut mdns_results) = discover_mdns().await {
-                results.append(&mut mdns_results);
-            }
+    if discovery.upnp {
+        if let Some(mut upnp_results) = discover_upnp().await {
+            discovered.append(&mut upnp_results);
         }
+    }
 
-        if config.scan.use_upnp {
-            if let Some(mut upnp_results) = discover_upnp().await {
-                results.append(&mut upnp_results);
+    for endpoint in discovered {
+        observer(state, ScanEvent::Found(&endpoint));
+        state.results.push(endpoint);
+    }
+
+    state.discovery_done = true;
+    Ok(())
+}
+
+/// Multicast discovery methods to run after the port scan.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Discovery {
+    pub mdns: bool,
+    pub upnp: bool,
+}
+
+impl Discovery {
+    /// mDNS and UPnP are not tied to a network, so each runs when any of the
+    /// networks' resolved settings enables it, or the scan-wide settings when
+    /// there are no networks.
+    pub fn for_networks(
+        config: &Config,
+        networks: &[NetworkEntry],
+    ) -> Result<Self, Box<dyn Error>> {
+        if networks.is_empty() {
+            let settings = profiles::settings_for(config, None)?;
+            return Ok(Discovery {
+                mdns: settings.use_mdns,
+                upnp: settings.use_upnp,
+            });
+        }
+
+        let mut discovery = Discovery::default();
+        // Networks that fail to resolve were already reported while planning
+        for settings in networks
+            .iter()
+            .filter_map(|network| profiles::settings_for(config, Some(network)).ok())
+        {
+            discovery.mdns |= settings.use_mdns;
+            discovery.upnp |= settings.use_upnp;
+        }
+        Ok(discovery)
+    }
+
+    pub fn any(&self) -> bool {
+        self.mdns || self.upnp
+    }
+}
+
+/// A network resolved against its profile: the address range to scan, the
+/// ports to probe and the settings to probe them with.
+#[derive(Debug, Clone, Serialize)]
+pub struct NetworkPlan {
+    pub cidr: String,
+    pub first: u32,
+    pub last: u32,
+    pub ports: Vec<u16>,
+    pub settings: ScanSettings,
+    /// Ranges within `first..=last` listed in `scan.exclude_ips`
+    pub excluded: Vec<(u32, u32)>,
+}
+
+impl NetworkPlan {
+    pub fn host_count(&self) -> u64 {
+        u64::from(self.last - self.first) + 1
+    }
+
+    pub fn excluded_count(&self) -> u64 {
+        self.excluded
+            .iter()
+            .map(|(first, last)| u64::from(last - first) + 1)
+            .sum()
+    }
+
+    /// Hosts that may be contacted, after exclusions.
+    pub fn target_count(&self) -> u64 {
+        self.host_count() - self.excluded_count()
+    }
+}
+
+/// Resolve each network's address range, profile and ports. Networks that
+/// cannot be resolved are skipped with a warning, as are networks whose
+/// profile does not port scan.
+pub fn plan_networks(config: &Config, networks: &[NetworkEntry]) -> Vec<NetworkPlan> {
+    let mut exclusions = HostSet::default();
+    for entry in &config.scan.exclude_ips {
+        match parse_block(entry) {
+            Ok((first, last)) => (first..=last).for_each(|host| exclusions.insert(host)),
+            Err(err) => log::warn!("Ignoring exclude_ips entry {}: {}", entry, err),
+        }
+    }
+
+    let mut plans = Vec::new();
+    for network in networks {
+        let cidr = network.cidr();
+        let planned = parse_network(cidr).and_then(|(first, last)| {
+            let settings = profiles::settings_for(config, Some(network))?;
+            let ports = ports::resolve(&settings.ports)?;
+            Ok(NetworkPlan {
+                cidr: cidr.to_string(),
+                first,
+                last,
+                ports,
+                settings,
+                excluded: exclusions.ranges_within(first, last),
+            })
+        });
+
+        match planned {
+            Ok(plan) if !plan.settings.port_scan => {
+                log::info!("Not port scanning {}: disabled by its profile", cidr)
             }
+            Ok(plan) if plan.ports.is_empty() => {
+                log::warn!("Skipping network {}: no ports to scan", cidr)
+            }
+            Ok(plan) => plans.push(plan),
+            Err(err) => log::warn!("Skipping network {}: {}", cidr, err),
+        }
+    }
+    plans
+}
+
+// Hosts shuffled together when randomizing target order. Working through the
+// network one block at a time keeps hosts completing steadily for checkpoints.
+const SHUFFLE_BLOCK_HOSTS: u64 = 256;
+
+/// Host/port pairs to probe in `first..=last`, skipping hosts that are already
+/// done. Targets are generated lazily so large networks are not held in memory.
+fn ordered_targets<'a>(
+    first: u32,
+    last: u32,
+    ports: &'a [u16],
+    done: &'a HostSet,
+    randomize: bool,
+) -> Box<dyn Iterator<Item = (u32, u16)> + 'a> {
+    if !randomize {
+        return Box::new(
+            (first..=last)
+                .filter(move |host| !done.contains(*host))
+                .flat_map(move |host| ports.iter().map(move |port| (host, *port))),
+        );
+    }
+
+    let host_count = u64::from(last - first) + 1;
+    let mut blocks: Vec<u64> = (0..host_count.div_ceil(SHUFFLE_BLOCK_HOSTS)).collect();
+    blocks.shuffle(&mut rand::thread_rng());
+
+    Box::new(blocks.into_iter().flat_map(move |block| {
+        let start = u64::from(first) + block * SHUFFLE_BLOCK_HOSTS;
+        let end = (start + SHUFFLE_BLOCK_HOSTS - 1).min(u64::from(last));
+        let mut targets: Vec<(u32, u16)> = (start as u32..=end as u32)
+            .filter(|host| !done.contains(*host))
+            .flat_map(|host| ports.iter().map(move |port| (host, *port)))
+            .collect();
+        targets.shuffle(&mut rand::thread_rng());
+        targets
+    }))
+}
+
+/// Expand an IPv4 CIDR block (or a single address) into the first and last
+/// host addresses to scan. The network and broadcast addresses are skipped
+/// for prefixes shorter than /31.
+pub fn parse_network(network: &str) -> Result<(u32, u32), String> {
+    let (base, broadcast) = parse_block(network)?;
+    if broadcast - base >= 3 {
+        Ok((base + 1, broadcast - 1))
+    } else {
+        Ok((base, broadcast))
+    }
+}
+
+/// First and last address of an IPv4 CIDR block (or a single address),
+/// including the network and broadcast addresses.
+pub fn parse_block(network: &str) -> Result<(u32, u32), String> {
+    let (address, prefix) = match network.split_once('/') {
+        Some((address, prefix)) => {
+            let prefix = prefix
+                .trim()
+                .parse::<u8>()
+                .map_err(|_| format!("invalid prefix length '{}'", prefix))?;
+            (address, prefix)
         }
+        None => (network, 32),
+    };
+    if prefix > 32 {
+        return Err(format!("invalid prefix length {}", prefix));
+    }
 
-        Ok(results)
-    })
+    let address: Ipv4Addr = address
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid IPv4 address '{}'", address))?;
+
+    let mask = if prefix == 0 {
+        0
+    } else {
+        u32::MAX << (32 - prefix)
+    };
+    let base = u32::from(address) & mask;
+    Ok((base, base | !mask))
 }
 
 async fn discover_mdns() -> Option<ScanResults> {
@@ -179,14 +812,24 @@ async fn discover_mdns() -> Option<ScanResults> {
     metadata.insert("service_name".to_string(), "example-api".to_string());
     metadata.insert("detected_by".to_string(), "mdns".to_string());
 
-    results.push(EndpointInfo {
+    let mut endpoint = EndpointInfo {
         endpoint_type: EndpointType::API,
         address: "192.168.1.100".to_string(),
         port: 8080,
         protocol: Protocol::HTTP,
         metadata,
         discovered_timestamp: chrono::Utc::now().timestamp() as u64,
-    });
+        api_model: None,
+        confidence: 0.0,
+        evidence: Vec::new(),
+    };
+    endpoint.add_evidence(Evidence::new(
+        EvidenceKind::ServiceAnnouncement,
+        "mdns",
+        "announced as _http._tcp service example-api".to_string(),
+        0.7,
+    ));
+    results.push(endpoint);
 
     Some(results)
 }
@@ -204,14 +847,24 @@ async fn discover_upnp() -> Option<ScanResults> {
     metadata.insert("device_type".to_string(), "MediaServer".to_string());
     metadata.insert("detected_by".to_string(), "upnp".to_string());
 
-    results.push(EndpointInfo {
+    let mut endpoint = EndpointInfo {
         endpoint_type: EndpointType::Other("MediaServer".to_string()),
         address: "192.168.1.150".to_string(),
         port: 8200,
         protocol: Protocol::HTTP,
         metadata,
         discovered_timestamp: chrono::Utc::now().timestamp() as u64,
-    });
+        api_model: None,
+        confidence: 0.0,
+        evidence: Vec::new(),
+    };
+    endpoint.add_evidence(Evidence::new(
+        EvidenceKind::ServiceAnnouncement,
+        "upnp",
+        "announced as UPnP MediaServer device".to_string(),
+        0.7,
+    ));
+    results.push(endpoint);
 
     Some(results)
 }
@@ -0,0 +1,263 @@
+use crate::config::Config;
+use crate::ports;
+use crate::scanner::{self, NetworkPlan};
+use std::error::Error;
+use std::fs;
+use std::net::{Ipv4Addr, ToSocketAddrs};
+
+// Blocks that are not routed on the public internet: RFC 1918, loopback,
+// link-local and carrier-grade NAT
+const PRIVATE_BLOCKS: [&str; 6] = [
+    "10.0.0.0/8",
+    "172.16.0.0/12",
+    "192.168.0.0/16",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "100.64.0.0/10",
+];
+
+/// Address ranges the operator is authorized to scan.
+pub struct Scope {
+    path: String,
+    ranges: Vec<(u32, u32)>,
+}
+
+impl Scope {
+    /// Read a scope file: one CIDR block, address or domain name per line,
+    /// with `#` comments. Domain names are resolved to their IPv4 addresses.
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path).map_err(|err| {
+            format!(
+                "Cannot read scope file {} ({}); list the CIDRs and domains you are authorized to scan, one per line",
+                path, err
+            )
+        })?;
+
+        let mut ranges = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let entry = line.split('#').next().unwrap_or("").trim();
+            if entry.is_empty() {
+                continue;
+            }
+
+            if let Ok(range) = scanner::parse_block(entry) {
+                ranges.push(range);
+                continue;
+            }
+
+            let is_domain = entry
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                && entry.chars().any(|c| c.is_ascii_alphabetic());
+            if !is_domain {
+                return Err(format!(
+                    "Invalid scope entry on line {} of {}: {}",
+                    number + 1,
+                    path,
+                    entry
+                )
+                .into());
+            }
+
+            match (entry, 0).to_socket_addrs() {
+                Ok(addresses) => {
+                    for address in addresses {
+                        if let std::net::IpAddr::V4(ip) = address.ip() {
+                            let ip = u32::from(ip);
+                            ranges.push((ip, ip));
+                        }
+                    }
+                }
+                Err(err) => log::warn!("Scope domain {} does not resolve: {}", entry, err),
+            }
+        }
+
+        // Merge overlapping and adjacent ranges so coverage checks can walk them in order
+        ranges.sort_unstable();
+        let mut merged: Vec<(u32, u32)> = Vec::new();
+        for (first, last) in ranges {
+            match merged.last_mut() {
+                Some(previous) if u64::from(first) <= u64::from(previous.1) + 1 => {
+                    previous.1 = previous.1.max(last)
+                }
+                _ => merged.push((first, last)),
+            }
+        }
+
+        Ok(Scope {
+            path: path.to_string(),
+            ranges: merged,
+        })
+    }
+
+    /// Whether every address in `first..=last` is in scope.
+    pub fn covers(&self, first: u32, last: u32) -> bool {
+        self.ranges
+            .iter()
+            .any(|(start, end)| *start <= first && last <= *end)
+    }
+}
+
+fn is_private(first: u32, last: u32) -> bool {
+    PRIVATE_BLOCKS.iter().any(|block| {
+        scanner::parse_block(block).is_ok_and(|(start, end)| start <= first && last <= end)
+    })
+}
+
+/// Reasons the planned scan may not run: networks outside the scope file,
+/// unacknowledged public ranges and too many targets.
+pub fn violations(config: &Config, plans: &[NetworkPlan]) -> Result<Vec<String>, Box<dyn Error>> {
+    let scope = Scope::load(&config.scope.file)?;
+    let mut problems = Vec::new();
+
+    for plan in plans {
+        if !scope.covers(plan.first, plan.last) {
+            problems.push(format!(
+                "{} is not within the authorized scope in {}",
+                plan.cidr, scope.path
+            ));
+        }
+        if !config.scope.allow_public && !is_private(plan.first, plan.last) {
+            problems.push(format!(
+                "{} contains public addresses; acknowledge with --allow-public or scope.allow_public",
+                plan.cidr
+            ));
+        }
+    }
+
+    let targets: u64 = plans.iter().map(NetworkPlan::target_count).sum();
+    if targets > config.scope.max_hosts {
+        problems.push(format!(
+            "{} hosts exceeds scope.max_hosts ({})",
+            targets, config.scope.max_hosts
+        ));
+    }
+
+    Ok(problems)
+}
+
+/// Refuse to start a scan that violates the scope rules.
+pub fn enforce(config: &Config, plans: &[NetworkPlan]) -> Result<(), Box<dyn Error>> {
+    let problems = violations(config, plans)?;
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Refusing to scan: {}", problems.join("; ")).into())
+    }
+}
+
+/// Print what a scan would touch without sending anything. Returns whether
+/// the scan would be allowed to run.
+pub fn print_plan(config: &Config) -> Result<bool, Box<dyn Error>> {
+    let plans = scanner::plan_networks(config, &config.scan.networks);
+
+    println!("Scan plan\n");
+    let mut total_hosts = 0;
+    let mut total_probes = 0;
+    for plan in &plans {
+        let settings = &plan.settings;
+        let targets = plan.target_count();
+        let probes = targets * plan.ports.len() as u64;
+        total_hosts += targets;
+        total_probes += probes;
+
+        match &settings.profile {
+            Some(profile) => println!("{} (profile {})", plan.cidr, profile),
+            None => println!("{}", plan.cidr),
+        }
+        println!(
+            "  hosts:     {} - {} ({} hosts)",
+            Ipv4Addr::from(plan.first),
+            Ipv4Addr::from(plan.last),
+            targets
+        );
+        if !plan.excluded.is_empty() {
+            let excluded: Vec<String> = plan
+                .excluded
+                .iter()
+                .map(|(first, last)| format_range(*first, *last))
+                .collect();
+            println!("  excluded:  {}", excluded.join(", "));
+        }
+        println!(
+            "  ports:     {} ({} ports)",
+            ports::format_list(&plan.ports),
+            plan.ports.len()
+        );
+        println!(
+            "  probes:    up to {} TCP connections, {} ms timeout",
+            probes, settings.timeout_ms
+        );
+
+        let discovery = &settings.host_discovery;
+        if discovery.enabled {
+            let mut methods = Vec::new();
+            if discovery.use_arp {
+                methods.push("ARP table".to_string());
+            }
+            if discovery.use_icmp {
+                methods.push("ICMP echo".to_string());
+            }
+            if !discovery.tcp_ping_ports.is_empty() {
+                methods.push(format!(
+                    "TCP ping to {}",
+                    ports::format_list(&discovery.tcp_ping_ports)
+                ));
+            }
+            println!("  discovery: {} before port probes", methods.join(", "));
+        } else {
+            println!("  discovery: off, every host is port scanned");
+        }
+
+        let rate = &settings.rate_limit;
+        let pps = if rate.packets_per_second == 0 {
+            "unlimited".to_string()
+        } else {
+            format!("{} connections/s", rate.packets_per_second)
+        };
+        println!(
+            "  rate:      {}, concurrency {}, {} per host{}",
+            pps,
+            settings.concurrency,
+            rate.max_connections_per_host,
+            if rate.randomize_order {
+                ", randomized order"
+            } else {
+                ""
+            }
+        );
+        println!();
+    }
+
+    let scan_settings = crate::profiles::settings_for(config, None)?;
+    println!(
+        "mDNS discovery: {}, UPnP discovery: {}",
+        if scan_settings.use_mdns { "yes" } else { "no" },
+        if scan_settings.use_upnp { "yes" } else { "no" }
+    );
+    println!(
+        "Total: {} hosts, up to {} probes\n",
+        total_hosts, total_probes
+    );
+
+    // A missing or invalid scope file is reported like any other refusal
+    let problems = violations(config, &plans).unwrap_or_else(|err| vec![err.to_string()]);
+    if problems.is_empty() {
+        println!("Scope check passed ({})", config.scope.file);
+        Ok(true)
+    } else {
+        println!("Scan would be refused:");
+        for problem in &problems {
+            println!("  - {}", problem);
+        }
+        Ok(false)
+    }
+}
+
+fn format_range(first: u32, last: u32) -> String {
+    if first == last {
+        Ipv4Addr::from(first).to_string()
+    } else {
+        format!("{}-{}", Ipv4Addr::from(first), Ipv4Addr::from(last))
+    }
+}
@@ -0,0 +1,138 @@
+use crate::config::RateLimitConfig;
+use crate::scanner::ProbeOutcome;
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+// First delay applied once a host crosses the failure threshold; it doubles
+// with every further failure up to `max_backoff_ms`
+const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Paces probes globally and per host according to `RateLimitConfig`.
+pub struct Throttle {
+    config: RateLimitConfig,
+    interval: Option<Duration>,
+    next_slot: tokio::sync::Mutex<Instant>,
+    hosts: Mutex<HashMap<u32, HostState>>,
+}
+
+struct HostState {
+    slots: Arc<Semaphore>,
+    consecutive_failures: u32,
+    backoff: Duration,
+}
+
+impl Throttle {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        let interval = if config.packets_per_second > 0 {
+            Some(Duration::from_secs(1) / config.packets_per_second)
+        } else {
+            None
+        };
+
+        Throttle {
+            config: config.clone(),
+            interval,
+            next_slot: tokio::sync::Mutex::new(Instant::now()),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Wait until a probe of `host` may start. The returned permit holds one of
+    /// the host's connection slots until it is dropped.
+    pub async fn acquire(&self, host: u32) -> OwnedSemaphorePermit {
+        let (slots, backoff) = {
+            let mut hosts = self.hosts.lock().unwrap();
+            let state = hosts.entry(host).or_insert_with(|| HostState {
+                slots: Arc::new(Semaphore::new(self.config.max_connections_per_host.max(1))),
+                consecutive_failures: 0,
+                backoff: Duration::ZERO,
+            });
+            (state.slots.clone(), state.backoff)
+        };
+
+        let permit = slots
+            .acquire_owned()
+            .await
+            .expect("host semaphores are never closed");
+
+        let jitter = if self.config.jitter_ms > 0 {
+            Duration::from_millis(rand::thread_rng().gen_range(0..=self.config.jitter_ms))
+        } else {
+            Duration::ZERO
+        };
+        let delay = backoff + jitter;
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        self.pace().await;
+        permit
+    }
+
+    /// Reserve the next global send slot and wait for it.
+    pub async fn pace(&self) {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        let slot = {
+            let mut next_slot = self.next_slot.lock().await;
+            let slot = (*next_slot).max(Instant::now());
+            *next_slot = slot + interval;
+            slot
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+
+    /// Adjust the host's backoff after a probe: timeouts and resets slow it
+    /// down, answers (open or closed) speed it back up.
+    pub fn record(&self, host: u32, outcome: ProbeOutcome) {
+        if !self.config.adaptive_backoff {
+            return;
+        }
+
+        let mut hosts = self.hosts.lock().unwrap();
+        let state = match hosts.get_mut(&host) {
+            Some(state) => state,
+            None => return,
+        };
+
+        match outcome {
+            ProbeOutcome::TimedOut | ProbeOutcome::Failed => {
+                state.consecutive_failures += 1;
+                if state.consecutive_failures >= self.config.backoff_threshold {
+                    let max_backoff = Duration::from_millis(self.config.max_backoff_ms);
+                    state.backoff = if state.backoff.is_zero() {
+                        INITIAL_BACKOFF
+                    } else {
+                        state.backoff * 2
+                    }
+                    .min(max_backoff);
+                    log::debug!(
+                        "Backing off {} by {:?} after {} failed probes",
+                        std::net::Ipv4Addr::from(host),
+                        state.backoff,
+                        state.consecutive_failures
+                    );
+                }
+            }
+            ProbeOutcome::Open | ProbeOutcome::Closed => {
+                state.consecutive_failures = 0;
+                state.backoff /= 2;
+                if state.backoff < Duration::from_millis(10) {
+                    state.backoff = Duration::ZERO;
+                }
+            }
+        }
+    }
+
+    /// Drop the state kept for a host once all of its ports have been probed.
+    pub fn forget(&self, host: u32) {
+        self.hosts.lock().unwrap().remove(&host);
+    }
+}
@@ -0,0 +1,619 @@
+use crate::config::Config;
+use jaq_core::load::{self, Arena, File, Loader};
+use jaq_core::{Compiler, Ctx, Filter, Native, RcIter};
+use jaq_json::Val;
+use serde_json::{Map, Value};
+use serde_json_path::JsonPath;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+// Outputs a jq expression may produce before it is stopped
+const MAX_JQ_OUTPUTS: usize = 10_000;
+// Transformations running at once for tool calls, including timed-out ones
+// whose blocking threads are still busy
+const MAX_RUNNING_TRANSFORMS: usize = 16;
+
+/// One step of a connector's `transformations`, applied to tool results
+/// before they reach the model.
+#[derive(Clone)]
+pub enum Transformation {
+    /// jq expression; several outputs become an array, none becomes null
+    Jq {
+        expression: String,
+        filter: Filter<Native<Val>>,
+    },
+    /// JSONPath query returning every match, or only the first
+    JsonPath { path: JsonPath, first: bool },
+    /// Keep only these dotted field paths
+    AllowFields(Vec<Vec<String>>),
+    /// Remove these dotted field paths
+    DenyFields(Vec<Vec<String>>),
+    /// Shorten long arrays and strings, and cap the size of the whole result
+    Truncate {
+        max_items: Option<usize>,
+        max_string_length: Option<usize>,
+        max_bytes: Option<usize>,
+    },
+}
+
+impl Transformation {
+    /// Parse one entry of `transformations`, checking expressions up front.
+    pub fn parse(step: &Value) -> Result<Self, Box<dyn Error>> {
+        let kind = step
+            .get("type")
+            .and_then(Value::as_str)
+            .ok_or("Transformation without a type")?;
+        let text = |key: &str| {
+            step.get(key)
+                .and_then(Value::as_str)
+                .ok_or_else(|| format!("{} transformation needs {}", kind, key))
+        };
+        let limit = |key: &str| {
+            step.get(key)
+                .and_then(Value::as_u64)
+                .map(|limit| limit as usize)
+        };
+
+        Ok(match kind {
+            "jq" => {
+                let expression = text("expression")?;
+                Transformation::Jq {
+                    filter: compile_jq(expression)?,
+                    expression: expression.to_string(),
+                }
+            }
+            "jsonpath" => {
+                let path = text("path")?;
+                Transformation::JsonPath {
+                    path: JsonPath::parse(path)
+                        .map_err(|err| format!("Invalid JSONPath '{}': {}", path, err))?,
+                    first: step.get("first").and_then(Value::as_bool).unwrap_or(false),
+                }
+            }
+            "allow_fields" => Transformation::AllowFields(field_paths(step, kind)?),
+            "deny_fields" => Transformation::DenyFields(field_paths(step, kind)?),
+            "truncate" => {
+                let (max_items, max_string_length, max_bytes) = (
+                    limit("max_items"),
+                    limit("max_string_length"),
+                    limit("max_bytes"),
+                );
+                if max_items.is_none() && max_string_length.is_none() && max_bytes.is_none() {
+                    return Err(
+                        "truncate transformation needs max_items, max_string_length or max_bytes"
+                            .into(),
+                    );
+                }
+                Transformation::Truncate {
+                    max_items,
+                    max_string_length,
+                    max_bytes,
+                }
+            }
+            _ => return Err(format!("Unsupported transformation type '{}'", kind).into()),
+        })
+    }
+
+    /// The `type` the step was declared with.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Transformation::Jq { .. } => "jq",
+            Transformation::JsonPath { .. } => "jsonpath",
+            Transformation::AllowFields(_) => "allow_fields",
+            Transformation::DenyFields(_) => "deny_fields",
+            Transformation::Truncate { .. } => "truncate",
+        }
+    }
+
+    pub fn apply(&self, input: Value) -> Result<Value, Box<dyn Error>> {
+        Ok(match self {
+            Transformation::Jq { expression, filter } => run_jq(expression, filter, input)?,
+            Transformation::JsonPath { path, first } => {
+                let matches = path.query(&input);
+                if *first {
+                    matches.first().cloned().unwrap_or(Value::Null)
+                } else {
+                    Value::Array(matches.all().into_iter().cloned().collect())
+                }
+            }
+            Transformation::AllowFields(paths) => allow_fields(input, paths),
+            Transformation::DenyFields(paths) => {
+                let mut value = input;
+                for path in paths {
+                    deny_field(&mut value, path);
+                }
+                value
+            }
+            Transformation::Truncate {
+                max_items,
+                max_string_length,
+                max_bytes,
+            } => {
+                let value = shorten(input, *max_items, *max_string_length);
+                match max_bytes {
+                    Some(max_bytes) => cap_size(value, *max_bytes),
+                    None => value,
+                }
+            }
+        })
+    }
+}
+
+// Compiled jq filters cannot be printed; the step type is enough
+impl fmt::Debug for Transformation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_tuple("Transformation").field(&self.kind()).finish()
+    }
+}
+
+/// Parse a connector's `transformations` list.
+pub fn parse_all(steps: &[Value]) -> Result<Vec<Transformation>, Box<dyn Error>> {
+    steps
+        .iter()
+        .enumerate()
+        .map(|(index, step)| {
+            Transformation::parse(step)
+                .map_err(|err| format!("transformation {}: {}", index + 1, err).into())
+        })
+        .collect()
+}
+
+/// Apply transformations in order.
+pub fn apply(transformations: &[Transformation], input: Value) -> Result<Value, Box<dyn Error>> {
+    transformations
+        .iter()
+        .try_fold(input, |value, transformation| transformation.apply(value))
+}
+
+/// Limits on transformations run for tool calls, from `[server]`.
+#[derive(Debug, Clone)]
+pub struct TransformLimits {
+    timeout: Duration,
+    running: Arc<Semaphore>,
+}
+
+impl TransformLimits {
+    pub fn new(config: &Config) -> Self {
+        TransformLimits {
+            timeout: Duration::from_millis(config.server.transform_timeout_ms.max(1)),
+            running: Arc::new(Semaphore::new(MAX_RUNNING_TRANSFORMS)),
+        }
+    }
+}
+
+/// Apply transformations in order on a blocking thread, failing once the
+/// timeout has passed. A jq expression that never finishes keeps its thread
+/// busy and its slot taken, so runaway expressions can occupy at most
+/// `MAX_RUNNING_TRANSFORMS` threads; further calls fail until one finishes.
+pub async fn apply_with_timeout(
+    transformations: &[Transformation],
+    input: Value,
+    limits: &TransformLimits,
+) -> Result<Value, String> {
+    let deadline = tokio::time::Instant::now() + limits.timeout;
+    let slot = match tokio::time::timeout_at(deadline, limits.running.clone().acquire_owned()).await
+    {
+        Ok(Ok(slot)) => slot,
+        _ => {
+            return Err(format!(
+                "Too many transformations still running, none finished within {} ms",
+                limits.timeout.as_millis()
+            ))
+        }
+    };
+
+    let transformations = transformations.to_vec();
+    let job = tokio::task::spawn_blocking(move || {
+        let result = apply(&transformations, input).map_err(|err| err.to_string());
+        drop(slot);
+        result
+    });
+    match tokio::time::timeout_at(deadline, job).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(err)) => Err(format!("Transformations failed: {}", err)),
+        Err(_) => Err(format!(
+            "Transformations did not finish within {} ms",
+            limits.timeout.as_millis()
+        )),
+    }
+}
+
+fn compile_jq(expression: &str) -> Result<Filter<Native<Val>>, Box<dyn Error>> {
+    let program = File {
+        code: expression,
+        path: (),
+    };
+    let loader = Loader::new(jaq_std::defs().chain(jaq_json::defs()));
+    let arena = Arena::default();
+    let modules = loader.load(&arena, program).map_err(|errors| {
+        let problems: Vec<String> = errors
+            .into_iter()
+            .flat_map(|(_, error)| match error {
+                load::Error::Io(items) => items
+                    .into_iter()
+                    .map(|(path, err)| format!("cannot load {}: {}", path, err))
+                    .collect(),
+                load::Error::Lex(items) => items
+                    .into_iter()
+                    .map(|(expected, rest)| syntax_error(expected.as_str(), rest))
+                    .collect(),
+                load::Error::Parse(items) => items
+                    .into_iter()
+                    .map(|(expected, rest)| syntax_error(expected.as_str(), rest))
+                    .collect::<Vec<_>>(),
+            })
+            .collect();
+        format!(
+            "Invalid jq expression '{}': {}",
+            expression,
+            problems.join("; ")
+        )
+    })?;
+
+    let filter = Compiler::default()
+        .with_funs(jaq_std::funs().chain(jaq_json::funs()))
+        .compile(modules)
+        .map_err(|errors| {
+            let problems: Vec<String> = errors
+                .into_iter()
+                .flat_map(|(_, undefined)| undefined)
+                .map(|(name, kind)| format!("undefined {} {}", kind.as_str(), name))
+                .collect();
+            format!(
+                "Invalid jq expression '{}': {}",
+                expression,
+                problems.join("; ")
+            )
+        })?;
+    Ok(filter)
+}
+
+fn syntax_error(expected: &str, rest: &str) -> String {
+    if rest.is_empty() {
+        format!("expected {} at end of input", expected)
+    } else {
+        let near: String = rest.chars().take(20).collect();
+        format!("expected {} near '{}'", expected, near)
+    }
+}
+
+fn run_jq(
+    expression: &str,
+    filter: &Filter<Native<Val>>,
+    input: Value,
+) -> Result<Value, Box<dyn Error>> {
+    // The identity filter is what generated connectors declare by default
+    if expression.trim() == "." {
+        return Ok(input);
+    }
+
+    let inputs = RcIter::new(core::iter::empty());
+    let mut outputs = Vec::new();
+    for output in filter.run((Ctx::new([], &inputs), Val::from(input))) {
+        if outputs.len() == MAX_JQ_OUTPUTS {
+            return Err(format!(
+                "jq expression '{}' produced more than {} outputs",
+                expression, MAX_JQ_OUTPUTS
+            )
+            .into());
+        }
+        let output =
+            output.map_err(|err| format!("jq expression '{}' failed: {}", expression, err))?;
+        outputs.push(Value::from(output));
+    }
+
+    Ok(match outputs.len() {
+        0 => Value::Null,
+        1 => outputs.remove(0),
+        _ => Value::Array(outputs),
+    })
+}
+
+/// `fields` as dotted paths; `*` matches any field name.
+fn field_paths(step: &Value, kind: &str) -> Result<Vec<Vec<String>>, Box<dyn Error>> {
+    let fields = step
+        .get("fields")
+        .and_then(Value::as_array)
+        .filter(|fields| !fields.is_empty())
+        .ok_or_else(|| format!("{} transformation needs a list of fields", kind))?;
+    fields
+        .iter()
+        .map(|field| match field.as_str() {
+            Some(field) if !field.is_empty() => Ok(field.split('.').map(String::from).collect()),
+            _ => Err(format!("{} fields must be non-empty strings", kind).into()),
+        })
+        .collect()
+}
+
+fn segment_matches(segment: &str, key: &str) -> bool {
+    segment == "*" || segment == key
+}
+
+/// Keep only the fields on `paths`. Arrays are filtered element by element.
+fn allow_fields(value: Value, paths: &[Vec<String>]) -> Value {
+    match value {
+        Value::Array(items) => Value::Array(
+            items
+                .into_iter()
+                .map(|item| allow_fields(item, paths))
+                .collect(),
+        ),
+        Value::Object(fields) => {
+            let mut kept = Map::new();
+            for (key, field) in fields {
+                let rest: Vec<Vec<String>> = paths
+                    .iter()
+                    .filter(|path| {
+                        path.first()
+                            .is_some_and(|first| segment_matches(first, &key))
+                    })
+                    .map(|path| path[1..].to_vec())
+                    .collect();
+                // A path ending here keeps the whole field, a longer one filters inside it
+                if rest.iter().any(Vec::is_empty) {
+                    kept.insert(key, field);
+                } else if !rest.is_empty() {
+                    kept.insert(key, allow_fields(field, &rest));
+                }
+            }
+            Value::Object(kept)
+        }
+        other => other,
+    }
+}
+
+/// Remove the field at `path`, looking inside every element of arrays.
+fn deny_field(value: &mut Value, path: &[String]) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                deny_field(item, path);
+            }
+        }
+        Value::Object(fields) => match path {
+            [] => {}
+            [last] => fields.retain(|key, _| !segment_matches(last, key)),
+            [first, rest @ ..] => {
+                for (key, field) in fields.iter_mut() {
+                    if segment_matches(first, key) {
+                        deny_field(field, rest);
+                    }
+                }
+            }
+        },
+        _ => {}
+    }
+}
+
+fn shorten(value: Value, max_items: Option<usize>, max_string_length: Option<usize>) -> Value {
+    match value {
+        Value::Array(items) => {
+            let total = items.len();
+            let keep = max_items.unwrap_or(total).min(total);
+            let mut shortened: Vec<Value> = items
+                .into_iter()
+                .take(keep)
+                .map(|item| shorten(item, max_items, max_string_length))
+                .collect();
+            if keep < total {
+                shortened.push(Value::String(format!("... {} more items", total - keep)));
+            }
+            Value::Array(shortened)
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, field)| (key, shorten(field, max_items, max_string_length)))
+                .collect(),
+        ),
+        Value::String(text) => match max_string_length {
+            Some(max) if text.chars().count() > max => {
+                Value::String(format!("{}...", text.chars().take(max).collect::<String>()))
+            }
+            _ => Value::String(text),
+        },
+        other => other,
+    }
+}
+
+/// Replace a result whose JSON is longer than `max_bytes` with its truncated text.
+fn cap_size(value: Value, max_bytes: usize) -> Value {
+    let text = value.to_string();
+    if text.len() <= max_bytes {
+        return value;
+    }
+    let mut end = max_bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    Value::String(format!(
+        "{}... (truncated from {} bytes)",
+        &text[..end],
+        text.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn run(step: Value, input: Value) -> Result<Value, Box<dyn Error>> {
+        Transformation::parse(&step)?.apply(input)
+    }
+
+    fn users() -> Value {
+        json!({
+            "data": {
+                "users": [
+                    { "id": 1, "name": "ada", "token": "t1", "profile": { "email": "a@x", "phone": "1" } },
+                    { "id": 2, "name": "grace", "token": "t2", "profile": { "email": "g@x", "phone": "2" } }
+                ]
+            }
+        })
+    }
+
+    #[test]
+    fn jq_outputs() {
+        let names = json!({ "type": "jq", "expression": "[.data.users[].name]" });
+        assert_eq!(run(names, users()).unwrap(), json!(["ada", "grace"]));
+
+        let several = json!({ "type": "jq", "expression": ".data.users[].id" });
+        assert_eq!(run(several, users()).unwrap(), json!([1, 2]));
+
+        let none = json!({ "type": "jq", "expression": "empty" });
+        assert_eq!(run(none, users()).unwrap(), Value::Null);
+
+        let identity = json!({ "type": "jq", "expression": "." });
+        assert_eq!(run(identity, users()).unwrap(), users());
+    }
+
+    #[test]
+    fn jq_errors() {
+        assert!(Transformation::parse(&json!({ "type": "jq", "expression": "map(" })).is_err());
+
+        let failing = json!({ "type": "jq", "expression": ".[0]" });
+        assert!(run(failing, json!({ "a": 1 })).is_err());
+
+        let endless = json!({ "type": "jq", "expression": "range(100000)" });
+        let err = run(endless, Value::Null).unwrap_err().to_string();
+        assert!(err.contains("more than 10000 outputs"), "{}", err);
+    }
+
+    #[test]
+    fn jsonpath_all_and_first() {
+        let all = json!({ "type": "jsonpath", "path": "$.data.users[*].id" });
+        assert_eq!(run(all, users()).unwrap(), json!([1, 2]));
+
+        let first = json!({ "type": "jsonpath", "path": "$.data.users[*].id", "first": true });
+        assert_eq!(run(first, users()).unwrap(), json!(1));
+
+        let missing = json!({ "type": "jsonpath", "path": "$.nothing", "first": true });
+        assert_eq!(run(missing, users()).unwrap(), Value::Null);
+
+        assert!(Transformation::parse(&json!({ "type": "jsonpath", "path": "$[" })).is_err());
+    }
+
+    #[test]
+    fn allow_fields_keeps_listed_paths() {
+        let step =
+            json!({ "type": "allow_fields", "fields": ["data.users.id", "data.*.profile.email"] });
+        assert_eq!(
+            run(step, users()).unwrap(),
+            json!({ "data": { "users": [
+                { "id": 1, "profile": { "email": "a@x" } },
+                { "id": 2, "profile": { "email": "g@x" } }
+            ] } })
+        );
+    }
+
+    #[test]
+    fn deny_fields_removes_listed_paths() {
+        let step =
+            json!({ "type": "deny_fields", "fields": ["data.users.token", "*.users.profile.*"] });
+        assert_eq!(
+            run(step, users()).unwrap(),
+            json!({ "data": { "users": [
+                { "id": 1, "name": "ada", "profile": {} },
+                { "id": 2, "name": "grace", "profile": {} }
+            ] } })
+        );
+    }
+
+    #[test]
+    fn truncate_shortens_arrays_and_strings() {
+        let step = json!({ "type": "truncate", "max_items": 2, "max_string_length": 3 });
+        assert_eq!(
+            run(step, json!(["abcdef", "ab", "c", "d"])).unwrap(),
+            json!(["abc...", "ab", "... 2 more items"])
+        );
+    }
+
+    #[test]
+    fn truncate_caps_size() {
+        let step = json!({ "type": "truncate", "max_bytes": 10 });
+        assert_eq!(
+            run(step, json!({ "text": "héllo world" })).unwrap(),
+            json!("{\"text\":\"h... (truncated from 23 bytes)")
+        );
+
+        let small = json!({ "type": "truncate", "max_bytes": 100 });
+        assert_eq!(run(small, json!([1, 2])).unwrap(), json!([1, 2]));
+
+        assert!(Transformation::parse(&json!({ "type": "truncate" })).is_err());
+    }
+
+    #[test]
+    fn pipeline_runs_in_order() {
+        let steps = parse_all(&[
+            json!({ "type": "jsonpath", "path": "$.data.users[*]" }),
+            json!({ "type": "deny_fields", "fields": ["token", "profile"] }),
+            json!({ "type": "jq", "expression": "map(.name)" }),
+        ])
+        .unwrap();
+        assert_eq!(apply(&steps, users()).unwrap(), json!(["ada", "grace"]));
+
+        let err = parse_all(&[
+            json!({ "type": "jq", "expression": "." }),
+            json!({ "type": "sort" }),
+        ])
+        .unwrap_err()
+        .to_string();
+        assert!(err.starts_with("transformation 2:"), "{}", err);
+    }
+
+    fn limits(timeout: Duration, running: usize) -> TransformLimits {
+        TransformLimits {
+            timeout,
+            running: Arc::new(Semaphore::new(running)),
+        }
+    }
+
+    #[test]
+    fn apply_with_timeout_stops_waiting() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let steps =
+            parse_all(&[json!({ "type": "jq", "expression": "[limit(3; repeat(1))]" })]).unwrap();
+        let result = runtime.block_on(apply_with_timeout(
+            &steps,
+            Value::Null,
+            &limits(Duration::from_secs(5), 4),
+        ));
+        assert_eq!(result, Ok(json!([1, 1, 1])));
+
+        let endless =
+            parse_all(&[json!({ "type": "jq", "expression": "last(repeat(1))" })]).unwrap();
+        let result = runtime.block_on(apply_with_timeout(
+            &endless,
+            Value::Null,
+            &limits(Duration::from_millis(50), 4),
+        ));
+        assert_eq!(
+            result,
+            Err("Transformations did not finish within 50 ms".to_string())
+        );
+        runtime.shutdown_background();
+    }
+
+    #[test]
+    fn apply_with_timeout_limits_running_transformations() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let limits = limits(Duration::from_millis(50), 1);
+        let endless =
+            parse_all(&[json!({ "type": "jq", "expression": "last(repeat(1))" })]).unwrap();
+        let result = runtime.block_on(apply_with_timeout(&endless, Value::Null, &limits));
+        assert!(result.unwrap_err().contains("did not finish"));
+
+        // The timed-out expression still holds the only slot
+        let identity = parse_all(&[json!({ "type": "jq", "expression": "." })]).unwrap();
+        let result = runtime.block_on(apply_with_timeout(&identity, json!(1), &limits));
+        assert_eq!(
+            result,
+            Err("Too many transformations still running, none finished within 50 ms".to_string())
+        );
+        runtime.shutdown_background();
+    }
+}